@@ -0,0 +1,169 @@
+//! Routing of GATT events to the applications sharing the BlueDroid stack
+//!
+//! Kept free of ESP-IDF types, so it builds and is tested on the host: the `Registry` of
+//! `esp-bluedroid` reduces every `GattsEvent` to an `EventTarget` and delivers it to the apps
+//! picked by `Router::route`.
+
+use core::{fmt, ops::Range};
+
+/// `gatt_if` of events which are not bound to a single app
+pub const GATT_IF_NONE: u8 = 0xff;
+
+/// What a GATT event is addressed to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventTarget {
+    /// Registration of `app_id` completed, binding it to `gatt_if`
+    Registered { app_id: u16, gatt_if: u8 },
+    /// Event of the app registered on `gatt_if`, or of every app for `GATT_IF_NONE`
+    Interface(u8),
+    /// Access of an attribute, owned by the service covering `handle`
+    Attribute { gatt_if: u8, handle: u16 },
+}
+
+/// Apps an event is delivered to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Route {
+    App(u16),
+    All,
+    /// No registered app owns the event
+    Unrouted,
+}
+
+/// Reasons an app or service can't be added to the `Router`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouterError {
+    /// Another app was registered with the same id
+    DuplicateApp(u16),
+    /// App was never added
+    UnknownApp(u16),
+    /// Handles of the service overlap those of a service of `app_id`
+    OverlappingService { app_id: u16 },
+}
+
+impl fmt::Display for RouterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DuplicateApp(app_id) => write!(f, "App {} is already registered", app_id),
+            Self::UnknownApp(app_id) => write!(f, "App {} is not registered", app_id),
+            Self::OverlappingService { app_id } => {
+                write!(f, "Service handles overlap a service of app {}", app_id)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RouterError {}
+
+#[derive(Debug)]
+struct AppRoute {
+    app_id: u16,
+    gatt_if: Option<u8>,
+    /// Attribute handles of every service of the app
+    services: Vec<Range<u16>>,
+}
+
+/// Table of registered apps, their `gatt_if` and the attribute handles of their services
+#[derive(Debug, Default)]
+pub struct Router {
+    apps: Vec<AppRoute>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an app whose registration is pending, it's bound to a `gatt_if` once it completes
+    pub fn add_app(&mut self, app_id: u16) -> Result<(), RouterError> {
+        if self.apps.iter().any(|app| app.app_id == app_id) {
+            return Err(RouterError::DuplicateApp(app_id));
+        }
+
+        self.apps.push(AppRoute {
+            app_id,
+            gatt_if: None,
+            services: Vec::new(),
+        });
+
+        Ok(())
+    }
+
+    pub fn remove_app(&mut self, app_id: u16) {
+        self.apps.retain(|app| app.app_id != app_id);
+    }
+
+    /// Claims `num_handles` handles starting at `service_handle` for `app_id`
+    pub fn add_service(
+        &mut self,
+        app_id: u16,
+        service_handle: u16,
+        num_handles: u16,
+    ) -> Result<(), RouterError> {
+        let handles = service_handle..service_handle.saturating_add(num_handles);
+
+        if let Some(owner) = self.apps.iter().find(|app| {
+            app.services
+                .iter()
+                .any(|service| service.start < handles.end && handles.start < service.end)
+        }) {
+            return Err(RouterError::OverlappingService {
+                app_id: owner.app_id,
+            });
+        }
+
+        self.apps
+            .iter_mut()
+            .find(|app| app.app_id == app_id)
+            .ok_or(RouterError::UnknownApp(app_id))?
+            .services
+            .push(handles);
+
+        Ok(())
+    }
+
+    pub fn gatt_if(&self, app_id: u16) -> Option<u8> {
+        self.apps
+            .iter()
+            .find(|app| app.app_id == app_id)
+            .and_then(|app| app.gatt_if)
+    }
+
+    pub fn app_id(&self, gatt_if: u8) -> Option<u16> {
+        self.apps
+            .iter()
+            .find(|app| app.gatt_if == Some(gatt_if))
+            .map(|app| app.app_id)
+    }
+
+    /// Picks the apps an event is delivered to, binding `gatt_if`s on registration
+    pub fn route(&mut self, target: EventTarget) -> Route {
+        match target {
+            EventTarget::Registered { app_id, gatt_if } => {
+                match self.apps.iter_mut().find(|app| app.app_id == app_id) {
+                    Some(app) => {
+                        app.gatt_if = Some(gatt_if);
+                        Route::App(app_id)
+                    }
+                    None => Route::Unrouted,
+                }
+            }
+            EventTarget::Interface(GATT_IF_NONE) => Route::All,
+            EventTarget::Interface(gatt_if) => self.route_interface(gatt_if),
+            EventTarget::Attribute { gatt_if, handle } => self
+                .apps
+                .iter()
+                .find(|app| app.services.iter().any(|service| service.contains(&handle)))
+                .map(|app| Route::App(app.app_id))
+                // Attributes outside of known services, e.g. before `ServiceCreated` was routed
+                .unwrap_or_else(|| self.route_interface(gatt_if)),
+        }
+    }
+
+    fn route_interface(&self, gatt_if: u8) -> Route {
+        match self.app_id(gatt_if) {
+            Some(app_id) => Route::App(app_id),
+            None if gatt_if == GATT_IF_NONE => Route::All,
+            None => Route::Unrouted,
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use bluedroid_router::{EventTarget, Route, Router, RouterError, GATT_IF_NONE};
+
+const OTA_APP: u16 = 254;
+const PRODUCT_APP: u16 = 0;
+
+/// Router with both apps registered: OTA on `gatt_if` 3 with a service at handle 40,
+/// product app on `gatt_if` 4 with a service at handle 80
+fn router() -> Router {
+    let mut router = Router::new();
+
+    router.add_app(OTA_APP).unwrap();
+    router.add_app(PRODUCT_APP).unwrap();
+
+    assert_eq!(
+        router.route(EventTarget::Registered {
+            app_id: OTA_APP,
+            gatt_if: 3
+        }),
+        Route::App(OTA_APP)
+    );
+    assert_eq!(
+        router.route(EventTarget::Registered {
+            app_id: PRODUCT_APP,
+            gatt_if: 4
+        }),
+        Route::App(PRODUCT_APP)
+    );
+
+    router.add_service(OTA_APP, 40, 25).unwrap();
+    router.add_service(PRODUCT_APP, 80, 4).unwrap();
+
+    router
+}
+
+#[test]
+fn registration_binds_gatt_if() {
+    let router = router();
+
+    assert_eq!(router.gatt_if(OTA_APP), Some(3));
+    assert_eq!(router.gatt_if(PRODUCT_APP), Some(4));
+    assert_eq!(router.app_id(3), Some(OTA_APP));
+    assert_eq!(router.app_id(5), None);
+}
+
+#[test]
+fn registration_of_unknown_app_is_unrouted() {
+    let mut router = router();
+
+    assert_eq!(
+        router.route(EventTarget::Registered {
+            app_id: 7,
+            gatt_if: 5
+        }),
+        Route::Unrouted
+    );
+    assert_eq!(router.app_id(5), None);
+}
+
+#[test]
+fn events_are_routed_by_gatt_if() {
+    let mut router = router();
+
+    assert_eq!(router.route(EventTarget::Interface(3)), Route::App(OTA_APP));
+    assert_eq!(
+        router.route(EventTarget::Interface(4)),
+        Route::App(PRODUCT_APP)
+    );
+    assert_eq!(router.route(EventTarget::Interface(9)), Route::Unrouted);
+    assert_eq!(
+        router.route(EventTarget::Interface(GATT_IF_NONE)),
+        Route::All
+    );
+}
+
+#[test]
+fn attributes_are_routed_by_service_handles() {
+    let mut router = router();
+
+    // Service declaration, first and last handle of the OTA service
+    for handle in [40, 41, 64] {
+        assert_eq!(
+            router.route(EventTarget::Attribute { gatt_if: 4, handle }),
+            Route::App(OTA_APP)
+        );
+    }
+
+    assert_eq!(
+        router.route(EventTarget::Attribute {
+            gatt_if: 3,
+            handle: 82
+        }),
+        Route::App(PRODUCT_APP)
+    );
+
+    // Outside of every service, `gatt_if` decides
+    assert_eq!(
+        router.route(EventTarget::Attribute {
+            gatt_if: 4,
+            handle: 65
+        }),
+        Route::App(PRODUCT_APP)
+    );
+    assert_eq!(
+        router.route(EventTarget::Attribute {
+            gatt_if: 9,
+            handle: 200
+        }),
+        Route::Unrouted
+    );
+}
+
+#[test]
+fn duplicate_app_is_rejected() {
+    let mut router = router();
+
+    assert_eq!(
+        router.add_app(OTA_APP),
+        Err(RouterError::DuplicateApp(OTA_APP))
+    );
+}
+
+#[test]
+fn overlapping_services_are_rejected() {
+    let mut router = router();
+
+    assert_eq!(
+        router.add_service(PRODUCT_APP, 60, 10),
+        Err(RouterError::OverlappingService { app_id: OTA_APP })
+    );
+    assert_eq!(
+        router.add_service(7, 100, 4),
+        Err(RouterError::UnknownApp(7))
+    );
+
+    // Adjacent ranges don't overlap
+    router.add_service(PRODUCT_APP, 65, 4).unwrap();
+    assert_eq!(
+        router.route(EventTarget::Attribute {
+            gatt_if: 3,
+            handle: 65
+        }),
+        Route::App(PRODUCT_APP)
+    );
+}
+
+#[test]
+fn removed_app_is_unrouted() {
+    let mut router = router();
+
+    router.remove_app(PRODUCT_APP);
+
+    assert_eq!(router.route(EventTarget::Interface(4)), Route::Unrouted);
+    assert_eq!(
+        router.route(EventTarget::Attribute {
+            gatt_if: 4,
+            handle: 82
+        }),
+        Route::Unrouted
+    );
+
+    // App id can be registered again
+    router.add_app(PRODUCT_APP).unwrap();
+}
@@ -1,26 +1,26 @@
 import asyncio
 from bleak import BleakClient, BleakScanner
 
+OTA_SERVICE_UUID = "81ea96fb-1117-4ea4-9df0-d30cd73e0e76"
+
 
 async def scan_ble_devices():
     while True:
         print("Scanning for BLE devices...")
-        devices = await BleakScanner.discover()
+        devices = await BleakScanner.discover(service_uuids=[OTA_SERVICE_UUID])
         for device in devices:
-            if device.name == "ESP32":
-
-                print(f"Device found: {device.name}, Address: {device.address}")
-
-                async with BleakClient(device.address) as client:
-                    services = await client.get_services()
-                    print(
-                        f"Services and Characteristics for device at {device.address}:"
-                    )
-                    for service in services:
-                        print(f"\nService: {service}")
-                        characteristics = service.characteristics
-                        for characteristic in characteristics:
-                            print(f"  - Characteristic: {characteristic}")
+            print(f"Device found: {device.name}, Address: {device.address}")
+
+            async with BleakClient(device.address) as client:
+                services = await client.get_services()
+                print(
+                    f"Services and Characteristics for device at {device.address}:"
+                )
+                for service in services:
+                    print(f"\nService: {service}")
+                    characteristics = service.characteristics
+                    for characteristic in characteristics:
+                        print(f"  - Characteristic: {characteristic}")
 
         print()
         print()
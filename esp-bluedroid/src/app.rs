@@ -0,0 +1,80 @@
+use std::sync::{Arc, Mutex, OnceLock};
+
+use esp_idf_svc::bt::ble::gatt::{server::GattsEvent, GattStatus};
+
+use crate::service::Service;
+
+type GattCallback = Box<dyn FnMut(u8, &GattsEvent) + Send + 'static>;
+
+/// GATT application: its services and the handlers of the events routed to it
+///
+/// Every app gets its own `gatt_if` once registered with `Registry::register`, several apps can
+/// share the stack.
+pub struct App {
+    app_id: u16,
+    services: Vec<Arc<Service>>,
+    gatt_if: OnceLock<u8>,
+    callbacks: Mutex<Vec<GattCallback>>,
+}
+
+impl App {
+    pub fn new(app_id: u16) -> Self {
+        Self {
+            app_id,
+            services: Vec::new(),
+            gatt_if: OnceLock::new(),
+            callbacks: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Adds a service, created on the stack once the app is registered
+    pub fn service(mut self, service: Arc<Service>) -> Self {
+        self.services.push(service);
+        self
+    }
+
+    pub fn app_id(&self) -> u16 {
+        self.app_id
+    }
+
+    /// Interface assigned by the stack, `None` until the registration completes
+    pub fn gatt_if(&self) -> Option<u8> {
+        self.gatt_if.get().copied()
+    }
+
+    pub fn services(&self) -> &[Arc<Service>] {
+        &self.services
+    }
+
+    /// Subscribes to the events of this app, called after its services handled them
+    pub fn subscribe<F>(&self, callback: F)
+    where
+        F: FnMut(u8, &GattsEvent) + Send + 'static,
+    {
+        self.callbacks.lock().unwrap().push(Box::new(callback));
+    }
+
+    pub(crate) fn handle_event(&self, gatt_if: u8, event: &GattsEvent) {
+        if let GattsEvent::ServiceRegistered {
+            status: GattStatus::Ok,
+            app_id,
+        } = event
+        {
+            if *app_id == self.app_id {
+                let _ = self.gatt_if.set(gatt_if);
+            }
+        }
+
+        for service in &self.services {
+            if let Err(error) = service.handle_event(gatt_if, event) {
+                log::error!("Service {:?}: {:?}", service.uuid(), error);
+            }
+        }
+
+        self.callbacks
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .for_each(|callback| callback(gatt_if, event));
+    }
+}
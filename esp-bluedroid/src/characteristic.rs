@@ -0,0 +1,310 @@
+use std::{
+    collections::HashMap,
+    sync::{Mutex, OnceLock},
+};
+
+use anyhow::Result;
+use enumset::EnumSet;
+use esp_idf_svc::{
+    bt::{
+        ble::gatt::{AutoResponse, GattCharacteristic, Permission, Property},
+        BtUuid,
+    },
+    sys::{
+        esp, esp_attr_control_t, esp_ble_gatts_add_char_descr, esp_ble_gatts_get_attr_value,
+        esp_ble_gatts_send_indicate, esp_ble_gatts_set_attr_value, esp_bt_uuid_t,
+        esp_bt_uuid_t__bindgen_ty_1, ESP_GATT_PERM_READ, ESP_GATT_PERM_WRITE, ESP_GATT_RSP_BY_APP,
+        ESP_UUID_LEN_16,
+    },
+};
+
+/// UUID of the Client Characteristic Configuration descriptor
+pub const CCCD_UUID: u16 = 0x2902;
+
+/// Longest attribute value allowed by ATT
+pub const MAX_ATTRIBUTE_LEN: usize = 512;
+
+/// Notifications and indications a peer subscribed to through the CCCD
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Subscription {
+    pub notify: bool,
+    pub indicate: bool,
+}
+
+impl Subscription {
+    /// Decodes a CCCD value: `[flags: u16 LE]`, bit 0 notifications, bit 1 indications
+    pub fn from_cccd(value: &[u8]) -> Option<Self> {
+        let flags = u16::from_le_bytes(value.try_into().ok()?);
+
+        Some(Self {
+            notify: flags & 0x0001 != 0,
+            indicate: flags & 0x0002 != 0,
+        })
+    }
+
+    /// Encodes the subscription as a CCCD value
+    pub fn to_cccd(&self) -> [u8; 2] {
+        (u16::from(self.notify) | u16::from(self.indicate) << 1).to_le_bytes()
+    }
+
+    pub fn is_subscribed(&self) -> bool {
+        self.notify || self.indicate
+    }
+}
+
+/// GATT characteristic of a `Service`, its value is stored in the attribute table of the stack
+///
+/// Handles are resolved by the `Service` it is added to, once the characteristic (and its CCCD,
+/// for notifying characteristics) is created. Subscriptions are tracked per connection from the
+/// CCCD writes.
+pub struct Characteristic {
+    uuid: BtUuid,
+    permissions: EnumSet<Permission>,
+    properties: EnumSet<Property>,
+    auto_rsp: AutoResponse,
+    max_len: usize,
+
+    attribute_handle: OnceLock<u16>,
+    cccd_handle: OnceLock<u16>,
+    subscribers: Mutex<HashMap<u16, Subscription>>,
+}
+
+impl Characteristic {
+    pub fn new(
+        uuid: BtUuid,
+        permissions: EnumSet<Permission>,
+        properties: EnumSet<Property>,
+        auto_rsp: AutoResponse,
+    ) -> Self {
+        Self {
+            uuid,
+            permissions,
+            properties,
+            auto_rsp,
+            max_len: MAX_ATTRIBUTE_LEN,
+            attribute_handle: OnceLock::new(),
+            cccd_handle: OnceLock::new(),
+            subscribers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Longest value the attribute can hold, `MAX_ATTRIBUTE_LEN` by default
+    pub fn with_max_len(mut self, max_len: usize) -> Self {
+        self.max_len = max_len;
+        self
+    }
+
+    pub fn uuid(&self) -> &BtUuid {
+        &self.uuid
+    }
+
+    pub fn handle(&self) -> Option<u16> {
+        self.attribute_handle.get().copied()
+    }
+
+    pub fn cccd_handle(&self) -> Option<u16> {
+        self.cccd_handle.get().copied()
+    }
+
+    /// Notifying and indicating characteristics need a CCCD for clients to subscribe
+    pub fn needs_cccd(&self) -> bool {
+        self.properties.contains(Property::Notify) || self.properties.contains(Property::Indicate)
+    }
+
+    /// Whether the characteristic and its CCCD were added to the service
+    pub fn is_initialized(&self) -> bool {
+        self.handle().is_some() && (!self.needs_cccd() || self.cccd_handle().is_some())
+    }
+
+    /// Copies the current value of the attribute
+    pub fn get_value(&self) -> Result<Vec<u8>> {
+        let attr_handle = self.attribute_handle()?;
+
+        let mut len: u16 = 0;
+        let mut data: *const u8 = core::ptr::null();
+
+        unsafe {
+            esp!(esp_ble_gatts_get_attr_value(
+                attr_handle,
+                &mut len,
+                &mut data
+            ))?;
+
+            if data.is_null() {
+                return Ok(Vec::new());
+            }
+
+            // Stack owns the buffer and may replace it on the next write, copy it right away
+            Ok(core::slice::from_raw_parts(data, len as _).to_vec())
+        }
+    }
+
+    /// Updates the attribute, served to clients on their next read
+    pub fn set_value(&self, value: &[u8]) -> Result<()> {
+        let attr_handle = self.attribute_handle()?;
+
+        esp!(unsafe {
+            esp_ble_gatts_set_attr_value(attr_handle, value.len() as _, value.as_ptr())
+        })?;
+
+        Ok(())
+    }
+
+    /// Updates the attribute and notifies every peer subscribed to notifications
+    pub fn notify(&self, gatt_if: u8, value: &[u8]) -> Result<()> {
+        self.send(gatt_if, value, |subscription| subscription.notify, false)
+    }
+
+    /// Updates the attribute and indicates it to every peer subscribed to indications
+    pub fn indicate(&self, gatt_if: u8, value: &[u8]) -> Result<()> {
+        self.send(gatt_if, value, |subscription| subscription.indicate, true)
+    }
+
+    /// Notifies a single peer if it's subscribed, without updating the attribute
+    ///
+    /// E.g. to answer one peer without other peers reading or being notified of the answer.
+    pub fn notify_peer(&self, gatt_if: u8, conn_id: u16, value: &[u8]) -> Result<()> {
+        let subscribed = self
+            .subscribers
+            .lock()
+            .unwrap()
+            .get(&conn_id)
+            .is_some_and(|subscription| subscription.notify);
+
+        if !subscribed {
+            return Ok(());
+        }
+
+        esp!(unsafe {
+            esp_ble_gatts_send_indicate(
+                gatt_if,
+                conn_id,
+                self.attribute_handle()?,
+                value.len() as _,
+                // Only read by the stack
+                value.as_ptr() as *mut _,
+                false,
+            )
+        })?;
+
+        Ok(())
+    }
+
+    fn send(
+        &self,
+        gatt_if: u8,
+        value: &[u8],
+        is_subscribed: impl Fn(&Subscription) -> bool,
+        need_confirm: bool,
+    ) -> Result<()> {
+        self.set_value(value)?;
+
+        let attr_handle = self.attribute_handle()?;
+
+        let peers = self
+            .subscribers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, subscription)| is_subscribed(subscription))
+            .map(|(conn_id, _)| *conn_id)
+            .collect::<Vec<_>>();
+
+        for conn_id in peers {
+            esp!(unsafe {
+                esp_ble_gatts_send_indicate(
+                    gatt_if,
+                    conn_id,
+                    attr_handle,
+                    value.len() as _,
+                    // Only read by the stack
+                    value.as_ptr() as *mut _,
+                    need_confirm,
+                )
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Definition passed to `EspGatts::add_characteristic`
+    pub(crate) fn definition(&self) -> GattCharacteristic {
+        GattCharacteristic {
+            uuid: self.uuid.clone(),
+            permissions: self.permissions,
+            properties: self.properties,
+            max_len: self.max_len,
+            auto_rsp: self.auto_rsp,
+        }
+    }
+
+    /// Adds the CCCD of the characteristic to `service_handle`
+    ///
+    /// `EspGatts::add_descriptor` leaves descriptors to the stack, which would answer CCCD
+    /// requests on its own. The CCCD is answered by the app instead, per connection, see
+    /// `Service::handle_event`.
+    pub(crate) fn add_cccd(&self, service_handle: u16) -> Result<()> {
+        let mut uuid = esp_bt_uuid_t {
+            len: ESP_UUID_LEN_16 as _,
+            uuid: esp_bt_uuid_t__bindgen_ty_1 { uuid16: CCCD_UUID },
+        };
+        let mut control = esp_attr_control_t {
+            auto_rsp: ESP_GATT_RSP_BY_APP as _,
+        };
+
+        esp!(unsafe {
+            esp_ble_gatts_add_char_descr(
+                service_handle,
+                &mut uuid,
+                (ESP_GATT_PERM_READ | ESP_GATT_PERM_WRITE) as _,
+                std::ptr::null_mut(),
+                &mut control,
+            )
+        })?;
+
+        Ok(())
+    }
+
+    pub(crate) fn set_attribute_handle(&self, handle: u16) -> Result<()> {
+        self.attribute_handle
+            .set(handle)
+            .map_err(|_| anyhow::anyhow!("Attribute handle already set"))
+    }
+
+    pub(crate) fn set_cccd_handle(&self, handle: u16) -> Result<()> {
+        self.cccd_handle
+            .set(handle)
+            .map_err(|_| anyhow::anyhow!("CCCD handle already set"))
+    }
+
+    /// Applies a CCCD write of `conn_id`
+    pub(crate) fn set_subscription(&self, conn_id: u16, subscription: Subscription) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+
+        if subscription.is_subscribed() {
+            subscribers.insert(conn_id, subscription);
+        } else {
+            subscribers.remove(&conn_id);
+        }
+    }
+
+    /// Subscription of `conn_id`, nothing if it never wrote the CCCD
+    pub(crate) fn subscription(&self, conn_id: u16) -> Subscription {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .get(&conn_id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Drops the subscription of a disconnected peer
+    pub(crate) fn remove_subscriber(&self, conn_id: u16) {
+        self.subscribers.lock().unwrap().remove(&conn_id);
+    }
+
+    fn attribute_handle(&self) -> Result<u16> {
+        self.handle()
+            .ok_or_else(|| anyhow::anyhow!("Attribute handle not set"))
+    }
+}
@@ -1,7 +1,41 @@
+use std::{borrow::Borrow, sync::Arc};
+
+use esp_idf_svc::{
+    bt::{
+        ble::{gap::EspBleGap, gatt::server::EspGatts},
+        Ble, BtDriver,
+    },
+    hal::modem::BluetoothModem,
+};
+use lazy_static::lazy_static;
+
+pub mod app;
+pub mod characteristic;
+pub mod registry;
+pub mod service;
+
+pub use app::App;
+pub use bluedroid_router as router;
+pub use characteristic::Characteristic;
+pub use registry::REGISTRY;
+pub use service::Service;
+
+pub type StaticBtDriver = BtDriver<'static, Ble>;
+pub type StaticEspGatts = Arc<EspGatts<'static, Ble, &'static BtDriver<'static, Ble>>>;
+pub type StaticEspBleGap = Arc<EspBleGap<'static, Ble, &'static BtDriver<'static, Ble>>>;
+
 lazy_static! {
     pub static ref BT_DRIVER: StaticBtDriver =
         BtDriver::new(unsafe { BluetoothModem::new() }, None).unwrap();
     pub static ref GAP: StaticEspBleGap = Arc::new(EspBleGap::new((*BT_DRIVER).borrow()).unwrap());
     pub static ref GATT: StaticEspGatts = Arc::new(EspGatts::new((*BT_DRIVER).borrow()).unwrap());
-    static ref OTA_BLE: Arc<Mutex<Option<Arc<OtaBle>>>> = Arc::new(Mutex::new(None));
+}
+
+/// Initializes the BlueDroid stack, `GAP` and `GATT` are usable afterwards
+///
+/// Their events are handled by `REGISTRY`, apps shouldn't subscribe to them directly.
+pub fn init() {
+    lazy_static::initialize(&BT_DRIVER);
+    lazy_static::initialize(&GATT);
+    lazy_static::initialize(&GAP);
 }
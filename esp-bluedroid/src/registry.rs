@@ -0,0 +1,183 @@
+use std::sync::{Arc, Mutex, OnceLock};
+
+use anyhow::Result;
+use esp_idf_svc::bt::ble::{
+    gap::BleGapEvent,
+    gatt::{server::GattsEvent, GattStatus},
+};
+use lazy_static::lazy_static;
+
+use crate::{
+    app::App,
+    router::{EventTarget, Route, Router},
+    GAP, GATT,
+};
+
+type GapCallback = Box<dyn FnMut(&BleGapEvent) + Send + 'static>;
+
+lazy_static! {
+    /// Apps sharing the stack, owns the `GAP` and `GATT` subscriptions
+    pub static ref REGISTRY: Registry = Registry::default();
+}
+
+/// Multiplexes stack events between the registered apps
+///
+/// GATT events are delivered to the app owning them (see `Router`), GAP events to every
+/// subscriber.
+#[derive(Default)]
+pub struct Registry {
+    router: Mutex<Router>,
+    apps: Mutex<Vec<Arc<App>>>,
+    gap_callbacks: Mutex<Vec<GapCallback>>,
+    subscribed: OnceLock<()>,
+}
+
+impl Registry {
+    /// Registers `app` with the stack, its services are created once the registration completes
+    pub fn register(&'static self, app: App) -> Result<Arc<App>> {
+        self.subscribe()?;
+
+        let app_id = app.app_id();
+        self.router.lock().unwrap().add_app(app_id)?;
+
+        for service in app.services() {
+            service.attach(app_id)?;
+        }
+
+        let app = Arc::new(app);
+        self.apps.lock().unwrap().push(app.clone());
+
+        if let Err(error) = GATT.register_app(app_id) {
+            self.router.lock().unwrap().remove_app(app_id);
+            self.apps
+                .lock()
+                .unwrap()
+                .retain(|registered| registered.app_id() != app_id);
+
+            return Err(error.into());
+        }
+
+        Ok(app)
+    }
+
+    pub fn app(&self, app_id: u16) -> Option<Arc<App>> {
+        self.apps
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|app| app.app_id() == app_id)
+            .cloned()
+    }
+
+    pub fn subscribe_gap_event<F>(&'static self, callback: F) -> Result<()>
+    where
+        F: FnMut(&BleGapEvent) + Send + 'static,
+    {
+        self.subscribe()?;
+        self.gap_callbacks.lock().unwrap().push(Box::new(callback));
+
+        Ok(())
+    }
+
+    /// Takes over the stack events, only done once
+    fn subscribe(&'static self) -> Result<()> {
+        if self.subscribed.set(()).is_err() {
+            return Ok(());
+        }
+
+        crate::init();
+
+        GAP.subscribe(move |event| {
+            log::debug!("GAP Event: {:?}", event);
+
+            self.gap_callbacks
+                .lock()
+                .unwrap()
+                .iter_mut()
+                .for_each(|callback| callback(&event));
+        })?;
+
+        GATT.subscribe(move |(gatt_if, event)| {
+            log::debug!("GATT Event: {:?} {:?}", gatt_if, event);
+
+            self.dispatch(gatt_if, &event);
+        })?;
+
+        Ok(())
+    }
+
+    fn dispatch(&self, gatt_if: u8, event: &GattsEvent) {
+        let route = self
+            .router
+            .lock()
+            .unwrap()
+            .route(event_target(gatt_if, event));
+
+        // Handlers may use the registry, it isn't locked while they run
+        let apps = self
+            .apps
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|app| match route {
+                Route::App(app_id) => app.app_id() == app_id,
+                Route::All => true,
+                Route::Unrouted => false,
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+
+        if apps.is_empty() {
+            log::debug!("Unrouted GATT event: {:?} {:?}", gatt_if, event);
+        }
+
+        for app in apps {
+            app.handle_event(gatt_if, event);
+
+            if let GattsEvent::ServiceCreated {
+                status: GattStatus::Ok,
+                service_handle,
+                ..
+            } = event
+            {
+                self.add_service(&app, *service_handle);
+            }
+        }
+    }
+
+    /// Routes the attributes of a service created by `app` to it
+    fn add_service(&self, app: &App, service_handle: u16) {
+        let Some(service) = app
+            .services()
+            .iter()
+            .find(|service| service.handle() == Some(service_handle))
+        else {
+            return;
+        };
+
+        if let Err(error) = self.router.lock().unwrap().add_service(
+            app.app_id(),
+            service_handle,
+            service.num_handles(),
+        ) {
+            log::error!("Service {:?}: {}", service.uuid(), error);
+        }
+    }
+}
+
+/// What `event` is addressed to
+fn event_target(gatt_if: u8, event: &GattsEvent) -> EventTarget {
+    match event {
+        GattsEvent::ServiceRegistered { app_id, .. } => EventTarget::Registered {
+            app_id: *app_id,
+            gatt_if,
+        },
+        GattsEvent::Read { handle, .. } | GattsEvent::Write { handle, .. } => {
+            EventTarget::Attribute {
+                gatt_if,
+                handle: *handle,
+            }
+        }
+        _ => EventTarget::Interface(gatt_if),
+    }
+}
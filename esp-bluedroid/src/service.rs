@@ -0,0 +1,371 @@
+use std::sync::{Arc, OnceLock};
+
+use anyhow::Result;
+#[cfg(feature = "embassy")]
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use esp_idf_svc::bt::{
+    ble::gatt::{server::GattsEvent, GattId, GattResponse, GattServiceId, GattStatus},
+    BtUuid,
+};
+
+use crate::{
+    characteristic::{Characteristic, Subscription},
+    GATT,
+};
+
+/// GATT service declared up front and created on the stack once its `App` is registered
+///
+/// ```ignore
+/// let status = Arc::new(Characteristic::new(uuid, Permission::Read.into(), Property::Read | Property::Notify, AutoResponse::ByGatt));
+/// let service = Arc::new(Service::new(service_uuid).characteristic(status.clone()));
+///
+/// REGISTRY.register(App::new(app_id).service(service))?;
+/// ```
+///
+/// Registration walks through register_app -> create_service -> add_characteristic (and
+/// add_descriptor for CCCDs) -> start_service, driven by GATT events passed to `handle_event`.
+/// Characteristics are added one at a time, so every `CharacteristicAdded` resolves the handle of
+/// a known characteristic.
+pub struct Service {
+    uuid: BtUuid,
+    instance_id: u8,
+    is_primary: bool,
+    characteristics: Vec<Arc<Characteristic>>,
+
+    app_id: OnceLock<u16>,
+    gatt_if: OnceLock<u8>,
+    handle: OnceLock<u16>,
+    started: OnceLock<()>,
+    /// Outcome of the setup, `true` once started
+    #[cfg(feature = "embassy")]
+    setup: Signal<CriticalSectionRawMutex, bool>,
+}
+
+impl Service {
+    pub fn new(uuid: BtUuid) -> Self {
+        Self {
+            uuid,
+            instance_id: 0,
+            is_primary: true,
+            characteristics: Vec::new(),
+            app_id: OnceLock::new(),
+            gatt_if: OnceLock::new(),
+            handle: OnceLock::new(),
+            started: OnceLock::new(),
+            #[cfg(feature = "embassy")]
+            setup: Signal::new(),
+        }
+    }
+
+    pub fn instance_id(mut self, instance_id: u8) -> Self {
+        self.instance_id = instance_id;
+        self
+    }
+
+    pub fn secondary(mut self) -> Self {
+        self.is_primary = false;
+        self
+    }
+
+    /// Adds a characteristic, they are created in the order they are added
+    pub fn characteristic(mut self, characteristic: Arc<Characteristic>) -> Self {
+        self.characteristics.push(characteristic);
+        self
+    }
+
+    pub fn uuid(&self) -> &BtUuid {
+        &self.uuid
+    }
+
+    pub fn app_id(&self) -> Option<u16> {
+        self.app_id.get().copied()
+    }
+
+    /// Interface of the registered app, needed to notify peers
+    pub fn gatt_if(&self) -> Option<u8> {
+        self.gatt_if.get().copied()
+    }
+
+    pub fn handle(&self) -> Option<u16> {
+        self.handle.get().copied()
+    }
+
+    pub fn is_started(&self) -> bool {
+        self.started.get().is_some()
+    }
+
+    pub fn characteristics(&self) -> &[Arc<Characteristic>] {
+        &self.characteristics
+    }
+
+    /// Number of attribute handles of the service:
+    /// service declaration + (declaration, value) for every characteristic + their CCCDs
+    pub fn num_handles(&self) -> u16 {
+        self.characteristics
+            .iter()
+            .map(|characteristic| if characteristic.needs_cccd() { 3 } else { 2 })
+            .sum::<u16>()
+            + 1
+    }
+
+    /// Binds the service to the app it is registered with, see `Registry::register`
+    pub(crate) fn attach(&self, app_id: u16) -> Result<()> {
+        self.app_id
+            .set(app_id)
+            .map_err(|_| anyhow::anyhow!("Service has already been registered"))
+    }
+
+    /// Waits until the service is started, instead of watching for `ServiceStarted`
+    ///
+    /// Fails if any step of the setup fails.
+    #[cfg(feature = "embassy")]
+    pub async fn wait_started(&self) -> Result<()> {
+        if self.is_started() {
+            return Ok(());
+        }
+
+        if self.setup.wait().await {
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!("Failed to set up service {:?}", self.uuid))
+        }
+    }
+
+    /// Advances the setup of the service and tracks subscriptions of its characteristics
+    ///
+    /// Called by its `App` with every event routed to it, events of other services are ignored.
+    pub fn handle_event(&self, gatt_if: u8, event: &GattsEvent) -> Result<()> {
+        let result = self.advance(gatt_if, event);
+
+        #[cfg(feature = "embassy")]
+        if result.is_err() && !self.is_started() {
+            self.setup.signal(false);
+        }
+
+        result
+    }
+
+    fn advance(&self, gatt_if: u8, event: &GattsEvent) -> Result<()> {
+        match event {
+            GattsEvent::ServiceRegistered { status, app_id } => {
+                if Some(*app_id) == self.app_id() {
+                    if *status != GattStatus::Ok {
+                        return Err(anyhow::anyhow!("Failed to register GATT app {}", app_id));
+                    }
+
+                    self.gatt_if
+                        .set(gatt_if)
+                        .map_err(|_| anyhow::anyhow!("GATT app registered twice"))?;
+
+                    GATT.create_service(
+                        gatt_if,
+                        &GattServiceId {
+                            id: GattId {
+                                uuid: self.uuid.clone(),
+                                inst_id: self.instance_id,
+                            },
+                            is_primary: self.is_primary,
+                        },
+                        self.num_handles(),
+                    )?;
+                }
+            }
+            GattsEvent::ServiceCreated {
+                status,
+                service_handle,
+                service_id,
+            } => {
+                if Some(gatt_if) == self.gatt_if()
+                    && service_id.id.uuid == self.uuid
+                    && service_id.id.inst_id == self.instance_id
+                {
+                    if *status != GattStatus::Ok {
+                        return Err(anyhow::anyhow!("Failed to create service {:?}", self.uuid));
+                    }
+
+                    self.handle
+                        .set(*service_handle)
+                        .map_err(|_| anyhow::anyhow!("Service created twice"))?;
+
+                    self.add_next_characteristic()?;
+                }
+            }
+            GattsEvent::CharacteristicAdded {
+                status,
+                attr_handle,
+                service_handle,
+                char_uuid,
+            } => {
+                if Some(*service_handle) == self.handle() {
+                    if *status != GattStatus::Ok {
+                        return Err(anyhow::anyhow!(
+                            "Failed to add characteristic {:?}",
+                            char_uuid
+                        ));
+                    }
+
+                    let characteristic = self
+                        .next_pending()
+                        .filter(|characteristic| characteristic.uuid() == char_uuid)
+                        .ok_or_else(|| {
+                            anyhow::anyhow!("Unexpected characteristic {:?}", char_uuid)
+                        })?;
+                    characteristic.set_attribute_handle(*attr_handle)?;
+
+                    self.add_next_characteristic()?;
+                }
+            }
+            GattsEvent::DescriptorAdded {
+                status,
+                attr_handle,
+                service_handle,
+                ..
+            } => {
+                if Some(*service_handle) == self.handle() {
+                    // Only CCCDs are added, right after their characteristic
+                    let characteristic = self
+                        .next_pending()
+                        .ok_or_else(|| anyhow::anyhow!("Unexpected descriptor"))?;
+
+                    if *status != GattStatus::Ok {
+                        return Err(anyhow::anyhow!(
+                            "Failed to add CCCD of {:?}",
+                            characteristic.uuid()
+                        ));
+                    }
+
+                    characteristic.set_cccd_handle(*attr_handle)?;
+
+                    self.add_next_characteristic()?;
+                }
+            }
+            GattsEvent::ServiceStarted {
+                status,
+                service_handle,
+            } => {
+                if Some(*service_handle) == self.handle() {
+                    if *status != GattStatus::Ok {
+                        return Err(anyhow::anyhow!("Failed to start service {:?}", self.uuid));
+                    }
+
+                    let _ = self.started.set(());
+
+                    #[cfg(feature = "embassy")]
+                    self.setup.signal(true);
+                }
+            }
+            GattsEvent::Read {
+                conn_id,
+                trans_id,
+                handle,
+                offset,
+                need_rsp,
+                ..
+            } => {
+                if Some(gatt_if) != self.gatt_if() || !*need_rsp {
+                    return Ok(());
+                }
+
+                // CCCDs are answered with the subscription of the reading peer, other reads are
+                // left to the owner of the service
+                if let Some(characteristic) = self.cccd_owner(*handle) {
+                    let value = characteristic.subscription(*conn_id).to_cccd();
+
+                    match value.get(*offset as usize..) {
+                        Some(value) => {
+                            let mut response = GattResponse::new();
+                            response
+                                .attr_handle(*handle)
+                                .auth_req(0)
+                                .offset(*offset)
+                                .value(value)?;
+
+                            GATT.send_response(
+                                gatt_if,
+                                *conn_id,
+                                *trans_id,
+                                GattStatus::Ok,
+                                Some(&response),
+                            )?;
+                        }
+                        None => GATT.send_response(
+                            gatt_if,
+                            *conn_id,
+                            *trans_id,
+                            GattStatus::InvalidOffset,
+                            None,
+                        )?,
+                    }
+                }
+            }
+            GattsEvent::Write {
+                conn_id,
+                trans_id,
+                handle,
+                need_rsp,
+                value,
+                ..
+            } => {
+                if Some(gatt_if) != self.gatt_if() {
+                    return Ok(());
+                }
+
+                // CCCDs are answered by the app, other writes are left to the owner of the service
+                if let Some(characteristic) = self.cccd_owner(*handle) {
+                    let status = match Subscription::from_cccd(value) {
+                        Some(subscription) => {
+                            characteristic.set_subscription(*conn_id, subscription);
+                            GattStatus::Ok
+                        }
+                        None => GattStatus::InvalidAttrLen,
+                    };
+
+                    if *need_rsp {
+                        GATT.send_response(gatt_if, *conn_id, *trans_id, status, None)?;
+                    }
+                }
+            }
+            GattsEvent::PeerDisconnected { conn_id, .. } => {
+                for characteristic in &self.characteristics {
+                    characteristic.remove_subscriber(*conn_id);
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Characteristic whose CCCD is `handle`
+    fn cccd_owner(&self, handle: u16) -> Option<&Arc<Characteristic>> {
+        self.characteristics
+            .iter()
+            .find(|characteristic| characteristic.cccd_handle() == Some(handle))
+    }
+
+    /// First characteristic which is not fully added to the service yet
+    fn next_pending(&self) -> Option<&Arc<Characteristic>> {
+        self.characteristics
+            .iter()
+            .find(|characteristic| !characteristic.is_initialized())
+    }
+
+    /// Adds the next characteristic or CCCD of the service, or starts it once all of them are
+    fn add_next_characteristic(&self) -> Result<()> {
+        let service_handle = self
+            .handle()
+            .ok_or_else(|| anyhow::anyhow!("Service handle not set yet"))?;
+
+        match self.next_pending() {
+            Some(characteristic) if characteristic.handle().is_none() => {
+                GATT.add_characteristic(service_handle, &characteristic.definition(), &[])?;
+            }
+            Some(characteristic) => {
+                characteristic.add_cccd(service_handle)?;
+            }
+            None => GATT.start_service(service_handle)?,
+        }
+
+        Ok(())
+    }
+}
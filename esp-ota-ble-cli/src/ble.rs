@@ -0,0 +1,250 @@
+use std::{collections::HashMap, pin::Pin, time::Duration};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use btleplug::{
+    api::{
+        Central, CentralEvent, Characteristic, Manager as _, Peripheral as _, PeripheralProperties,
+        ScanFilter, ValueNotification, WriteType,
+    },
+    platform::{Adapter, Manager, Peripheral},
+};
+use futures::{Stream, StreamExt};
+use ota_protocol::advertisement::ServiceData;
+
+use crate::{
+    transport::{MissingCharacteristic, OtaCharacteristic, OtaTransport, SERVICE_UUID},
+    Error,
+};
+
+type Notifications = Pin<Box<dyn Stream<Item = ValueNotification> + Send>>;
+
+/// Device advertising the OTA service, found by `BleTransport::scan`
+#[derive(Debug, Clone)]
+pub struct ScannedDevice {
+    pub address: String,
+    pub name: Option<String>,
+    pub rssi: Option<i16>,
+    /// `None` if the device doesn't advertise service data, e.g. it runs an older firmware
+    pub service_data: Option<ServiceData>,
+}
+
+/// `OtaTransport` over a BLE connection to a device advertising the OTA service
+pub struct BleTransport {
+    peripheral: Peripheral,
+    characteristics: HashMap<OtaCharacteristic, Characteristic>,
+    notifications: Notifications,
+}
+
+impl BleTransport {
+    /// Scans for a device advertising the OTA service and connects to it
+    ///
+    /// With `address` set, other devices are ignored.
+    pub async fn connect(address: Option<&str>, scan_timeout: Duration) -> Result<Self, Error> {
+        let adapter = Self::adapter().await?;
+
+        adapter
+            .start_scan(ScanFilter {
+                services: vec![SERVICE_UUID],
+            })
+            .await?;
+
+        let found = tokio::time::timeout(scan_timeout, Self::find(&adapter, address)).await;
+        adapter.stop_scan().await?;
+
+        let peripheral = found.map_err(|_| Error::DeviceNotFound)??;
+
+        log::info!("Connecting to {}", peripheral.address());
+
+        peripheral.connect().await?;
+        peripheral.discover_services().await?;
+
+        let characteristics = peripheral
+            .characteristics()
+            .into_iter()
+            .filter(|c| c.service_uuid == SERVICE_UUID)
+            .filter_map(|c| OtaCharacteristic::from_uuid(c.uuid).map(|ota| (ota, c)))
+            .collect::<HashMap<_, _>>();
+
+        // Optional ones fail with `Error::Unsupported` once used
+        if let Some(missing) = OtaCharacteristic::REQUIRED
+            .iter()
+            .find(|c| !characteristics.contains_key(c))
+        {
+            peripheral.disconnect().await?;
+
+            return Err(Error::Transport(anyhow::anyhow!(
+                "Device doesn't expose {:?} characteristic",
+                missing
+            )));
+        }
+
+        let notifications = peripheral.notifications().await?;
+
+        Ok(Self {
+            peripheral,
+            characteristics,
+            notifications,
+        })
+    }
+
+    /// Lists the devices advertising the OTA service within `duration`, without connecting
+    pub async fn scan(duration: Duration) -> Result<Vec<ScannedDevice>, Error> {
+        let adapter = Self::adapter().await?;
+
+        adapter
+            .start_scan(ScanFilter {
+                services: vec![SERVICE_UUID],
+            })
+            .await?;
+        tokio::time::sleep(duration).await;
+        adapter.stop_scan().await?;
+
+        let mut devices = Vec::new();
+
+        for peripheral in adapter.peripherals().await? {
+            let Some(properties) = peripheral.properties().await? else {
+                continue;
+            };
+
+            if !advertises_ota(&properties) {
+                continue;
+            }
+
+            devices.push(ScannedDevice {
+                address: peripheral.address().to_string(),
+                service_data: properties
+                    .service_data
+                    .get(&SERVICE_UUID)
+                    .and_then(|data| ServiceData::decode(data).ok()),
+                name: properties.local_name,
+                rssi: properties.rssi,
+            });
+        }
+
+        Ok(devices)
+    }
+
+    pub async fn disconnect(&self) -> Result<(), Error> {
+        self.peripheral.disconnect().await?;
+
+        Ok(())
+    }
+
+    async fn adapter() -> Result<Adapter, Error> {
+        Manager::new()
+            .await?
+            .adapters()
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::Transport(anyhow::anyhow!("No Bluetooth adapter found")))
+    }
+
+    /// Waits for the first device advertising the OTA service (and matching `address`, if set)
+    async fn find(adapter: &Adapter, address: Option<&str>) -> Result<Peripheral, Error> {
+        let mut events = adapter.events().await?;
+
+        while let Some(event) = events.next().await {
+            let (CentralEvent::DeviceDiscovered(id) | CentralEvent::DeviceUpdated(id)) = event
+            else {
+                continue;
+            };
+
+            let peripheral = adapter.peripheral(&id).await?;
+            let Some(properties) = peripheral.properties().await? else {
+                continue;
+            };
+
+            // Scan filter is only a hint on some platforms
+            if !advertises_ota(&properties) {
+                continue;
+            }
+
+            if let Some(address) = address {
+                if !peripheral
+                    .address()
+                    .to_string()
+                    .eq_ignore_ascii_case(address)
+                {
+                    continue;
+                }
+            }
+
+            log::info!(
+                "Found {} ({})",
+                peripheral.address(),
+                properties.local_name.as_deref().unwrap_or("unnamed")
+            );
+
+            return Ok(peripheral);
+        }
+
+        Err(Error::DeviceNotFound)
+    }
+
+    fn characteristic(
+        &self,
+        characteristic: OtaCharacteristic,
+    ) -> Result<&Characteristic, MissingCharacteristic> {
+        // Required characteristics were checked on connect
+        self.characteristics
+            .get(&characteristic)
+            .ok_or(MissingCharacteristic(characteristic))
+    }
+}
+
+#[async_trait]
+impl OtaTransport for BleTransport {
+    async fn read(&mut self, characteristic: OtaCharacteristic) -> Result<Vec<u8>> {
+        let characteristic = self.characteristic(characteristic)?;
+
+        Ok(self.peripheral.read(characteristic).await?)
+    }
+
+    async fn write(&mut self, characteristic: OtaCharacteristic, value: &[u8]) -> Result<()> {
+        let characteristic = self.characteristic(characteristic)?;
+
+        self.peripheral
+            .write(characteristic, value, WriteType::WithResponse)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn write_without_response(
+        &mut self,
+        characteristic: OtaCharacteristic,
+        value: &[u8],
+    ) -> Result<()> {
+        let characteristic = self.characteristic(characteristic)?;
+
+        self.peripheral
+            .write(characteristic, value, WriteType::WithoutResponse)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn subscribe(&mut self, characteristic: OtaCharacteristic) -> Result<()> {
+        let characteristic = self.characteristic(characteristic)?;
+
+        Ok(self.peripheral.subscribe(characteristic).await?)
+    }
+
+    async fn next_notification(&mut self) -> Result<(OtaCharacteristic, Vec<u8>)> {
+        while let Some(notification) = self.notifications.next().await {
+            if let Some(characteristic) = OtaCharacteristic::from_uuid(notification.uuid) {
+                return Ok((characteristic, notification.value));
+            }
+        }
+
+        Err(anyhow::anyhow!("Device disconnected"))
+    }
+}
+
+/// Whether the OTA service UUID is advertised, on its own or with service data
+fn advertises_ota(properties: &PeripheralProperties) -> bool {
+    properties.services.contains(&SERVICE_UUID)
+        || properties.service_data.contains_key(&SERVICE_UUID)
+}
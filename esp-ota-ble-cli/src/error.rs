@@ -0,0 +1,100 @@
+use std::fmt;
+
+use ota_protocol::status::{Status, StatusReport};
+
+use crate::transport::{MissingCharacteristic, OtaCharacteristic};
+
+/// Reasons the CLI fails, each one maps to its own process exit code
+#[derive(Debug)]
+pub enum Error {
+    /// Image can't be read or doesn't fit the protocol
+    InvalidImage(String),
+    /// No device advertising the OTA service was found
+    DeviceNotFound,
+    /// Bluetooth adapter or link failure
+    Transport(anyhow::Error),
+    /// Device rejected a request, `Status` tells why
+    Rejected(Status),
+    /// Device didn't report the end of the upload in time
+    Timeout,
+    /// Signing key or signature can't be read or written
+    InvalidKey(String),
+    /// Application on the device refused the update with its own reason code
+    Denied(u8),
+    /// Application on the device can't take the update right now
+    Deferred,
+    /// Partition table can't be read or parsed
+    InvalidPartitionTable(String),
+    /// Another client owns the transfer in progress on the device
+    Busy,
+    /// Device doesn't expose a characteristic the request needs, its firmware is too old
+    Unsupported(OtaCharacteristic),
+}
+
+impl Error {
+    /// Process exit code, `1` and `2` are left to panics and argument errors
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Self::InvalidImage(_) => 3,
+            Self::DeviceNotFound => 4,
+            Self::Transport(_) => 5,
+            Self::Rejected(_) => 6,
+            Self::Timeout => 7,
+            Self::InvalidKey(_) => 8,
+            Self::Denied(_) => 9,
+            Self::Deferred => 10,
+            Self::InvalidPartitionTable(_) => 11,
+            Self::Busy => 12,
+            Self::Unsupported(_) => 13,
+        }
+    }
+
+    /// Error of a status report with an error status
+    pub fn from_report(report: &StatusReport) -> Self {
+        match report.status {
+            Status::UpdateDenied => Self::Denied(report.reason),
+            Status::UpdateDeferred => Self::Deferred,
+            Status::Busy => Self::Busy,
+            status => Self::Rejected(status),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidImage(reason) => write!(f, "Invalid image: {}", reason),
+            Self::DeviceNotFound => write!(f, "No device advertising the OTA service found"),
+            Self::Transport(error) => write!(f, "Transport error: {:#}", error),
+            Self::Rejected(status) => write!(f, "Device rejected the request: {:?}", status),
+            Self::Timeout => write!(f, "Timed out waiting for the device"),
+            Self::InvalidKey(reason) => write!(f, "Invalid key: {}", reason),
+            Self::Denied(reason) => write!(f, "Device denied the update (reason {})", reason),
+            Self::Deferred => write!(f, "Device deferred the update, retry later"),
+            Self::InvalidPartitionTable(reason) => write!(f, "Invalid partition table: {}", reason),
+            Self::Busy => write!(f, "Device is being updated by another client"),
+            Self::Unsupported(characteristic) => write!(
+                f,
+                "Device doesn't support {:?}, its firmware is too old",
+                characteristic
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<anyhow::Error> for Error {
+    fn from(error: anyhow::Error) -> Self {
+        match error.downcast::<MissingCharacteristic>() {
+            Ok(MissingCharacteristic(characteristic)) => Self::Unsupported(characteristic),
+            Err(error) => Self::Transport(error),
+        }
+    }
+}
+
+impl From<btleplug::Error> for Error {
+    fn from(error: btleplug::Error) -> Self {
+        Self::Transport(error.into())
+    }
+}
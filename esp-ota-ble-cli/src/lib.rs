@@ -0,0 +1,12 @@
+//! Host side of the OTA GATT service: uploads firmware images to `esp-ota-ble` devices
+//!
+//! The upload logic only talks to an `OtaTransport`, `ble::BleTransport` is the real one.
+
+pub mod ble;
+pub mod error;
+pub mod partitions;
+pub mod signing;
+pub mod transport;
+pub mod upload;
+
+pub use error::Error;
@@ -1,3 +1,434 @@
-fn main() {
-    println!("Hello, world!");
+use std::{
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::Duration,
+};
+
+use clap::{Parser, Subcommand};
+use esp_ota_ble_cli::{
+    ble::BleTransport,
+    partitions::PartitionTable,
+    signing,
+    upload::{self, UploadOptions},
+    Error,
+};
+use indicatif::{ProgressBar, ProgressStyle};
+use ota_protocol::{advertisement::PROTOCOL_VERSION, slot::BootOutcome};
+
+/// Firmware updates of `esp-ota-ble` devices over BLE
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Uploads a firmware image (`.bin`) to the first device advertising the OTA service
+    Upload {
+        /// Firmware image to upload
+        file: PathBuf,
+
+        /// Only connect to the device with this address
+        #[arg(long)]
+        address: Option<String>,
+
+        /// Seconds to scan for a device before giving up
+        #[arg(long, default_value_t = 10)]
+        scan_timeout: u64,
+
+        /// Size of a single `file_block` write, header included, by default the ATT MTU
+        /// negotiated with the device minus 3
+        #[arg(long)]
+        block_size: Option<usize>,
+
+        /// Abort a transfer already in progress on the device, with a signed image also
+        /// install it if it is older than the running one
+        #[arg(long)]
+        force: bool,
+
+        /// Stream blocks with writes without response, acknowledged every few blocks
+        #[arg(long)]
+        stream: bool,
+
+        /// Reboot the device into the new image once it was activated
+        #[arg(long)]
+        reset: bool,
+
+        /// Detached signature of the image, made by `sign`
+        #[arg(long)]
+        signature: Option<PathBuf>,
+
+        /// Partition table of the device (CSV or binary), checked to fit the image before
+        /// connecting
+        #[arg(long)]
+        partitions: Option<PathBuf>,
+    },
+    /// Lists devices advertising the OTA service with their firmware versions, without
+    /// connecting
+    Scan {
+        /// Seconds to scan for
+        #[arg(long, default_value_t = 5)]
+        duration: u64,
+    },
+    /// Reports whether the last update was confirmed or rolled back
+    Slots {
+        /// Only connect to the device with this address
+        #[arg(long)]
+        address: Option<String>,
+
+        /// Seconds to scan for a device before giving up
+        #[arg(long, default_value_t = 10)]
+        scan_timeout: u64,
+    },
+    /// Prints the firmware running on the device and the largest image it accepts
+    Info {
+        /// Only connect to the device with this address
+        #[arg(long)]
+        address: Option<String>,
+
+        /// Seconds to scan for a device before giving up
+        #[arg(long, default_value_t = 10)]
+        scan_timeout: u64,
+    },
+    /// Prints a partition table (CSV or binary) and checks that its OTA slots are usable
+    Partitions {
+        /// Partition table to check
+        table: PathBuf,
+    },
+    /// Checks that a firmware image fits the smallest OTA slot of a partition table
+    Check {
+        /// Firmware image to check
+        file: PathBuf,
+
+        /// Partition table of the device (CSV or binary)
+        #[arg(long)]
+        partitions: PathBuf,
+    },
+    /// Generates an Ed25519 key pair for signing images
+    ///
+    /// The raw private key is written to `KEY`, the public one to `KEY.pub`.
+    Keygen {
+        /// Where to write the private key
+        key: PathBuf,
+
+        /// Replace keys already stored at `KEY` and `KEY.pub`
+        #[arg(long)]
+        force: bool,
+    },
+    /// Signs a firmware image, writing a detached signature to `FILE.sig`
+    Sign {
+        /// Firmware image to sign
+        file: PathBuf,
+
+        /// Private key generated by `keygen`
+        #[arg(long)]
+        key: PathBuf,
+
+        /// Where to write the signature instead of `FILE.sig`
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    match run(Cli::parse()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            log::error!("{}", error);
+            ExitCode::from(error.exit_code())
+        }
+    }
+}
+
+async fn run(cli: Cli) -> Result<(), Error> {
+    match cli.command {
+        Commands::Upload {
+            file,
+            address,
+            scan_timeout,
+            block_size,
+            force,
+            stream,
+            reset,
+            signature,
+            partitions,
+        } => {
+            let image = read_image(&file)?;
+            if let Some(partitions) = partitions {
+                PartitionTable::read(&partitions)?.check_image(image.len())?;
+            }
+
+            let signature = signature
+                .as_deref()
+                .map(signing::read_signature)
+                .transpose()?;
+
+            let mut transport =
+                BleTransport::connect(address.as_deref(), Duration::from_secs(scan_timeout))
+                    .await?;
+
+            let options = UploadOptions {
+                block_size,
+                force,
+                signature,
+                streaming: stream,
+                ..Default::default()
+            };
+
+            let progress = ProgressBar::new(image.len() as u64).with_style(
+                ProgressStyle::with_template(
+                    "{bar:40} {bytes}/{total_bytes} ({bytes_per_sec}, ETA {eta})",
+                )
+                .unwrap(),
+            );
+
+            let result = upload::upload(&mut transport, &image, &options, |sent, _| {
+                progress.set_position(sent as u64)
+            })
+            .await;
+
+            progress.finish();
+
+            if result.is_ok() {
+                log::info!("Image uploaded and activated");
+
+                if reset {
+                    log::info!("Resetting device");
+                    upload::reset(&mut transport).await?;
+                }
+            }
+
+            // Link is usually already gone after a reset
+            if let Err(error) = transport.disconnect().await {
+                log::warn!("Failed to disconnect: {}", error);
+            }
+
+            result
+        }
+        Commands::Scan { duration } => {
+            let devices = BleTransport::scan(Duration::from_secs(duration)).await?;
+
+            if devices.is_empty() {
+                return Err(Error::DeviceNotFound);
+            }
+
+            for device in devices {
+                let rssi = device
+                    .rssi
+                    .map_or_else(|| "-".to_string(), |rssi| format!("{} dBm", rssi));
+                let details = match device.service_data {
+                    Some(data) => format!(
+                        "id {:08x}, version {}{}{}",
+                        data.device_id,
+                        data.firmware_version
+                            .map_or_else(|| "unknown".to_string(), |version| version.to_string()),
+                        if data.transfer_in_progress {
+                            ", transfer in progress"
+                        } else {
+                            ""
+                        },
+                        if data.protocol_version != PROTOCOL_VERSION {
+                            format!(", protocol v{}", data.protocol_version)
+                        } else {
+                            String::new()
+                        },
+                    ),
+                    None => "no service data".to_string(),
+                };
+
+                log::info!(
+                    "{} {:<16} {:>8}  {}",
+                    device.address,
+                    device.name.as_deref().unwrap_or("unnamed"),
+                    rssi,
+                    details
+                );
+            }
+
+            Ok(())
+        }
+        Commands::Slots {
+            address,
+            scan_timeout,
+        } => {
+            let mut transport =
+                BleTransport::connect(address.as_deref(), Duration::from_secs(scan_timeout))
+                    .await?;
+
+            let result = upload::slot_states(&mut transport).await;
+
+            if let Err(error) = transport.disconnect().await {
+                log::warn!("Failed to disconnect: {}", error);
+            }
+
+            let states = result?;
+            log::info!(
+                "Running slot: {:?}, next slot: {:?}",
+                states.running,
+                states.next
+            );
+
+            match states.outcome() {
+                BootOutcome::Confirmed => log::info!("Running image is confirmed"),
+                BootOutcome::PendingVerify => {
+                    log::info!("Running image is waiting for its health check")
+                }
+                BootOutcome::RolledBack => {
+                    log::warn!("Last update was rolled back to the previous image")
+                }
+                BootOutcome::Untracked => {
+                    log::info!("Device doesn't track its images, rollback is disabled")
+                }
+            }
+
+            Ok(())
+        }
+        Commands::Info {
+            address,
+            scan_timeout,
+        } => {
+            let mut transport =
+                BleTransport::connect(address.as_deref(), Duration::from_secs(scan_timeout))
+                    .await?;
+
+            let result = match upload::firmware_info(&mut transport).await {
+                Ok(info) => match upload::link_info(&mut transport).await {
+                    Ok(link) => Ok((info, Some(link))),
+                    Err(Error::Unsupported(_)) => Ok((info, None)),
+                    Err(error) => Err(error),
+                },
+                Err(error) => Err(error),
+            };
+
+            if let Err(error) = transport.disconnect().await {
+                log::warn!("Failed to disconnect: {}", error);
+            }
+
+            let (info, link) = result?;
+            log::info!("Project: {}", info.project_name());
+            log::info!("Version: {}", info.version());
+            log::info!("Secure version: {}", info.secure_version);
+            log::info!("ESP-IDF: {}", info.idf_version());
+            log::info!("Built: {} {}", info.date(), info.time());
+            log::info!(
+                "ELF SHA-256: {}",
+                info.elf_sha256
+                    .iter()
+                    .map(|byte| format!("{:02x}", byte))
+                    .collect::<String>()
+            );
+            log::info!("Max OTA size: {} bytes", info.max_ota_size);
+            match link {
+                Some(link) => log::info!(
+                    "MTU: {} bytes, blocks of {} bytes",
+                    link.mtu,
+                    link.block_size()
+                ),
+                None => log::info!("MTU: not reported by the firmware"),
+            }
+
+            Ok(())
+        }
+        Commands::Partitions { table } => {
+            let table = PartitionTable::read(&table)?;
+
+            for partition in &table.partitions {
+                log::info!(
+                    "{:<16} {:<10} {:#04x} {:#010x} {:#010x}{}{}",
+                    partition.name,
+                    format!("{:?}", partition.ty),
+                    partition.subtype,
+                    partition.offset,
+                    partition.size,
+                    if partition.encrypted {
+                        " encrypted"
+                    } else {
+                        ""
+                    },
+                    if partition.readonly { " readonly" } else { "" },
+                );
+            }
+
+            let issues = table.validate();
+            for issue in &issues {
+                log::error!("{}", issue);
+            }
+
+            if !issues.is_empty() {
+                return Err(Error::InvalidPartitionTable(format!(
+                    "{} issue(s) found",
+                    issues.len()
+                )));
+            }
+
+            if let Some(max_ota_size) = table.max_ota_size() {
+                log::info!("Max OTA size: {} bytes", max_ota_size);
+            }
+
+            Ok(())
+        }
+        Commands::Check { file, partitions } => {
+            let image = read_image(&file)?;
+            let table = PartitionTable::read(&partitions)?;
+
+            for issue in table.validate() {
+                log::warn!("{}", issue);
+            }
+
+            table.check_image(image.len())?;
+
+            if let Some(max_ota_size) = table.max_ota_size() {
+                log::info!(
+                    "Image of {} bytes fits the smallest OTA slot, {} bytes left",
+                    image.len(),
+                    max_ota_size as usize - image.len()
+                );
+            }
+
+            Ok(())
+        }
+        Commands::Keygen { key, force } => {
+            let signing_key = signing::generate_key();
+            signing::write_key(&signing_key, &key, force)?;
+
+            log::info!(
+                "Public key written to {}: {}",
+                signing::public_key_path(&key).display(),
+                signing::public_key(&signing_key)
+                    .0
+                    .iter()
+                    .map(|byte| format!("{:02x}", byte))
+                    .collect::<String>()
+            );
+
+            Ok(())
+        }
+        Commands::Sign { file, key, output } => {
+            let image = read_image(&file)?;
+            let signature = signing::sign(&signing::read_key(&key)?, &image);
+
+            let output = output.unwrap_or_else(|| {
+                let mut path = file.into_os_string();
+                path.push(".sig");
+                path.into()
+            });
+
+            std::fs::write(&output, signature.encode())
+                .map_err(|error| Error::InvalidKey(format!("{}: {}", output.display(), error)))?;
+
+            log::info!("Signature written to {}", output.display());
+
+            Ok(())
+        }
+    }
+}
+
+fn read_image(file: &Path) -> Result<Vec<u8>, Error> {
+    std::fs::read(file)
+        .map_err(|error| Error::InvalidImage(format!("{}: {}", file.display(), error)))
 }
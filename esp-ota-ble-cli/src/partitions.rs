@@ -0,0 +1,450 @@
+//! ESP-IDF partition tables, to check offline that an image fits the OTA slots of a device
+//!
+//! Tables are read either as the CSV passed to `gen_esp32part.py` or as the binary it generates
+//! (`partition-table.bin`, flashed at `0x8000`).
+
+use std::{fmt, path::Path};
+
+use crate::Error;
+
+/// Offset of the first partition when the CSV doesn't give one, after the table at `0x8000`
+const FIRST_OFFSET: u32 = 0x9000;
+
+/// App partitions have to start on a 64 KiB boundary, the MMU maps them in pages of this size
+pub const APP_ALIGN: u32 = 0x10000;
+
+/// Other partitions have to start on a flash sector
+pub const DATA_ALIGN: u32 = 0x1000;
+
+/// Length of a binary table entry
+const ENTRY_LEN: usize = 32;
+
+const ENTRY_MAGIC: [u8; 2] = [0xaa, 0x50];
+
+/// Magic of the entry holding the MD5 digest of the preceding entries
+const MD5_MAGIC: [u8; 2] = [0xeb, 0xeb];
+
+const SUBTYPE_OTA_MIN: u8 = 0x10;
+const SUBTYPE_OTA_MAX: u8 = 0x1f;
+
+/// `ota` subtype of a data partition, the one holding `otadata`
+const SUBTYPE_OTADATA: u8 = 0x00;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionType {
+    App,
+    Data,
+    /// Application defined type, `0x40..=0xfe`
+    Custom(u8),
+}
+
+impl PartitionType {
+    fn from_u8(ty: u8) -> Self {
+        match ty {
+            0x00 => Self::App,
+            0x01 => Self::Data,
+            ty => Self::Custom(ty),
+        }
+    }
+
+    fn parse(ty: &str) -> Result<Self, String> {
+        match ty {
+            "app" => Ok(Self::App),
+            "data" => Ok(Self::Data),
+            ty => parse_u8(ty)
+                .map(Self::from_u8)
+                .ok_or_else(|| format!("unknown type {:?}", ty)),
+        }
+    }
+
+    /// Partitions of this type have to start at a multiple of this
+    pub fn alignment(&self) -> u32 {
+        match self {
+            Self::App => APP_ALIGN,
+            _ => DATA_ALIGN,
+        }
+    }
+
+    /// Subtype of a partition of this type named `subtype` in a CSV
+    fn parse_subtype(&self, subtype: &str) -> Result<u8, String> {
+        let known = match (self, subtype) {
+            (Self::App, "factory") => Some(0x00),
+            (Self::App, "test") => Some(0x20),
+            (Self::App, subtype) => subtype
+                .strip_prefix("ota_")
+                .and_then(|index| index.parse::<u8>().ok())
+                .filter(|index| *index <= SUBTYPE_OTA_MAX - SUBTYPE_OTA_MIN)
+                .map(|index| SUBTYPE_OTA_MIN + index),
+            (Self::Data, "ota") => Some(SUBTYPE_OTADATA),
+            (Self::Data, "phy") => Some(0x01),
+            (Self::Data, "nvs") => Some(0x02),
+            (Self::Data, "coredump") => Some(0x03),
+            (Self::Data, "nvs_keys") => Some(0x04),
+            (Self::Data, "efuse") => Some(0x05),
+            (Self::Data, "undefined") => Some(0x06),
+            (Self::Data, "esphttpd") => Some(0x80),
+            (Self::Data, "fat") => Some(0x81),
+            (Self::Data, "spiffs") => Some(0x82),
+            (Self::Data, "littlefs") => Some(0x83),
+            _ => None,
+        };
+
+        known
+            .or_else(|| parse_u8(subtype))
+            .ok_or_else(|| format!("unknown subtype {:?}", subtype))
+    }
+}
+
+/// Single entry of a partition table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Partition {
+    pub name: String,
+    pub ty: PartitionType,
+    pub subtype: u8,
+    pub offset: u32,
+    pub size: u32,
+    pub encrypted: bool,
+    pub readonly: bool,
+}
+
+impl Partition {
+    /// Index of an `ota_<index>` app partition
+    pub fn ota_index(&self) -> Option<u8> {
+        (self.ty == PartitionType::App
+            && (SUBTYPE_OTA_MIN..=SUBTYPE_OTA_MAX).contains(&self.subtype))
+        .then(|| self.subtype - SUBTYPE_OTA_MIN)
+    }
+
+    /// Whether the partition holds `otadata`, which selects the OTA slot to boot
+    pub fn is_otadata(&self) -> bool {
+        self.ty == PartitionType::Data && self.subtype == SUBTYPE_OTADATA
+    }
+
+    /// First byte after the partition
+    pub fn end(&self) -> u64 {
+        self.offset as u64 + self.size as u64
+    }
+}
+
+/// Partitions in table order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartitionTable {
+    pub partitions: Vec<Partition>,
+}
+
+impl PartitionTable {
+    /// Reads a binary table, or a CSV if `path` doesn't start with a binary entry
+    pub fn read(path: &Path) -> Result<Self, Error> {
+        let invalid =
+            |error: String| Error::InvalidPartitionTable(format!("{}: {}", path.display(), error));
+
+        let bytes = std::fs::read(path).map_err(|error| invalid(error.to_string()))?;
+
+        if bytes.starts_with(&ENTRY_MAGIC) {
+            Self::decode(&bytes)
+        } else {
+            let csv = String::from_utf8(bytes).map_err(|error| invalid(error.to_string()))?;
+            Self::parse_csv(&csv)
+        }
+        .map_err(|error| match error {
+            Error::InvalidPartitionTable(error) => invalid(error),
+            error => error,
+        })
+    }
+
+    /// Parses the CSV format of `gen_esp32part.py`
+    ///
+    /// Missing offsets follow the previous partition, aligned as its type requires.
+    pub fn parse_csv(csv: &str) -> Result<Self, Error> {
+        let mut partitions = Vec::new();
+        let mut next_offset = FIRST_OFFSET as u64;
+
+        for (number, line) in csv.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let partition = parse_line(line, next_offset).map_err(|error| {
+                Error::InvalidPartitionTable(format!("line {}: {}", number + 1, error))
+            })?;
+
+            next_offset = partition.end();
+            partitions.push(partition);
+        }
+
+        Ok(Self { partitions })
+    }
+
+    /// Decodes a binary table, the MD5 entry isn't checked
+    pub fn decode(bytes: &[u8]) -> Result<Self, Error> {
+        let mut partitions = Vec::new();
+
+        for (index, entry) in bytes.chunks(ENTRY_LEN).enumerate() {
+            let invalid =
+                |error: &str| Error::InvalidPartitionTable(format!("entry {}: {}", index, error));
+
+            if entry.iter().all(|&byte| byte == 0xff) {
+                return Ok(Self { partitions });
+            }
+
+            if entry.len() < ENTRY_LEN {
+                return Err(invalid("truncated"));
+            }
+
+            match entry[..2].try_into().unwrap() {
+                ENTRY_MAGIC => {}
+                MD5_MAGIC => continue,
+                _ => return Err(invalid("invalid magic")),
+            }
+
+            let name = &entry[12..28];
+            let name_len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+            let flags = u32::from_le_bytes(entry[28..32].try_into().unwrap());
+
+            partitions.push(Partition {
+                name: String::from_utf8_lossy(&name[..name_len]).into_owned(),
+                ty: PartitionType::from_u8(entry[2]),
+                subtype: entry[3],
+                offset: u32::from_le_bytes(entry[4..8].try_into().unwrap()),
+                size: u32::from_le_bytes(entry[8..12].try_into().unwrap()),
+                encrypted: flags & 0x1 != 0,
+                readonly: flags & 0x2 != 0,
+            });
+        }
+
+        // Table filling the whole sector has no end marker
+        Ok(Self { partitions })
+    }
+
+    /// `ota_<index>` app partitions, in table order
+    pub fn ota_slots(&self) -> impl Iterator<Item = &Partition> {
+        self.partitions
+            .iter()
+            .filter(|partition| partition.ota_index().is_some())
+    }
+
+    /// Largest image an update is accepted for: the size of the smallest OTA slot
+    pub fn max_ota_size(&self) -> Option<u32> {
+        self.ota_slots().map(|slot| slot.size).min()
+    }
+
+    /// Problems preventing OTA updates with this table, empty if there are none
+    pub fn validate(&self) -> Vec<TableIssue> {
+        let mut issues = Vec::new();
+
+        for (i, partition) in self.partitions.iter().enumerate() {
+            if self.partitions[..i]
+                .iter()
+                .any(|other| other.name == partition.name)
+            {
+                issues.push(TableIssue::DuplicateName(partition.name.clone()));
+            }
+
+            let align = partition.ty.alignment();
+            if partition.offset % align != 0 {
+                issues.push(TableIssue::Misaligned {
+                    name: partition.name.clone(),
+                    offset: partition.offset,
+                    align,
+                });
+            }
+
+            for other in &self.partitions[i + 1..] {
+                if (partition.offset as u64) < other.end()
+                    && (other.offset as u64) < partition.end()
+                {
+                    issues.push(TableIssue::Overlap {
+                        first: partition.name.clone(),
+                        second: other.name.clone(),
+                    });
+                }
+            }
+        }
+
+        let slots: Vec<_> = self.ota_slots().collect();
+        match slots.as_slice() {
+            [] => issues.push(TableIssue::NoOtaSlots),
+            [slot] => issues.push(TableIssue::SingleOtaSlot(slot.name.clone())),
+            [first, rest @ ..] => {
+                for slot in rest.iter().filter(|slot| slot.size != first.size) {
+                    issues.push(TableIssue::OtaSizeMismatch {
+                        first: first.name.clone(),
+                        first_size: first.size,
+                        second: slot.name.clone(),
+                        second_size: slot.size,
+                    });
+                }
+            }
+        }
+
+        match self
+            .partitions
+            .iter()
+            .filter(|partition| partition.is_otadata())
+            .count()
+        {
+            0 => issues.push(TableIssue::MissingOtadata),
+            1 => {}
+            _ => issues.push(TableIssue::MultipleOtadata),
+        }
+
+        issues
+    }
+
+    /// Fails if an image of `len` bytes doesn't fit into the smallest OTA slot
+    pub fn check_image(&self, len: usize) -> Result<(), Error> {
+        let max_ota_size = self
+            .max_ota_size()
+            .ok_or_else(|| Error::InvalidPartitionTable("table has no OTA slots".to_string()))?;
+
+        if len > max_ota_size as usize {
+            return Err(Error::InvalidImage(format!(
+                "image of {} bytes exceeds the smallest OTA slot of {} bytes",
+                len, max_ota_size
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// Problems of a partition table found by `PartitionTable::validate`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TableIssue {
+    /// Two partitions have the same name
+    DuplicateName(String),
+    /// Partition doesn't start at a multiple of `align`
+    Misaligned {
+        name: String,
+        offset: u32,
+        align: u32,
+    },
+    /// Two partitions share flash
+    Overlap { first: String, second: String },
+    /// There is no `ota_<index>` app partition, the device can't be updated
+    NoOtaSlots,
+    /// Only one OTA slot, the running image would be overwritten by an update
+    SingleOtaSlot(String),
+    /// OTA slots differ in size, images have to fit the smallest one
+    OtaSizeMismatch {
+        first: String,
+        first_size: u32,
+        second: String,
+        second_size: u32,
+    },
+    /// No `data, ota` partition, the bootloader can't switch slots
+    MissingOtadata,
+    /// More than one `data, ota` partition
+    MultipleOtadata,
+}
+
+impl fmt::Display for TableIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DuplicateName(name) => write!(f, "Partition name {} is used twice", name),
+            Self::Misaligned {
+                name,
+                offset,
+                align,
+            } => write!(
+                f,
+                "Partition {} at {:#x} is not aligned to {:#x}",
+                name, offset, align
+            ),
+            Self::Overlap { first, second } => {
+                write!(f, "Partitions {} and {} overlap", first, second)
+            }
+            Self::NoOtaSlots => write!(f, "No OTA slots"),
+            Self::SingleOtaSlot(name) => write!(f, "{} is the only OTA slot", name),
+            Self::OtaSizeMismatch {
+                first,
+                first_size,
+                second,
+                second_size,
+            } => write!(
+                f,
+                "OTA slots {} ({:#x}) and {} ({:#x}) differ in size",
+                first, first_size, second, second_size
+            ),
+            Self::MissingOtadata => write!(f, "No otadata partition"),
+            Self::MultipleOtadata => write!(f, "More than one otadata partition"),
+        }
+    }
+}
+
+/// Parses `name, type, subtype, offset, size[, flags]`, an empty offset is `next_offset` aligned
+fn parse_line(line: &str, next_offset: u64) -> Result<Partition, String> {
+    let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+
+    let [name, ty, subtype, offset, size, rest @ ..] = fields.as_slice() else {
+        return Err(format!("expected at least 5 fields, got {}", fields.len()));
+    };
+
+    if name.is_empty() || name.len() > 15 {
+        return Err(format!("invalid name {:?}", name));
+    }
+
+    let ty = PartitionType::parse(ty)?;
+    let subtype = ty.parse_subtype(subtype)?;
+
+    let offset = if offset.is_empty() {
+        let align = ty.alignment() as u64;
+        u32::try_from(next_offset.div_ceil(align) * align)
+            .map_err(|_| "partition doesn't fit into flash".to_string())?
+    } else {
+        parse_size(offset).ok_or_else(|| format!("invalid offset {:?}", offset))?
+    };
+
+    let size = parse_size(size)
+        .filter(|size| *size > 0)
+        .ok_or_else(|| format!("invalid size {:?}", size))?;
+
+    let mut partition = Partition {
+        name: name.to_string(),
+        ty,
+        subtype,
+        offset,
+        size,
+        encrypted: false,
+        readonly: false,
+    };
+
+    for flag in rest
+        .iter()
+        .flat_map(|flags| flags.split(':'))
+        .map(str::trim)
+        .filter(|flag| !flag.is_empty())
+    {
+        match flag {
+            "encrypted" => partition.encrypted = true,
+            "readonly" => partition.readonly = true,
+            flag => return Err(format!("unknown flag {:?}", flag)),
+        }
+    }
+
+    Ok(partition)
+}
+
+/// Hex (`0x`) or decimal number, optionally followed by `K` or `M`
+fn parse_size(value: &str) -> Option<u32> {
+    let (value, multiplier) = match value.chars().last()? {
+        'k' | 'K' => (&value[..value.len() - 1], 1024),
+        'm' | 'M' => (&value[..value.len() - 1], 1024 * 1024),
+        _ => (value, 1),
+    };
+
+    let value = match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+        None => value.parse().ok()?,
+    };
+
+    value.checked_mul(multiplier)
+}
+
+fn parse_u8(value: &str) -> Option<u8> {
+    parse_size(value)?.try_into().ok()
+}
@@ -0,0 +1,130 @@
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use ed25519_dalek::{Signer, SigningKey};
+use ota_protocol::{
+    hash::ImageHash,
+    signature::{ImageSignature, PublicKey},
+};
+use rand_core::OsRng;
+
+use crate::Error;
+
+/// Permissions of private keys on Unix, only readable and writable by their owner
+#[cfg(unix)]
+const PRIVATE_KEY_MODE: u32 = 0o600;
+
+/// Generates a new key pair from the OS random number generator
+pub fn generate_key() -> SigningKey {
+    SigningKey::generate(&mut OsRng)
+}
+
+/// Public half of `key`, to be passed to `BleParams` of the firmware
+pub fn public_key(key: &SigningKey) -> PublicKey {
+    PublicKey(key.verifying_key().to_bytes())
+}
+
+/// Detached signature of `image`, made over its `ImageHash`
+pub fn sign(key: &SigningKey, image: &[u8]) -> ImageSignature {
+    ImageSignature(key.sign(&ImageHash::of(image).0).to_bytes())
+}
+
+/// Path the public key of the private key stored at `path` is written to: `<path>.pub`
+pub fn public_key_path(path: &Path) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(".pub");
+    path.into()
+}
+
+/// Writes the raw 32-byte private key to `path` and its public key next to it
+///
+/// The private key is only readable by its owner on Unix, it's removed again if the public key
+/// can't be written. Existing keys are only replaced with `overwrite`, a lost private key can't
+/// sign updates for devices trusting it anymore.
+pub fn write_key(key: &SigningKey, path: &Path, overwrite: bool) -> Result<(), Error> {
+    let public_path = public_key_path(path);
+
+    if !overwrite {
+        if let Some(existing) = [path, &public_path].into_iter().find(|path| path.exists()) {
+            return Err(Error::InvalidKey(format!(
+                "{} already exists",
+                existing.display()
+            )));
+        }
+    }
+
+    write_file(path, &key.to_bytes(), overwrite, true)?;
+
+    let written = write_file(&public_path, &public_key(key).0, overwrite, false);
+
+    // A private key without its public key is never left behind
+    if written.is_err() {
+        let _ = std::fs::remove_file(path);
+    }
+
+    written
+}
+
+/// Writes `bytes` to `path`, a `private` file is only readable by its owner on Unix
+fn write_file(path: &Path, bytes: &[u8], overwrite: bool, private: bool) -> Result<(), Error> {
+    let error = |error: std::io::Error| Error::InvalidKey(format!("{}: {}", path.display(), error));
+
+    let mut options = OpenOptions::new();
+    options.write(true);
+    if overwrite {
+        options.create(true).truncate(true);
+    } else {
+        options.create_new(true);
+    }
+
+    #[cfg(unix)]
+    if private {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        options.mode(PRIVATE_KEY_MODE);
+    }
+
+    let mut file = options.open(path).map_err(error)?;
+
+    // The mode only applies to files created by `open`, not to a replaced one
+    #[cfg(unix)]
+    if private {
+        use std::os::unix::fs::PermissionsExt;
+
+        file.set_permissions(std::fs::Permissions::from_mode(PRIVATE_KEY_MODE))
+            .map_err(error)?;
+    }
+    #[cfg(not(unix))]
+    let _ = private;
+
+    file.write_all(bytes).map_err(error)
+}
+
+/// Reads a raw 32-byte private key written by `write_key`
+pub fn read_key(path: &Path) -> Result<SigningKey, Error> {
+    let bytes = std::fs::read(path)
+        .map_err(|error| Error::InvalidKey(format!("{}: {}", path.display(), error)))?;
+
+    let bytes = bytes.try_into().map_err(|bytes: Vec<u8>| {
+        Error::InvalidKey(format!(
+            "{}: expected {} bytes, got {}",
+            path.display(),
+            ed25519_dalek::SECRET_KEY_LENGTH,
+            bytes.len()
+        ))
+    })?;
+
+    Ok(SigningKey::from_bytes(&bytes))
+}
+
+/// Reads a raw 64-byte signature written by the `sign` subcommand
+pub fn read_signature(path: &Path) -> Result<ImageSignature, Error> {
+    let bytes = std::fs::read(path)
+        .map_err(|error| Error::InvalidKey(format!("{}: {}", path.display(), error)))?;
+
+    ImageSignature::decode(&bytes)
+        .map_err(|error| Error::InvalidKey(format!("{}: {}", path.display(), error)))
+}
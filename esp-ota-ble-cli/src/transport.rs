@@ -0,0 +1,112 @@
+use std::fmt;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use ota_protocol::uuids;
+use uuid::Uuid;
+
+pub const SERVICE_UUID: Uuid = Uuid::from_u128(uuids::SERVICE);
+
+/// Characteristics of the OTA service
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OtaCharacteristic {
+    FileBlock,
+    TotalFileSize,
+    FileHash,
+    Status,
+    Command,
+    FinishedUpload,
+    FileSignature,
+    SlotStates,
+    FirmwareInfo,
+    LinkInfo,
+    StreamBlock,
+    StreamAck,
+}
+
+impl OtaCharacteristic {
+    pub const ALL: [Self; 12] = [
+        Self::FileBlock,
+        Self::TotalFileSize,
+        Self::FileHash,
+        Self::Status,
+        Self::Command,
+        Self::FinishedUpload,
+        Self::FileSignature,
+        Self::SlotStates,
+        Self::FirmwareInfo,
+        Self::LinkInfo,
+        Self::StreamBlock,
+        Self::StreamAck,
+    ];
+
+    /// Exposed by every version of the service, the others were added later and are only looked
+    /// up when used
+    pub const REQUIRED: [Self; 6] = [
+        Self::FileBlock,
+        Self::TotalFileSize,
+        Self::FileHash,
+        Self::Status,
+        Self::Command,
+        Self::FinishedUpload,
+    ];
+
+    pub fn uuid(&self) -> Uuid {
+        Uuid::from_u128(match self {
+            Self::FileBlock => uuids::FILE_BLOCK,
+            Self::TotalFileSize => uuids::TOTAL_FILE_SIZE,
+            Self::FileHash => uuids::FILE_HASH,
+            Self::Status => uuids::STATUS,
+            Self::Command => uuids::COMMAND,
+            Self::FinishedUpload => uuids::FINISHED_UPLOAD,
+            Self::FileSignature => uuids::FILE_SIGNATURE,
+            Self::SlotStates => uuids::SLOT_STATES,
+            Self::FirmwareInfo => uuids::FIRMWARE_INFO,
+            Self::LinkInfo => uuids::LINK_INFO,
+            Self::StreamBlock => uuids::STREAM_BLOCK,
+            Self::StreamAck => uuids::STREAM_ACK,
+        })
+    }
+
+    pub fn from_uuid(uuid: Uuid) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| c.uuid() == uuid)
+    }
+}
+
+/// Error of an `OtaTransport` used with a characteristic the device doesn't expose, e.g. because
+/// it runs an older firmware
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MissingCharacteristic(pub OtaCharacteristic);
+
+impl fmt::Display for MissingCharacteristic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Device doesn't expose {:?} characteristic", self.0)
+    }
+}
+
+impl std::error::Error for MissingCharacteristic {}
+
+/// Connection to a device exposing the OTA service
+///
+/// Implemented over BLE by `ble::BleTransport` and by simulated devices in tests.
+#[async_trait]
+pub trait OtaTransport: Send {
+    /// Reads the current value of `characteristic`
+    async fn read(&mut self, characteristic: OtaCharacteristic) -> Result<Vec<u8>>;
+
+    /// Writes `value` with response, failing if the device rejects it
+    async fn write(&mut self, characteristic: OtaCharacteristic, value: &[u8]) -> Result<()>;
+
+    /// Writes `value` without response, it may be dropped without the write failing
+    async fn write_without_response(
+        &mut self,
+        characteristic: OtaCharacteristic,
+        value: &[u8],
+    ) -> Result<()>;
+
+    /// Enables notifications of `characteristic`
+    async fn subscribe(&mut self, characteristic: OtaCharacteristic) -> Result<()>;
+
+    /// Waits for the next notification of any subscribed characteristic
+    async fn next_notification(&mut self) -> Result<(OtaCharacteristic, Vec<u8>)>;
+}
@@ -0,0 +1,358 @@
+use std::time::Duration;
+
+use ota_protocol::{
+    block::Block,
+    command::Command,
+    hash::ImageHash,
+    info::FirmwareInfo,
+    link::{LinkInfo, ATT_WRITE_HEADER_LEN, DEFAULT_MTU},
+    signature::ImageSignature,
+    size::TotalFileSize,
+    slot::SlotStates,
+    status::{FinishedUpload, StatusReport},
+    stream::{StreamAck, StreamBlock, StreamSender},
+};
+
+use crate::{
+    transport::{OtaCharacteristic, OtaTransport},
+    Error,
+};
+
+pub struct UploadOptions {
+    /// Size of a single `file_block` write, header included, `None` fills the ATT MTU
+    /// negotiated with the device (see `LinkInfo::block_size`)
+    pub block_size: Option<usize>,
+    /// Abort a transfer already in progress on the device instead of failing, for signed images
+    /// also skip the device's anti-downgrade policy
+    pub force: bool,
+    /// How long to wait for `finished_upload` after the last block
+    pub finish_timeout: Duration,
+    /// Signature of the image, required by devices configured with a public key
+    pub signature: Option<ImageSignature>,
+    /// Stream blocks with writes without response, acknowledged by the device every few
+    /// blocks, instead of waiting for the response to every block
+    pub streaming: bool,
+    /// How long to wait for an ack while streaming before retransmitting
+    pub ack_timeout: Duration,
+}
+
+impl Default for UploadOptions {
+    fn default() -> Self {
+        Self {
+            block_size: None,
+            force: false,
+            finish_timeout: Duration::from_secs(30),
+            signature: None,
+            streaming: false,
+            ack_timeout: Duration::from_millis(500),
+        }
+    }
+}
+
+/// Uploads `image` and waits until the device reports it was activated
+///
+/// If the device is already receiving the same image (e.g. the link dropped during a previous
+/// upload), the transfer is resumed from the offset it reports, unless `force` is set.
+/// `progress` is called with `(sent, total)` bytes after every block, or every ack while
+/// streaming.
+pub async fn upload<T: OtaTransport>(
+    transport: &mut T,
+    image: &[u8],
+    options: &UploadOptions,
+    mut progress: impl FnMut(u32, u32) + Send,
+) -> Result<(), Error> {
+    let size = u32::try_from(image.len())
+        .ok()
+        .filter(|size| *size > 0)
+        .ok_or_else(|| Error::InvalidImage(format!("unsupported size {}", image.len())))?;
+
+    let block_size = match options.block_size {
+        Some(block_size) => block_size,
+        None => match link_info(transport).await {
+            Ok(link) => link.block_size(),
+            // Firmware predating `link_info`, only the default MTU is certain
+            Err(Error::Unsupported(_)) => {
+                log::warn!("Device doesn't report its MTU, set --block-size for faster uploads");
+                (DEFAULT_MTU - ATT_WRITE_HEADER_LEN) as usize
+            }
+            Err(error) => return Err(error),
+        },
+    };
+
+    let data_len = Block::max_data_len(block_size);
+    if data_len == 0 {
+        return Err(Error::InvalidImage(format!(
+            "block size {} leaves no room for data",
+            block_size
+        )));
+    }
+    log::info!("Writing blocks of {} bytes", block_size);
+
+    let hash = ImageHash::of(image);
+
+    transport.subscribe(OtaCharacteristic::Status).await?;
+    transport
+        .subscribe(OtaCharacteristic::FinishedUpload)
+        .await?;
+    if options.streaming {
+        transport.subscribe(OtaCharacteristic::StreamAck).await?;
+    }
+
+    request(
+        transport,
+        OtaCharacteristic::TotalFileSize,
+        &TotalFileSize(size).encode(),
+    )
+    .await?;
+    request(transport, OtaCharacteristic::FileHash, &hash.encode()).await?;
+    if let Some(signature) = &options.signature {
+        request(
+            transport,
+            OtaCharacteristic::FileSignature,
+            &signature.encode(),
+        )
+        .await?;
+    }
+
+    let command = if options.force {
+        Command::StartForceTransfer
+    } else {
+        Command::StartTransfer
+    };
+    log::info!("Starting transfer of {} bytes", size);
+    request(transport, OtaCharacteristic::Command, &command.encode()).await?;
+
+    let report = read_status(transport).await?;
+    let start = report.offset.min(size);
+    if start > 0 {
+        log::info!("Resuming transfer {} at {} bytes", report.session_id, start);
+        progress(start, size);
+    }
+
+    if options.streaming {
+        if stream(transport, image, start, block_size, options, &mut progress).await? {
+            return Ok(());
+        }
+
+        return wait_for_finish(transport, options.finish_timeout).await;
+    }
+
+    let mut buf = vec![0; block_size];
+    for (index, chunk) in image[start as usize..].chunks(data_len).enumerate() {
+        let offset = start + (index * data_len) as u32;
+        let len = Block::new(offset, chunk)
+            .encode_into(&mut buf)
+            .expect("Block fits the buffer");
+
+        request(transport, OtaCharacteristic::FileBlock, &buf[..len]).await?;
+
+        progress(offset + chunk.len() as u32, size);
+    }
+
+    wait_for_finish(transport, options.finish_timeout).await
+}
+
+/// Streams `image` from `start` until the device acknowledged every block, retransmitting the
+/// ones it reports missing
+///
+/// Returns whether the device already reported the end of the upload.
+async fn stream<T: OtaTransport>(
+    transport: &mut T,
+    image: &[u8],
+    start: u32,
+    block_size: usize,
+    options: &UploadOptions,
+    progress: &mut (impl FnMut(u32, u32) + Send),
+) -> Result<bool, Error> {
+    let size = image.len() as u32;
+    let data_len = StreamBlock::max_data_len(block_size);
+    let blocks = image[start as usize..].chunks(data_len).collect::<Vec<_>>();
+
+    // Set by the device on `StartTransfer`, it tells the window
+    let ack = read_stream_ack(transport).await?;
+    let mut sender = StreamSender::new(blocks.len() as u32, ack.window);
+    log::info!(
+        "Streaming {} blocks, window of {}",
+        blocks.len(),
+        ack.window
+    );
+
+    let mut buf = vec![0; block_size];
+    while !sender.is_done() {
+        while let Some(seq) = sender.next_block() {
+            let len = StreamBlock::new(seq, blocks[seq as usize])
+                .encode_into(&mut buf)
+                .expect("Block fits the buffer");
+
+            transport
+                .write_without_response(OtaCharacteristic::StreamBlock, &buf[..len])
+                .await?;
+        }
+
+        let notification =
+            tokio::time::timeout(options.ack_timeout, transport.next_notification()).await;
+        let Ok(notification) = notification else {
+            log::debug!("No ack after block {}, retransmitting", sender.acked());
+            sender.on_timeout();
+            continue;
+        };
+
+        let (characteristic, value) = notification?;
+        match characteristic {
+            OtaCharacteristic::StreamAck => {
+                let ack = StreamAck::decode(&value).map_err(|error| {
+                    Error::Transport(anyhow::anyhow!("Invalid stream ack: {}", error))
+                })?;
+                if ack.is_nak() {
+                    log::debug!("Device misses {:?}", ack.missing().collect::<Vec<_>>());
+                }
+
+                sender.on_ack(&ack);
+
+                let sent = start as usize + sender.acked() as usize * data_len;
+                progress(sent.min(image.len()) as u32, size);
+            }
+            OtaCharacteristic::Status => match StatusReport::decode(&value) {
+                Ok(report) if report.status.is_error() => return Err(Error::from_report(&report)),
+                _ => {}
+            },
+            OtaCharacteristic::FinishedUpload
+                if FinishedUpload::decode(&value) == Ok(FinishedUpload(true)) =>
+            {
+                return Ok(true)
+            }
+            _ => {}
+        }
+    }
+
+    Ok(false)
+}
+
+/// Asks the device to reboot, e.g. into the uploaded image
+pub async fn reset<T: OtaTransport>(transport: &mut T) -> Result<(), Error> {
+    request(
+        transport,
+        OtaCharacteristic::Command,
+        &Command::ResetDevice.encode(),
+    )
+    .await
+}
+
+async fn read_status<T: OtaTransport>(transport: &mut T) -> Result<StatusReport, Error> {
+    let value = transport.read(OtaCharacteristic::Status).await?;
+
+    StatusReport::decode(&value)
+        .map_err(|error| Error::Transport(anyhow::anyhow!("Invalid status: {}", error)))
+}
+
+/// Reads the slot states, telling whether the last update was confirmed or rolled back
+pub async fn slot_states<T: OtaTransport>(transport: &mut T) -> Result<SlotStates, Error> {
+    let value = transport.read(OtaCharacteristic::SlotStates).await?;
+
+    SlotStates::decode(&value)
+        .map_err(|error| Error::Transport(anyhow::anyhow!("Invalid slot states: {}", error)))
+}
+
+/// Reads what the device is running and the largest image it accepts
+pub async fn firmware_info<T: OtaTransport>(transport: &mut T) -> Result<FirmwareInfo, Error> {
+    let value = transport.read(OtaCharacteristic::FirmwareInfo).await?;
+
+    FirmwareInfo::decode(&value)
+        .map_err(|error| Error::Transport(anyhow::anyhow!("Invalid firmware info: {}", error)))
+}
+
+async fn read_stream_ack<T: OtaTransport>(transport: &mut T) -> Result<StreamAck, Error> {
+    let value = transport.read(OtaCharacteristic::StreamAck).await?;
+
+    StreamAck::decode(&value)
+        .map_err(|error| Error::Transport(anyhow::anyhow!("Invalid stream ack: {}", error)))
+}
+
+/// Reads the ATT MTU negotiated with the device and the longest block it accepts
+pub async fn link_info<T: OtaTransport>(transport: &mut T) -> Result<LinkInfo, Error> {
+    let value = transport.read(OtaCharacteristic::LinkInfo).await?;
+
+    LinkInfo::decode(&value)
+        .map_err(|error| Error::Transport(anyhow::anyhow!("Invalid link info: {}", error)))
+}
+
+/// How long a rejected write waits for the status notification the device sends along with it
+const REJECTION_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Writes `value`, turning a rejected write into the status reported by the device
+async fn request<T: OtaTransport>(
+    transport: &mut T,
+    characteristic: OtaCharacteristic,
+    value: &[u8],
+) -> Result<(), Error> {
+    let Err(error) = transport.write(characteristic, value).await else {
+        return Ok(());
+    };
+
+    let error = Error::from(error);
+    if let Error::Unsupported(_) = error {
+        return Err(error);
+    }
+
+    // A client refused because another one owns the transfer is only told by a notification,
+    // `status` keeps reporting the owner's transfer
+    if let Some(report) = notified_error(transport).await {
+        return Err(Error::from_report(&report));
+    }
+
+    match read_status(transport).await {
+        Ok(report) if report.status.is_error() => Err(Error::from_report(&report)),
+        _ => Err(error),
+    }
+}
+
+/// Error the device notified through `status`, if one arrives within `REJECTION_TIMEOUT`
+async fn notified_error<T: OtaTransport>(transport: &mut T) -> Option<StatusReport> {
+    let wait = async {
+        loop {
+            let (characteristic, value) = transport.next_notification().await.ok()?;
+
+            if characteristic == OtaCharacteristic::Status {
+                match StatusReport::decode(&value) {
+                    Ok(report) if report.status.is_error() => return Some(report),
+                    _ => {}
+                }
+            }
+        }
+    };
+
+    tokio::time::timeout(REJECTION_TIMEOUT, wait)
+        .await
+        .ok()
+        .flatten()
+}
+
+async fn wait_for_finish<T: OtaTransport>(
+    transport: &mut T,
+    timeout: Duration,
+) -> Result<(), Error> {
+    let wait = async {
+        loop {
+            let (characteristic, value) = transport.next_notification().await?;
+
+            match characteristic {
+                OtaCharacteristic::FinishedUpload
+                    if FinishedUpload::decode(&value) == Ok(FinishedUpload(true)) =>
+                {
+                    return Ok(())
+                }
+                OtaCharacteristic::Status => match StatusReport::decode(&value) {
+                    Ok(report) if report.status.is_error() => {
+                        return Err(Error::from_report(&report))
+                    }
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+    };
+
+    tokio::time::timeout(timeout, wait)
+        .await
+        .map_err(|_| Error::Timeout)?
+}
@@ -0,0 +1,221 @@
+use std::path::Path;
+
+use esp_ota_ble_cli::{
+    partitions::{Partition, PartitionTable, PartitionType, TableIssue},
+    Error,
+};
+
+const WROOM_1_N8R8: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/../partitions/esp32-s3-wroom-1-n8r8.csv"
+);
+
+/// Binary entry as generated by `gen_esp32part.py`
+fn entry(name: &str, ty: u8, subtype: u8, offset: u32, size: u32, flags: u32) -> Vec<u8> {
+    let mut entry = vec![0xaa, 0x50, ty, subtype];
+    entry.extend_from_slice(&offset.to_le_bytes());
+    entry.extend_from_slice(&size.to_le_bytes());
+
+    let mut label = [0; 16];
+    label[..name.len()].copy_from_slice(name.as_bytes());
+    entry.extend_from_slice(&label);
+    entry.extend_from_slice(&flags.to_le_bytes());
+
+    entry
+}
+
+fn names(table: &PartitionTable) -> Vec<&str> {
+    table
+        .partitions
+        .iter()
+        .map(|partition| partition.name.as_str())
+        .collect()
+}
+
+#[test]
+fn shipped_table_is_valid() {
+    let table = PartitionTable::read(Path::new(WROOM_1_N8R8)).unwrap();
+
+    assert_eq!(
+        names(&table),
+        ["nvs", "otadata", "phy_init", "ota_0", "ota_1"]
+    );
+    assert_eq!(
+        table.partitions[3],
+        Partition {
+            name: "ota_0".to_string(),
+            ty: PartitionType::App,
+            subtype: 0x10,
+            offset: 0x30000,
+            size: 0x1df000,
+            encrypted: false,
+            readonly: false,
+        }
+    );
+    assert_eq!(table.validate(), []);
+    assert_eq!(table.max_ota_size(), Some(0x1df000));
+}
+
+#[test]
+fn binary_table_matches_csv() {
+    let mut bytes = [
+        entry("nvs", 0x01, 0x02, 0x9000, 0x24000, 0),
+        entry("otadata", 0x01, 0x00, 0x2d000, 0x2000, 0),
+        entry("phy_init", 0x01, 0x01, 0x2f000, 0x1000, 0),
+        entry("ota_0", 0x00, 0x10, 0x30000, 0x1df000, 0),
+        entry("ota_1", 0x00, 0x11, 0x210000, 0x1df000, 0),
+    ]
+    .concat();
+
+    // MD5 entry, then the rest of the sector is erased
+    bytes.extend_from_slice(&[0xeb, 0xeb]);
+    bytes.extend_from_slice(&[0xff; 14]);
+    bytes.extend_from_slice(&[0x5a; 16]);
+    bytes.extend_from_slice(&[0xff; 64]);
+
+    assert_eq!(
+        PartitionTable::decode(&bytes).unwrap(),
+        PartitionTable::read(Path::new(WROOM_1_N8R8)).unwrap()
+    );
+}
+
+#[test]
+fn binary_table_flags() {
+    let bytes = entry("nvs_keys", 0x01, 0x04, 0x9000, 0x1000, 0x3);
+    let table = PartitionTable::decode(&bytes).unwrap();
+
+    assert!(table.partitions[0].encrypted);
+    assert!(table.partitions[0].readonly);
+
+    assert!(matches!(
+        PartitionTable::decode(&bytes[..20]),
+        Err(Error::InvalidPartitionTable(_))
+    ));
+    assert!(matches!(
+        PartitionTable::decode(&[0x12; 32]),
+        Err(Error::InvalidPartitionTable(_))
+    ));
+}
+
+#[test]
+fn missing_offsets_follow_previous_partition() {
+    let table = PartitionTable::parse_csv(
+        "# Name, Type, SubType, Offset, Size, Flags
+        nvs,      data, nvs,     ,        0x6000,
+        otadata,  data, ota,     ,        8K,
+        phy_init, data, phy,     ,        0x1000,
+        factory,  app,  factory, ,        1M,
+        ota_0,    app,  ota_0,   ,        1M,
+        ota_1,    app,  ota_1,   ,        1M,
+        storage,  data, 0x99,    ,        64K,   encrypted:readonly",
+    )
+    .unwrap();
+
+    let offsets: Vec<u32> = table
+        .partitions
+        .iter()
+        .map(|partition| partition.offset)
+        .collect();
+
+    assert_eq!(
+        offsets,
+        [0x9000, 0xf000, 0x11000, 0x20000, 0x120000, 0x220000, 0x320000]
+    );
+    assert_eq!(table.partitions[6].subtype, 0x99);
+    assert!(table.partitions[6].encrypted && table.partitions[6].readonly);
+
+    // Factory app is not an OTA slot
+    assert_eq!(
+        table
+            .ota_slots()
+            .map(|slot| slot.name.as_str())
+            .collect::<Vec<_>>(),
+        ["ota_0", "ota_1"]
+    );
+    assert_eq!(table.validate(), []);
+}
+
+#[test]
+fn reports_invalid_lines() {
+    let error = |csv| match PartitionTable::parse_csv(csv) {
+        Err(Error::InvalidPartitionTable(error)) => error,
+        result => panic!("unexpected result: {:?}", result),
+    };
+
+    assert_eq!(
+        error("nvs, data, nvs, 0x9000, 0x6000\nota_0, app, ota_16, 0x10000, 1M"),
+        "line 2: unknown subtype \"ota_16\""
+    );
+    assert_eq!(
+        error("nvs, data, nvs, 0x9000"),
+        "line 1: expected at least 5 fields, got 4"
+    );
+    assert_eq!(
+        error("nvs, data, nvs, 0x9000, 0x6000, secret"),
+        "line 1: unknown flag \"secret\""
+    );
+}
+
+#[test]
+fn finds_inconsistent_ota_slots() {
+    let table = PartitionTable::parse_csv(
+        "nvs,     data, nvs,   0x9000,   0x6000
+        ota_0,    app,  ota_0, 0x10000,  0x100000
+        ota_1,    app,  ota_1, 0x118000, 0xc0000
+        spiffs,   data, spiffs, 0x1d0000, 0x10000",
+    )
+    .unwrap();
+
+    assert_eq!(
+        table.validate(),
+        [
+            TableIssue::Misaligned {
+                name: "ota_1".to_string(),
+                offset: 0x118000,
+                align: 0x10000,
+            },
+            TableIssue::Overlap {
+                first: "ota_1".to_string(),
+                second: "spiffs".to_string(),
+            },
+            TableIssue::OtaSizeMismatch {
+                first: "ota_0".to_string(),
+                first_size: 0x100000,
+                second: "ota_1".to_string(),
+                second_size: 0xc0000,
+            },
+            TableIssue::MissingOtadata,
+        ]
+    );
+    assert_eq!(table.max_ota_size(), Some(0xc0000));
+}
+
+#[test]
+fn table_without_ota_slots() {
+    let table = PartitionTable::parse_csv(
+        "nvs,     data, nvs,     0x9000,  0x6000
+        phy_init, data, phy,     0xf000,  0x1000
+        factory,  app,  factory, 0x10000, 1M",
+    )
+    .unwrap();
+
+    assert_eq!(
+        table.validate(),
+        [TableIssue::NoOtaSlots, TableIssue::MissingOtadata]
+    );
+    assert!(matches!(
+        table.check_image(1024),
+        Err(Error::InvalidPartitionTable(_))
+    ));
+}
+
+#[test]
+fn image_has_to_fit_smallest_slot() {
+    let table = PartitionTable::read(Path::new(WROOM_1_N8R8)).unwrap();
+
+    assert!(table.check_image(0x1df000).is_ok());
+    assert!(matches!(
+        table.check_image(0x1df001),
+        Err(Error::InvalidImage(_))
+    ));
+}
@@ -0,0 +1,86 @@
+use std::path::PathBuf;
+
+use esp_ota_ble_cli::{signing, Error};
+
+/// Empty directory for the keys of `test`
+fn key_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("esp-ota-ble-cli-{}-{}", std::process::id(), test));
+
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    dir
+}
+
+#[test]
+fn writes_key_pair() {
+    let path = key_dir("writes_key_pair").join("ota.key");
+    let key = signing::generate_key();
+
+    signing::write_key(&key, &path, false).unwrap();
+
+    assert_eq!(signing::read_key(&path).unwrap().to_bytes(), key.to_bytes());
+    assert_eq!(
+        std::fs::read(signing::public_key_path(&path)).unwrap(),
+        signing::public_key(&key).0
+    );
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
+
+#[test]
+fn existing_key_is_kept() {
+    let path = key_dir("existing_key_is_kept").join("ota.key");
+    let key = signing::generate_key();
+    signing::write_key(&key, &path, false).unwrap();
+
+    let result = signing::write_key(&signing::generate_key(), &path, false);
+
+    assert!(matches!(result, Err(Error::InvalidKey(_))));
+    assert_eq!(signing::read_key(&path).unwrap().to_bytes(), key.to_bytes());
+}
+
+#[test]
+fn existing_key_is_replaced_with_overwrite() {
+    let path = key_dir("existing_key_is_replaced_with_overwrite").join("ota.key");
+    signing::write_key(&signing::generate_key(), &path, false).unwrap();
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+    }
+
+    let key = signing::generate_key();
+    signing::write_key(&key, &path, true).unwrap();
+
+    assert_eq!(signing::read_key(&path).unwrap().to_bytes(), key.to_bytes());
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
+
+#[test]
+fn private_key_is_removed_if_public_key_fails() {
+    let path = key_dir("private_key_is_removed_if_public_key_fails").join("ota.key");
+
+    // Public key can't be written over a directory
+    std::fs::create_dir(signing::public_key_path(&path)).unwrap();
+
+    let result = signing::write_key(&signing::generate_key(), &path, true);
+
+    assert!(matches!(result, Err(Error::InvalidKey(_))));
+    assert!(!path.exists());
+}
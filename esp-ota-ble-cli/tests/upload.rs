@@ -0,0 +1,633 @@
+use std::{collections::VecDeque, time::Duration};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use esp_ota_ble_cli::{
+    signing,
+    transport::{MissingCharacteristic, OtaCharacteristic, OtaTransport},
+    upload::{self, upload, UploadOptions},
+    Error,
+};
+use ota_protocol::{
+    block::Block,
+    command::Command,
+    hash::ImageHash,
+    info::FirmwareInfo,
+    link::LinkInfo,
+    session::{BlockOutcome, ImageInfo, OtaSession, SessionError, SessionState},
+    signature::ImageSignature,
+    sink::MemorySink,
+    size::TotalFileSize,
+    slot::{BootOutcome, SlotState, SlotStates},
+    status::{FinishedUpload, Status, StatusReport},
+    stream::{StreamAck, StreamBlock, StreamReceiver},
+};
+
+/// Device answering OTA requests the way `esp-ota-ble` does, backed by an in-memory sink
+struct SimulatedDevice {
+    session: OtaSession<MemorySink>,
+    total_file_size: Option<u32>,
+    file_hash: Option<ImageHash>,
+    file_signature: Option<ImageSignature>,
+    status: Status,
+    finished: bool,
+    notifications: VecDeque<(OtaCharacteristic, Vec<u8>)>,
+    /// Number of `file_block` writes accepted before the link drops
+    link_budget: Option<usize>,
+    slot_states: SlotStates,
+    /// Answer of the application to `StartTransfer`, `Ok` allows the update
+    policy: Result<(), StatusReport>,
+    /// Another client owns the transfer, writes are refused with a `Busy` notification while
+    /// `status` keeps reporting the owner's transfer
+    owned_by_other: bool,
+    firmware_info: FirmwareInfo,
+    link_info: LinkInfo,
+    /// Longest `file_block` write received
+    largest_block: usize,
+    stream: StreamReceiver,
+    /// Value of `stream_ack`, the last ack notified
+    stream_ack: StreamAck,
+    /// Every `stream_block` write with this number is dropped by the link
+    stream_loss: Option<usize>,
+    stream_writes: usize,
+    /// Characteristics the firmware doesn't expose yet
+    missing: Vec<OtaCharacteristic>,
+}
+
+impl SimulatedDevice {
+    fn new(capacity: u32) -> Self {
+        Self {
+            session: OtaSession::new(MemorySink::new(capacity)),
+            total_file_size: None,
+            file_hash: None,
+            file_signature: None,
+            status: Status::Idle,
+            finished: false,
+            notifications: VecDeque::new(),
+            link_budget: None,
+            slot_states: SlotStates {
+                running: SlotState::Valid,
+                next: SlotState::Undefined,
+            },
+            policy: Ok(()),
+            owned_by_other: false,
+            firmware_info: firmware_info(capacity),
+            link_info: LinkInfo {
+                mtu: 247,
+                max_block_size: 512,
+            },
+            largest_block: 0,
+            stream: StreamReceiver::new(16, 8),
+            stream_ack: StreamAck::new(0, 16),
+            stream_loss: None,
+            stream_writes: 0,
+            missing: Vec::new(),
+        }
+    }
+
+    fn expose(&self, characteristic: OtaCharacteristic) -> Result<()> {
+        if self.missing.contains(&characteristic) {
+            return Err(MissingCharacteristic(characteristic).into());
+        }
+
+        Ok(())
+    }
+
+    fn set_status(&mut self, status: Status) {
+        self.status = status;
+        self.notifications.push_back((
+            OtaCharacteristic::Status,
+            self.session.report(status).encode().to_vec(),
+        ));
+    }
+
+    fn apply(&mut self, characteristic: OtaCharacteristic, value: &[u8]) -> Result<(), Status> {
+        match characteristic {
+            OtaCharacteristic::TotalFileSize => {
+                let TotalFileSize(size) =
+                    TotalFileSize::decode(value).map_err(|_| Status::InvalidLength)?;
+                self.total_file_size = Some(size);
+            }
+            OtaCharacteristic::FileHash => {
+                let hash = ImageHash::decode(value).map_err(|_| Status::InvalidLength)?;
+                self.file_hash = Some(hash);
+            }
+            OtaCharacteristic::FileSignature => {
+                let signature = ImageSignature::decode(value).map_err(|_| Status::InvalidLength)?;
+                self.file_signature = Some(signature);
+            }
+            OtaCharacteristic::Command => {
+                let command = Command::decode(value).map_err(|_| Status::UnknownCommand)?;
+                let image = ImageInfo {
+                    size: self.total_file_size.ok_or(Status::UnknownFileSize)?,
+                    hash: self.file_hash.ok_or(Status::UnknownFileHash)?,
+                    signature: self.file_signature,
+                };
+
+                match command {
+                    Command::StartTransfer | Command::StartForceTransfer => {
+                        if let Err(report) = self.policy {
+                            self.status = report.status;
+                            self.notifications
+                                .push_back((OtaCharacteristic::Status, report.encode().to_vec()));
+                            return Err(report.status);
+                        }
+
+                        let force = command == Command::StartForceTransfer;
+                        self.session.start(image, force).map_err(status)?;
+                        self.stream.reset();
+                        self.stream_ack = self.stream.ack();
+                        self.set_status(Status::Receiving);
+                    }
+                    Command::ClearTransfer => {
+                        self.session.clear().map_err(status)?;
+                        self.set_status(Status::Idle);
+                    }
+                    Command::ResetDevice => {}
+                }
+            }
+            OtaCharacteristic::FileBlock => {
+                self.largest_block = self.largest_block.max(value.len());
+
+                let block = Block::decode(value).map_err(|_| Status::InvalidLength)?;
+                let outcome = self
+                    .session
+                    .write_block(block.header.offset, block.data)
+                    .map_err(status)?;
+
+                if outcome == BlockOutcome::Complete {
+                    self.finish()?;
+                }
+            }
+            OtaCharacteristic::StreamBlock => {
+                let block = StreamBlock::decode(value).map_err(|_| Status::InvalidLength)?;
+                let session = &mut self.session;
+
+                let ack = self.stream.receive(block.header.seq, block.data, |data| {
+                    session
+                        .write_block(session.state().received(), data)
+                        .map(|_| ())
+                        .map_err(status)
+                })?;
+
+                let complete = matches!(self.session.state(), SessionState::Verifying { .. });
+                if let Some(ack) = ack.or_else(|| complete.then(|| self.stream.ack())) {
+                    self.stream_ack = ack;
+                    self.notifications
+                        .push_back((OtaCharacteristic::StreamAck, ack.encode().to_vec()));
+                }
+
+                if complete {
+                    self.finish()?;
+                }
+            }
+            OtaCharacteristic::Status
+            | OtaCharacteristic::FinishedUpload
+            | OtaCharacteristic::SlotStates
+            | OtaCharacteristic::FirmwareInfo
+            | OtaCharacteristic::LinkInfo
+            | OtaCharacteristic::StreamAck => return Err(Status::InvalidLength),
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Status> {
+        self.session.finish().map_err(status)?;
+        self.finished = true;
+        self.set_status(Status::Finished);
+        self.notifications.push_back((
+            OtaCharacteristic::FinishedUpload,
+            FinishedUpload(true).encode().to_vec(),
+        ));
+
+        Ok(())
+    }
+}
+
+fn status<E>(error: SessionError<E>) -> Status {
+    error.status()
+}
+
+#[async_trait]
+impl OtaTransport for SimulatedDevice {
+    async fn read(&mut self, characteristic: OtaCharacteristic) -> Result<Vec<u8>> {
+        self.expose(characteristic)?;
+
+        match characteristic {
+            OtaCharacteristic::Status => match self.policy {
+                Err(report) if report.status == self.status => Ok(report.encode().to_vec()),
+                _ => Ok(self.session.report(self.status).encode().to_vec()),
+            },
+            OtaCharacteristic::FinishedUpload => {
+                Ok(FinishedUpload(self.finished).encode().to_vec())
+            }
+            OtaCharacteristic::SlotStates => Ok(self.slot_states.encode().to_vec()),
+            OtaCharacteristic::FirmwareInfo => Ok(self.firmware_info.encode().to_vec()),
+            OtaCharacteristic::LinkInfo => Ok(self.link_info.encode().to_vec()),
+            OtaCharacteristic::StreamAck => Ok(self.stream_ack.encode().to_vec()),
+            _ => Err(anyhow::anyhow!("{:?} is not readable", characteristic)),
+        }
+    }
+
+    async fn write(&mut self, characteristic: OtaCharacteristic, value: &[u8]) -> Result<()> {
+        self.expose(characteristic)?;
+
+        if self.owned_by_other {
+            self.notifications.push_back((
+                OtaCharacteristic::Status,
+                self.session.report(Status::Busy).encode().to_vec(),
+            ));
+            return Err(anyhow::anyhow!("Write rejected"));
+        }
+
+        if characteristic == OtaCharacteristic::FileBlock {
+            match &mut self.link_budget {
+                Some(0) => return Err(anyhow::anyhow!("Link lost")),
+                Some(budget) => *budget -= 1,
+                None => {}
+            }
+        }
+
+        self.apply(characteristic, value).map_err(|status| {
+            self.set_status(status);
+            anyhow::anyhow!("Write rejected")
+        })
+    }
+
+    async fn write_without_response(
+        &mut self,
+        characteristic: OtaCharacteristic,
+        value: &[u8],
+    ) -> Result<()> {
+        self.expose(characteristic)?;
+
+        self.stream_writes += 1;
+        if matches!(self.stream_loss, Some(nth) if self.stream_writes.is_multiple_of(nth)) {
+            return Ok(());
+        }
+
+        // Failures are only reported through `status`
+        if let Err(status) = self.apply(characteristic, value) {
+            self.set_status(status);
+        }
+
+        Ok(())
+    }
+
+    async fn subscribe(&mut self, characteristic: OtaCharacteristic) -> Result<()> {
+        self.expose(characteristic)
+    }
+
+    async fn next_notification(&mut self) -> Result<(OtaCharacteristic, Vec<u8>)> {
+        match self.notifications.pop_front() {
+            Some(notification) => Ok(notification),
+            // Streaming clients wait for acks with a timeout
+            None if self.stream_writes > 0 => std::future::pending().await,
+            None => Err(anyhow::anyhow!("Device disconnected")),
+        }
+    }
+}
+
+/// Info of a device running `esp-ota-ble` 1.0.0
+fn firmware_info(max_ota_size: u32) -> FirmwareInfo {
+    let mut encoded = [0; FirmwareInfo::LEN];
+    encoded[..5].copy_from_slice(b"1.0.0");
+    encoded[32..43].copy_from_slice(b"esp-ota-ble");
+    encoded[164..].copy_from_slice(&max_ota_size.to_le_bytes());
+
+    FirmwareInfo::decode(&encoded).unwrap()
+}
+
+fn image(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7) as u8).collect()
+}
+
+fn options(block_size: usize) -> UploadOptions {
+    UploadOptions {
+        block_size: Some(block_size),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn uploads_image() {
+    let image = image(1000);
+    let mut device = SimulatedDevice::new(4096);
+    let mut sent = Vec::new();
+
+    upload(&mut device, &image, &options(68), |progress, total| {
+        sent.push((progress, total))
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(device.session.sink().image(), image);
+    assert!(device.session.sink().is_finalized());
+    assert_eq!(sent.len(), 1000_usize.div_ceil(64));
+    assert_eq!(sent.last(), Some(&(1000, 1000)));
+}
+
+#[tokio::test]
+async fn block_size_follows_negotiated_mtu() {
+    let image = image(4000);
+    let mut device = SimulatedDevice::new(4096);
+
+    upload(&mut device, &image, &UploadOptions::default(), |_, _| {})
+        .await
+        .unwrap();
+
+    assert_eq!(device.session.sink().image(), image);
+    assert_eq!(device.largest_block, 244);
+
+    // MTU larger than the attribute, blocks are capped by the device
+    let mut device = SimulatedDevice::new(4096);
+    device.link_info.mtu = 517;
+
+    upload(&mut device, &image, &UploadOptions::default(), |_, _| {})
+        .await
+        .unwrap();
+
+    assert_eq!(device.largest_block, 512);
+}
+
+#[tokio::test]
+async fn firmware_without_optional_characteristics() {
+    let image = image(300);
+    let mut device = SimulatedDevice::new(4096);
+    device.missing = vec![
+        OtaCharacteristic::LinkInfo,
+        OtaCharacteristic::FileSignature,
+        OtaCharacteristic::StreamBlock,
+        OtaCharacteristic::StreamAck,
+    ];
+
+    // Blocks fall back to the default MTU
+    upload(&mut device, &image, &UploadOptions::default(), |_, _| {})
+        .await
+        .unwrap();
+
+    assert_eq!(device.session.sink().image(), image);
+    assert_eq!(device.largest_block, 20);
+
+    let options = UploadOptions {
+        signature: Some(signing::sign(&signing::generate_key(), &image)),
+        ..options(20)
+    };
+    let signed = upload(&mut device, &image, &options, |_, _| {}).await;
+    assert!(matches!(
+        signed,
+        Err(Error::Unsupported(OtaCharacteristic::FileSignature))
+    ));
+    assert_eq!(signed.unwrap_err().exit_code(), 13);
+
+    let streamed = upload(&mut device, &image, &streaming(20), |_, _| {}).await;
+    assert!(matches!(streamed, Err(Error::Unsupported(_))));
+}
+
+fn streaming(block_size: usize) -> UploadOptions {
+    UploadOptions {
+        streaming: true,
+        ack_timeout: Duration::from_millis(10),
+        ..options(block_size)
+    }
+}
+
+#[tokio::test]
+async fn streams_image() {
+    let image = image(5000);
+    let mut device = SimulatedDevice::new(8192);
+    let mut sent = Vec::new();
+
+    upload(&mut device, &image, &streaming(68), |progress, total| {
+        sent.push((progress, total))
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(device.session.sink().image(), image);
+    assert!(device.session.sink().is_finalized());
+    assert_eq!(device.stream_writes, 5000_usize.div_ceil(64));
+    assert_eq!(sent.last(), Some(&(5000, 5000)));
+}
+
+#[tokio::test]
+async fn lossy_stream_is_retransmitted() {
+    let image = image(5000);
+    let mut device = SimulatedDevice::new(8192);
+    device.stream_loss = Some(7);
+
+    upload(&mut device, &image, &streaming(68), |_, _| {})
+        .await
+        .unwrap();
+
+    assert_eq!(device.session.sink().image(), image);
+    assert!(device.session.sink().is_finalized());
+    assert!(device.stream_writes > 5000_usize.div_ceil(64));
+}
+
+#[tokio::test]
+async fn stream_failure_is_reported() {
+    let mut device = SimulatedDevice::new(8192);
+    device.session.sink_mut().fail_next_write();
+
+    let result = upload(&mut device, &image(5000), &streaming(68), |_, _| {}).await;
+
+    assert!(matches!(result, Err(Error::Rejected(Status::UpdateFailed))));
+}
+
+#[tokio::test]
+async fn transfer_in_progress_requires_force() {
+    let image = image(100);
+    let mut device = SimulatedDevice::new(4096);
+
+    let other = ImageInfo {
+        size: 50,
+        hash: ImageHash::of(&image[..50]),
+        signature: None,
+    };
+    device.session.start(other, false).unwrap();
+
+    let result = upload(&mut device, &image, &options(20), |_, _| {}).await;
+    assert!(matches!(
+        result,
+        Err(Error::Rejected(Status::TransferInProgress))
+    ));
+
+    // Reconnect, dropping notifications of the rejected attempt
+    device.notifications.clear();
+
+    let options = UploadOptions {
+        force: true,
+        ..options(20)
+    };
+    upload(&mut device, &image, &options, |_, _| {})
+        .await
+        .unwrap();
+
+    assert_eq!(device.session.sink().image(), image);
+}
+
+#[tokio::test]
+async fn interrupted_upload_is_resumed() {
+    let image = image(1000);
+    let mut device = SimulatedDevice::new(4096);
+    device.link_budget = Some(10);
+
+    let result = upload(&mut device, &image, &options(20), |_, _| {}).await;
+    assert!(matches!(result, Err(Error::Transport(_))));
+
+    // Reconnect
+    device.link_budget = None;
+    device.notifications.clear();
+
+    let mut sent = Vec::new();
+    upload(&mut device, &image, &options(20), |progress, _| {
+        sent.push(progress)
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(sent.first(), Some(&(10 * 16)));
+    assert_eq!(sent.len(), 1 + (1000_usize - 10 * 16).div_ceil(16));
+    assert_eq!(device.session.sink().image(), image);
+}
+
+#[tokio::test]
+async fn signed_upload() {
+    let image = image(300);
+    let key = signing::generate_key();
+
+    let mut device = SimulatedDevice::new(4096);
+    device.session = OtaSession::with_public_key(MemorySink::new(4096), signing::public_key(&key));
+
+    let unsigned = upload(&mut device, &image, &options(20), |_, _| {}).await;
+    assert!(matches!(
+        unsigned,
+        Err(Error::Rejected(Status::MissingSignature))
+    ));
+
+    device.notifications.clear();
+
+    let options = UploadOptions {
+        signature: Some(signing::sign(&key, &image)),
+        ..options(20)
+    };
+    upload(&mut device, &image, &options, |_, _| {})
+        .await
+        .unwrap();
+
+    assert_eq!(device.session.sink().image(), image);
+    assert!(device.session.sink().is_finalized());
+}
+
+#[tokio::test]
+async fn image_larger_than_partition_is_rejected() {
+    let mut device = SimulatedDevice::new(512);
+
+    let result = upload(&mut device, &image(1000), &options(20), |_, _| {}).await;
+
+    assert!(matches!(
+        result,
+        Err(Error::Rejected(Status::ImageTooLarge))
+    ));
+    assert_eq!(result.unwrap_err().exit_code(), 6);
+}
+
+#[tokio::test]
+async fn invalid_images_are_rejected_before_connecting() {
+    let mut device = SimulatedDevice::new(512);
+
+    let empty = upload(&mut device, &[], &options(20), |_, _| {}).await;
+    let no_room = upload(&mut device, &image(10), &options(4), |_, _| {}).await;
+
+    assert!(matches!(empty, Err(Error::InvalidImage(_))));
+    assert!(matches!(no_room, Err(Error::InvalidImage(_))));
+    assert_eq!(device.total_file_size, None);
+}
+
+#[tokio::test]
+async fn update_denied_by_application() {
+    let mut device = SimulatedDevice::new(4096);
+    device.policy = Err(StatusReport {
+        reason: 0x2a,
+        ..device.session.report(Status::UpdateDenied)
+    });
+
+    let result = upload(&mut device, &image(100), &options(20), |_, _| {}).await;
+
+    assert!(matches!(result, Err(Error::Denied(0x2a))));
+    assert_eq!(result.unwrap_err().exit_code(), 9);
+    assert!(device.session.sink().image().is_empty());
+}
+
+#[tokio::test]
+async fn deferred_update_is_retried() {
+    let image = image(100);
+    let mut device = SimulatedDevice::new(4096);
+    device.policy = Err(device.session.report(Status::UpdateDeferred));
+
+    let result = upload(&mut device, &image, &options(20), |_, _| {}).await;
+    assert!(matches!(result, Err(Error::Deferred)));
+
+    // Critical job is over
+    device.policy = Ok(());
+    device.notifications.clear();
+
+    upload(&mut device, &image, &options(20), |_, _| {})
+        .await
+        .unwrap();
+
+    assert_eq!(device.session.sink().image(), image);
+}
+
+#[tokio::test]
+async fn transfer_owned_by_another_client() {
+    let mut device = SimulatedDevice::new(4096);
+    let owner_image = image(200);
+    device
+        .session
+        .start(
+            ImageInfo {
+                size: 200,
+                hash: ImageHash::of(&owner_image),
+                signature: None,
+            },
+            false,
+        )
+        .unwrap();
+    device.status = Status::Receiving;
+    device.owned_by_other = true;
+
+    let result = upload(&mut device, &image(100), &options(20), |_, _| {}).await;
+
+    assert!(matches!(result, Err(Error::Busy)));
+    assert_eq!(result.unwrap_err().exit_code(), 12);
+    assert_eq!(device.status, Status::Receiving);
+    assert!(device.session.sink().image().is_empty());
+}
+
+#[tokio::test]
+async fn reads_firmware_info() {
+    let mut device = SimulatedDevice::new(4096);
+
+    let info = upload::firmware_info(&mut device).await.unwrap();
+
+    assert_eq!(info.version(), "1.0.0");
+    assert_eq!(info.project_name(), "esp-ota-ble");
+    assert_eq!(info.max_ota_size, 4096);
+}
+
+#[tokio::test]
+async fn reports_rolled_back_update() {
+    let mut device = SimulatedDevice::new(512);
+    device.slot_states = SlotStates {
+        running: SlotState::Valid,
+        next: SlotState::Aborted,
+    };
+
+    let states = upload::slot_states(&mut device).await.unwrap();
+
+    assert_eq!(states, device.slot_states);
+    assert_eq!(states.outcome(), BootOutcome::RolledBack);
+}
@@ -0,0 +1,261 @@
+//! Advertising of the OTA service, see `BleParams::advertising`
+
+use std::time::Duration;
+
+use anyhow::Result;
+use esp_bluedroid::GAP;
+use esp_idf_svc::{
+    bt::{
+        ble::gap::{AdvConfiguration, AppearanceCategory},
+        BtUuid,
+    },
+    sys::{
+        esp, esp_ble_addr_type_t_BLE_ADDR_TYPE_PUBLIC, esp_ble_adv_channel_t_ADV_CHNL_ALL,
+        esp_ble_adv_filter_t_ADV_FILTER_ALLOW_SCAN_ANY_CON_ANY, esp_ble_adv_params_t,
+        esp_ble_adv_type_t_ADV_TYPE_IND, esp_ble_gap_config_scan_rsp_data_raw,
+        esp_ble_gap_start_advertising, esp_ble_power_type_t_ESP_BLE_PWR_TYPE_ADV,
+        esp_ble_tx_power_set, esp_mac_type_t_ESP_MAC_BT, esp_power_level_t, esp_read_mac,
+        ESP_BLE_ADV_FLAG_BREDR_NOT_SPT, ESP_BLE_ADV_FLAG_GEN_DISC,
+    },
+};
+use ota_protocol::advertisement::ServiceData;
+
+/// Legacy advertising and scan response payloads are limited to 31 bytes each
+const MAX_PAYLOAD_LEN: usize = 31;
+
+/// AD type of service data under a 128-bit UUID
+const AD_TYPE_SERVICE_DATA_128: u8 = 0x21;
+
+/// Advertising intervals are counted in slots of 0.625 ms
+const INTERVAL_SLOT_US: u128 = 625;
+
+/// Range of advertising intervals allowed by the spec, 20 ms to 10.24 s
+const INTERVAL_SLOTS: std::ops::RangeInclusive<u128> = 0x20..=0x4000;
+
+/// Where `GattUuids::service` is advertised
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServiceUuidPlacement {
+    /// In the advertisement
+    Advertisement,
+    /// In the scan response, only seen by active scanners
+    ScanResponse,
+    /// Not advertised, clients have to find the device by name or address
+    Omitted,
+}
+
+pub struct AdvertisingParams {
+    /// Name advertised by the device, the stack default is kept if `None`
+    ///
+    /// The stack shortens it to the room left in its packet.
+    pub device_name: Option<String>,
+    pub appearance: AppearanceCategory,
+    /// Shortest advertising interval, at least 20 ms
+    pub min_interval: Duration,
+    /// Longest advertising interval, at most 10.24 s
+    pub max_interval: Duration,
+    /// TX power of advertisements, also advertised, e.g. `esp_power_level_t_ESP_PWR_LVL_P9`
+    pub tx_power: Option<esp_power_level_t>,
+    /// Manufacturer specific data of the advertisement, starting with the company identifier
+    pub manufacturer_data: Option<Vec<u8>>,
+    pub service_uuid: ServiceUuidPlacement,
+    /// Advertise `ServiceData` (firmware version, transfer state and `device_id`) in the scan
+    /// response, which then carries the service UUID too
+    pub service_data: bool,
+    /// Identifier advertised in `ServiceData`, the last 4 bytes of the Bluetooth MAC if `None`
+    pub device_id: Option<u32>,
+}
+
+impl Default for AdvertisingParams {
+    fn default() -> Self {
+        Self {
+            device_name: None,
+            appearance: AppearanceCategory::Unknown,
+            min_interval: Duration::from_millis(20),
+            max_interval: Duration::from_millis(40),
+            tx_power: None,
+            manufacturer_data: None,
+            service_uuid: ServiceUuidPlacement::Advertisement,
+            service_data: true,
+            device_id: None,
+        }
+    }
+}
+
+impl AdvertisingParams {
+    /// Checks that intervals are in range and that the payloads fit into legacy advertising
+    pub(super) fn validate(&self, service_uuid: &BtUuid) -> Result<()> {
+        let (min, max) = (
+            interval_slots(self.min_interval)?,
+            interval_slots(self.max_interval)?,
+        );
+        if min > max {
+            return Err(anyhow::anyhow!(
+                "Advertising interval {:?} is longer than {:?}",
+                self.min_interval,
+                self.max_interval
+            ));
+        }
+
+        // Every field is prefixed with its length and type
+        let field = |len: usize| 2 + len;
+        let uuid_len = service_uuid.as_bytes().len();
+
+        let mut advertisement = field(1);
+        if self.appearance != AppearanceCategory::Unknown {
+            advertisement += field(2);
+        }
+        if self.tx_power.is_some() {
+            advertisement += field(1);
+        }
+        if let Some(data) = &self.manufacturer_data {
+            advertisement += field(data.len());
+        }
+        if self.service_uuid == ServiceUuidPlacement::Advertisement {
+            advertisement += field(uuid_len);
+        }
+
+        let scan_response = if self.service_data {
+            field(uuid_len + ServiceData::LEN)
+        } else if self.service_uuid == ServiceUuidPlacement::ScanResponse {
+            field(uuid_len)
+        } else {
+            0
+        };
+
+        for (packet, len) in [
+            ("Advertisement", advertisement),
+            ("Scan response", scan_response),
+        ] {
+            if len > MAX_PAYLOAD_LEN {
+                return Err(anyhow::anyhow!(
+                    "{} of {} bytes exceeds {} bytes",
+                    packet,
+                    len,
+                    MAX_PAYLOAD_LEN
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sets the advertisement and scan response, advertising has to be (re)started afterwards
+    pub(super) fn configure(
+        &self,
+        service_uuid: &BtUuid,
+        service_data: &ServiceData,
+    ) -> Result<()> {
+        if let Some(name) = &self.device_name {
+            GAP.set_device_name(name)?;
+        }
+
+        if let Some(tx_power) = self.tx_power {
+            esp!(unsafe {
+                esp_ble_tx_power_set(esp_ble_power_type_t_ESP_BLE_PWR_TYPE_ADV, tx_power)
+            })?;
+        }
+
+        let in_advertisement = self.service_uuid == ServiceUuidPlacement::Advertisement;
+        let in_scan_response = self.service_uuid == ServiceUuidPlacement::ScanResponse;
+
+        // Name goes where the service UUID is not, unless service data fills the scan response
+        let name_in_advertisement = !in_advertisement || self.service_data;
+
+        GAP.set_adv_conf(&AdvConfiguration {
+            set_scan_rsp: false,
+            include_name: name_in_advertisement,
+            include_txpower: self.tx_power.is_some(),
+            manufacturer_data: self.manufacturer_data.as_deref(),
+            service_uuid: in_advertisement.then(|| service_uuid.clone()),
+            appearance: self.appearance,
+            flag: (ESP_BLE_ADV_FLAG_GEN_DISC | ESP_BLE_ADV_FLAG_BREDR_NOT_SPT) as _,
+            ..Default::default()
+        })?;
+
+        if self.service_data {
+            self.set_service_data(service_uuid, service_data)
+        } else {
+            GAP.set_adv_conf(&AdvConfiguration {
+                set_scan_rsp: true,
+                include_name: !name_in_advertisement,
+                service_uuid: in_scan_response.then(|| service_uuid.clone()),
+                ..Default::default()
+            })?;
+
+            Ok(())
+        }
+    }
+
+    /// Replaces the advertised `ServiceData`, e.g. once a transfer starts or ends
+    ///
+    /// `AdvConfiguration` only supports service data of 16-bit UUIDs, so the scan response is
+    /// set raw.
+    pub(super) fn set_service_data(
+        &self,
+        service_uuid: &BtUuid,
+        service_data: &ServiceData,
+    ) -> Result<()> {
+        if !self.service_data {
+            return Ok(());
+        }
+
+        let uuid = service_uuid.as_bytes();
+        let mut scan_response = Vec::with_capacity(2 + uuid.len() + ServiceData::LEN);
+
+        scan_response.push((1 + uuid.len() + ServiceData::LEN) as u8);
+        scan_response.push(AD_TYPE_SERVICE_DATA_128);
+        scan_response.extend_from_slice(uuid);
+        scan_response.extend_from_slice(&service_data.encode());
+
+        esp!(unsafe {
+            esp_ble_gap_config_scan_rsp_data_raw(
+                scan_response.as_mut_ptr(),
+                scan_response.len() as u32,
+            )
+        })?;
+
+        Ok(())
+    }
+
+    /// `device_id`, or the last 4 bytes of the Bluetooth MAC
+    pub(super) fn device_id(&self) -> Result<u32> {
+        if let Some(device_id) = self.device_id {
+            return Ok(device_id);
+        }
+
+        let mut mac = [0u8; 6];
+        esp!(unsafe { esp_read_mac(mac.as_mut_ptr(), esp_mac_type_t_ESP_MAC_BT) })?;
+
+        Ok(u32::from_be_bytes(mac[2..6].try_into().unwrap()))
+    }
+
+    /// Starts connectable undirected advertising with the configured intervals
+    pub(super) fn start(&self) -> Result<()> {
+        let mut params = esp_ble_adv_params_t {
+            adv_int_min: interval_slots(self.min_interval)?,
+            adv_int_max: interval_slots(self.max_interval)?,
+            adv_type: esp_ble_adv_type_t_ADV_TYPE_IND,
+            own_addr_type: esp_ble_addr_type_t_BLE_ADDR_TYPE_PUBLIC,
+            channel_map: esp_ble_adv_channel_t_ADV_CHNL_ALL,
+            adv_filter_policy: esp_ble_adv_filter_t_ADV_FILTER_ALLOW_SCAN_ANY_CON_ANY,
+            ..Default::default()
+        };
+
+        esp!(unsafe { esp_ble_gap_start_advertising(&mut params) })?;
+
+        Ok(())
+    }
+}
+
+fn interval_slots(interval: Duration) -> Result<u16> {
+    let slots = interval.as_micros() / INTERVAL_SLOT_US;
+
+    if !INTERVAL_SLOTS.contains(&slots) {
+        return Err(anyhow::anyhow!(
+            "Advertising interval {:?} is outside of 20 ms..=10.24 s",
+            interval
+        ));
+    }
+
+    Ok(slots as u16)
+}
@@ -0,0 +1,61 @@
+use anyhow::Result;
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel, signal::Signal,
+};
+
+use super::{
+    events::{OtaEvent, UpdateOutcome},
+    OtaBle,
+};
+
+/// Events queued for `OtaBle::next_event`, newer ones are dropped while it's full
+const EVENT_QUEUE_LEN: usize = 16;
+
+/// Async counterpart of the callbacks, fed by the GATT event handler
+pub(super) struct AsyncEvents {
+    events: Channel<CriticalSectionRawMutex, OtaEvent, EVENT_QUEUE_LEN>,
+    update_outcome: Signal<CriticalSectionRawMutex, UpdateOutcome>,
+}
+
+impl AsyncEvents {
+    pub(super) fn new() -> Self {
+        Self {
+            events: Channel::new(),
+            update_outcome: Signal::new(),
+        }
+    }
+
+    pub(super) fn push(&self, event: OtaEvent) {
+        if self.events.try_send(event).is_err() {
+            log::warn!("OTA event queue is full, dropping {:?}", event);
+        }
+
+        match event {
+            OtaEvent::UpdateReady => self.update_outcome.signal(UpdateOutcome::Ready),
+            OtaEvent::Aborted { reason } => {
+                self.update_outcome.signal(UpdateOutcome::Aborted(reason))
+            }
+            OtaEvent::TransferStarted { .. } => self.update_outcome.reset(),
+            _ => {}
+        }
+    }
+}
+
+impl OtaBle {
+    /// Waits for the next OTA event
+    ///
+    /// Events are queued from the moment `OtaBle` is created, meant for a single consumer.
+    pub async fn next_event(&self) -> OtaEvent {
+        self.async_events.events.receive().await
+    }
+
+    /// Waits until the current (or next) update is ready or aborted
+    pub async fn wait_for_update(&self) -> UpdateOutcome {
+        self.async_events.update_outcome.wait().await
+    }
+
+    /// Waits until the OTA service is registered and started
+    pub async fn wait_service_started(&self) -> Result<()> {
+        self.service.wait_started().await
+    }
+}
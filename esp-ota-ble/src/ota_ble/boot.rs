@@ -0,0 +1,43 @@
+use esp_idf_svc::sys::{
+    esp, esp_ota_get_next_update_partition, esp_ota_get_running_partition,
+    esp_ota_get_state_partition, esp_ota_img_states_t, esp_ota_img_states_t_ESP_OTA_IMG_ABORTED,
+    esp_ota_img_states_t_ESP_OTA_IMG_INVALID, esp_ota_img_states_t_ESP_OTA_IMG_NEW,
+    esp_ota_img_states_t_ESP_OTA_IMG_PENDING_VERIFY, esp_ota_img_states_t_ESP_OTA_IMG_VALID,
+    esp_partition_t,
+};
+use ota_protocol::slot::{SlotState, SlotStates};
+
+/// States of the running slot and of the one the next update is written to
+///
+/// `EspOta` folds `New` and `PendingVerify` into a single state, so they are read directly.
+pub fn slot_states() -> SlotStates {
+    unsafe {
+        SlotStates {
+            running: slot_state(esp_ota_get_running_partition()),
+            next: slot_state(esp_ota_get_next_update_partition(std::ptr::null())),
+        }
+    }
+}
+
+pub(super) fn slot_state(partition: *const esp_partition_t) -> SlotState {
+    if partition.is_null() {
+        return SlotState::Undefined;
+    }
+
+    let mut state: esp_ota_img_states_t = 0;
+
+    // Fails for the factory app and slots which were never written
+    if esp!(unsafe { esp_ota_get_state_partition(partition, &mut state) }).is_err() {
+        return SlotState::Undefined;
+    }
+
+    #[allow(non_upper_case_globals)]
+    match state {
+        esp_ota_img_states_t_ESP_OTA_IMG_NEW => SlotState::New,
+        esp_ota_img_states_t_ESP_OTA_IMG_PENDING_VERIFY => SlotState::PendingVerify,
+        esp_ota_img_states_t_ESP_OTA_IMG_VALID => SlotState::Valid,
+        esp_ota_img_states_t_ESP_OTA_IMG_INVALID => SlotState::Invalid,
+        esp_ota_img_states_t_ESP_OTA_IMG_ABORTED => SlotState::Aborted,
+        _ => SlotState::Undefined,
+    }
+}
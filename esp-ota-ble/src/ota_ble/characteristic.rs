@@ -1,61 +1,85 @@
+use std::{marker::PhantomData, sync::Arc};
+
 use anyhow::Result;
-use esp_idf_svc::{
-    bt::BtUuid,
-    sys::{esp, esp_ble_gatts_get_attr_value},
+use enumset::EnumSet;
+use esp_bluedroid::Characteristic;
+use esp_idf_svc::bt::{
+    ble::gatt::{AutoResponse, Permission, Property},
+    BtUuid,
 };
-use std::marker::PhantomData;
+use ota_protocol::codec::Codec;
 
+/// `esp_bluedroid::Characteristic` holding a `T`
+///
+/// Values are encoded with the `Codec` of `T` from `ota-protocol`, attribute storage,
+/// handles and subscriptions are left to the underlying characteristic.
 pub struct OtaCharacteristic<T> {
-    uuid: BtUuid,
-    attribute_handle: Option<u16>,
+    characteristic: Arc<Characteristic>,
 
-    _phantom: PhantomData<T>,
+    // Doesn't hold a `T`, so it is `Sync` regardless of it
+    _phantom: PhantomData<fn() -> T>,
 }
 
-impl<T> OtaCharacteristic<T> {
-    pub fn new(uuid: BtUuid) -> Self {
-        Self {
-            uuid,
-            attribute_handle: None,
-            _phantom: PhantomData,
-        }
+impl<T: Codec> OtaCharacteristic<T> {
+    /// Characteristic with a `T::MAX_LEN` long attribute
+    pub fn new(
+        uuid: BtUuid,
+        permissions: EnumSet<Permission>,
+        properties: EnumSet<Property>,
+        auto_rsp: AutoResponse,
+    ) -> Self {
+        Characteristic::new(uuid, permissions, properties, auto_rsp)
+            .with_max_len(T::MAX_LEN)
+            .into()
     }
 
-    pub fn set_attribute_handle(&mut self, handle: u16) {
-        self.attribute_handle = Some(handle);
-    }
+    /// Decodes the current value of the attribute
+    pub fn get_value(&self) -> Result<T> {
+        let raw_value = self.characteristic.get_value()?;
 
-    pub fn is_initialized(&self) -> bool {
-        self.attribute_handle.is_some()
+        T::decode(&raw_value).map_err(|error| anyhow::anyhow!("Failed to decode value: {}", error))
     }
 
-    pub fn get_value<'a>(&self) -> Result<T>
-    where
-        T: TryFrom<&'a [u8]>,
-    {
-        let raw_bytes = self.get_raw_value()?;
+    /// Updates the attribute, served to clients on their next read
+    pub fn set_value(&self, value: &T) -> Result<()> {
+        self.characteristic.set_value(value.encode().as_ref())
+    }
 
-        T::try_from(raw_bytes).map_err(|_| anyhow::anyhow!("Failed to convert data: "))
+    /// Updates the attribute and notifies every peer subscribed to notifications
+    pub fn notify(&self, gatt_if: u8, value: &T) -> Result<()> {
+        self.characteristic.notify(gatt_if, value.encode().as_ref())
     }
 
-    pub fn get_raw_value<'a>(&self) -> Result<&'a [u8]> {
-        let attr_handle = self
-            .attribute_handle
-            .ok_or_else(|| anyhow::anyhow!("Attribute handle not set"))?;
+    /// Notifies only `conn_id`, other peers keep reading the current value
+    pub fn notify_peer(&self, gatt_if: u8, conn_id: u16, value: &T) -> Result<()> {
+        self.characteristic
+            .notify_peer(gatt_if, conn_id, value.encode().as_ref())
+    }
 
-        let mut len: u16 = 0;
-        let mut data: *const u8 = core::ptr::null_mut();
+    /// Updates the attribute and indicates it to every peer subscribed to indications
+    pub fn indicate(&self, gatt_if: u8, value: &T) -> Result<()> {
+        self.characteristic
+            .indicate(gatt_if, value.encode().as_ref())
+    }
+}
 
-        let raw_data = unsafe {
-            esp!(esp_ble_gatts_get_attr_value(
-                attr_handle,
-                &mut len,
-                &mut data
-            ))?;
+impl<T> OtaCharacteristic<T> {
+    /// Underlying characteristic, to be added to a `Service`
+    pub fn characteristic(&self) -> &Arc<Characteristic> {
+        &self.characteristic
+    }
 
-            core::slice::from_raw_parts(data, len as _)
-        };
+    pub fn handle(&self) -> Option<u16> {
+        self.characteristic.handle()
+    }
+}
 
-        Ok(raw_data)
+/// Wraps a characteristic configured by hand, e.g. for raw values of a configurable length
+impl<T> From<Characteristic> for OtaCharacteristic<T> {
+    fn from(characteristic: Characteristic) -> Self {
+        Self {
+            characteristic: Arc::new(characteristic),
+            _phantom: PhantomData,
+        }
     }
 }
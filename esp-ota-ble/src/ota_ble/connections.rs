@@ -0,0 +1,119 @@
+//! Peers connected to the OTA service and which of them owns the transfer
+
+use esp_idf_svc::{bt::BdAddr, sys::CONFIG_BT_ACL_CONNECTIONS};
+use ota_protocol::link::DEFAULT_MTU;
+
+/// How many peers may connect, and what the others may do while one of them owns a transfer
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionPolicy {
+    /// One peer at a time, the device stops advertising while it's connected
+    #[default]
+    SingleClient,
+    /// Several peers, only the one which started the transfer may write until it ends
+    MultiClientReadOnly,
+    /// Several peers, while one owns the transfer the others may still clear it, force a new
+    /// one or reset the device
+    MultiClient,
+}
+
+/// What a write of a peer does to the transfer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Access {
+    /// Starts or continues the transfer: `StartTransfer`, `total_file_size` and `file_block`
+    Transfer,
+    /// Takes over or ends the transfer: `ClearTransfer`, `StartForceTransfer` and `ResetDevice`
+    Control,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Peer {
+    pub conn_id: u16,
+    pub addr: BdAddr,
+    /// ATT MTU negotiated by the peer
+    pub mtu: u16,
+}
+
+/// Connected peers, in connection order
+pub(super) struct Peers {
+    policy: ConnectionPolicy,
+    connected: Vec<Peer>,
+    /// Peer which started the transfer in progress, released once it ends or the peer leaves
+    transfer_owner: Option<u16>,
+}
+
+impl Peers {
+    pub fn new(policy: ConnectionPolicy) -> Self {
+        Self {
+            policy,
+            connected: Vec::new(),
+            transfer_owner: None,
+        }
+    }
+
+    pub fn connect(&mut self, conn_id: u16, addr: BdAddr) {
+        if self.get(conn_id).is_none() {
+            self.connected.push(Peer {
+                conn_id,
+                addr,
+                mtu: DEFAULT_MTU,
+            });
+        }
+    }
+
+    /// Drops `conn_id`, a transfer it owned can be resumed by any peer
+    pub fn disconnect(&mut self, conn_id: u16) {
+        self.connected.retain(|peer| peer.conn_id != conn_id);
+
+        if self.transfer_owner == Some(conn_id) {
+            self.transfer_owner = None;
+        }
+    }
+
+    /// Whether the device should advertise to let more peers connect
+    pub fn should_advertise(&self) -> bool {
+        match self.policy {
+            ConnectionPolicy::SingleClient => self.connected.is_empty(),
+            _ => self.connected.len() < CONFIG_BT_ACL_CONNECTIONS as usize,
+        }
+    }
+
+    /// Checks that `conn_id` may write while another peer may own the transfer
+    ///
+    /// Returns the owner if it may not.
+    pub fn check(&self, conn_id: u16, access: Access) -> Result<(), u16> {
+        match self.transfer_owner {
+            Some(owner) if owner != conn_id => match (self.policy, access) {
+                (ConnectionPolicy::MultiClient, Access::Control) => Ok(()),
+                _ => Err(owner),
+            },
+            _ => Ok(()),
+        }
+    }
+
+    pub fn get(&self, conn_id: u16) -> Option<&Peer> {
+        self.connected.iter().find(|peer| peer.conn_id == conn_id)
+    }
+
+    pub fn set_mtu(&mut self, conn_id: u16, mtu: u16) {
+        if let Some(peer) = self
+            .connected
+            .iter_mut()
+            .find(|peer| peer.conn_id == conn_id)
+        {
+            peer.mtu = mtu;
+        }
+    }
+
+    /// Peer owning the transfer in progress, if it's still connected
+    pub fn transfer_owner(&self) -> Option<&Peer> {
+        self.transfer_owner.and_then(|owner| self.get(owner))
+    }
+
+    pub fn take_transfer(&mut self, conn_id: u16) {
+        self.transfer_owner = Some(conn_id);
+    }
+
+    pub fn release_transfer(&mut self) {
+        self.transfer_owner = None;
+    }
+}
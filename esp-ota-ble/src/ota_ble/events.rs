@@ -0,0 +1,40 @@
+use ota_protocol::status::Status;
+
+/// Progress of OTA updates, see `OtaBle::subscribe_ota_event`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OtaEvent {
+    /// Client started (or resumed) the transfer of a `size` bytes image
+    TransferStarted {
+        size: u32,
+    },
+    /// `received` bytes out of `total` were written, emitted every `PROGRESS_INTERVAL` bytes
+    Progress {
+        received: u32,
+        total: u32,
+    },
+    /// Received image doesn't match its hash or isn't a valid image, followed by `Aborted`
+    VerificationFailed,
+    /// Whole image was received, verified and set as the boot image
+    UpdateReady,
+    /// Client requested a reset, the device restarts shortly
+    RebootScheduled,
+    /// Transfer was dropped, `reason` is the status reported to the client:
+    /// `Status::Idle` if it was cleared by the client
+    Aborted {
+        reason: Status,
+    },
+    PeerConnected {
+        conn_id: u16,
+    },
+    PeerDisconnected {
+        conn_id: u16,
+    },
+}
+
+/// How the update awaited by `OtaBle::wait_for_update` ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpdateOutcome {
+    /// Device boots into the new image on the next reset
+    Ready,
+    Aborted(Status),
+}
@@ -0,0 +1,244 @@
+//! Link tuning of the peer owning a transfer, see `BleParams::link`
+
+use std::time::Duration;
+
+use anyhow::Result;
+use esp_idf_svc::{
+    bt::BdAddr,
+    sys::{
+        esp, esp_ble_conn_update_params_t, esp_ble_gap_set_pkt_data_len,
+        esp_ble_gap_update_conn_params, esp_ble_gatt_set_local_mtu,
+    },
+};
+use ota_protocol::link::{DEFAULT_MTU, MAX_MTU};
+
+/// Connection intervals are counted in units of 1.25 ms
+const INTERVAL_UNIT_US: u128 = 1250;
+
+/// Range of connection intervals allowed by the spec, 7.5 ms to 4 s
+const INTERVAL_UNITS: std::ops::RangeInclusive<u128> = 0x06..=0x0c80;
+
+/// Supervision timeouts are counted in units of 10 ms
+const TIMEOUT_UNIT_MS: u128 = 10;
+
+/// Range of supervision timeouts allowed by the spec, 100 ms to 32 s
+const TIMEOUT_UNITS: std::ops::RangeInclusive<u128> = 0x0a..=0x0c80;
+
+/// Longest link layer payload of the data length extension
+const MAX_TX_DATA_LEN: u16 = 251;
+
+/// Link layer payload of BLE 4.0 links
+const DEFAULT_TX_DATA_LEN: u16 = 27;
+
+/// Connection parameters requested from the central, which has the final say
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnParams {
+    pub min_interval: Duration,
+    pub max_interval: Duration,
+    /// Connection events the peripheral may skip when it has nothing to send
+    pub latency: u16,
+    /// Time without packets after which the link is considered lost
+    pub timeout: Duration,
+}
+
+impl ConnParams {
+    fn validate(&self) -> Result<()> {
+        let (min, max) = (
+            interval_units(self.min_interval)?,
+            interval_units(self.max_interval)?,
+        );
+        if min > max {
+            return Err(anyhow::anyhow!(
+                "Connection interval {:?} is longer than {:?}",
+                self.min_interval,
+                self.max_interval
+            ));
+        }
+
+        // The link must survive the peripheral skipping `latency` events
+        let timeout = timeout_units(self.timeout)?;
+        if timeout as u128 * TIMEOUT_UNIT_MS * 1000
+            <= (1 + self.latency as u128) * max as u128 * INTERVAL_UNIT_US * 2
+        {
+            return Err(anyhow::anyhow!(
+                "Supervision timeout {:?} is too short for a latency of {} at {:?}",
+                self.timeout,
+                self.latency,
+                self.max_interval
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Asks the central connected at `addr` to switch to these parameters
+    fn request(&self, addr: BdAddr) -> Result<()> {
+        let mut params = esp_ble_conn_update_params_t {
+            bda: addr.raw(),
+            min_int: interval_units(self.min_interval)?,
+            max_int: interval_units(self.max_interval)?,
+            latency: self.latency,
+            timeout: timeout_units(self.timeout)?,
+        };
+
+        esp!(unsafe { esp_ble_gap_update_conn_params(&mut params) })?;
+
+        Ok(())
+    }
+}
+
+pub struct LinkParams {
+    /// ATT MTU offered to clients, which usually negotiate it right after connecting
+    pub mtu: u16,
+    /// Requested while a transfer is in progress, short intervals carry more blocks per second
+    pub transfer: ConnParams,
+    /// Requested once the transfer ends, to save power
+    pub idle: ConnParams,
+    /// Request the 2M PHY during a transfer, on chips supporting BLE 5
+    pub prefer_2m_phy: bool,
+    /// Request link layer packets of 251 bytes during a transfer (data length extension), so a
+    /// block isn't split into several packets
+    pub data_length_extension: bool,
+}
+
+impl Default for LinkParams {
+    fn default() -> Self {
+        Self {
+            mtu: MAX_MTU,
+            transfer: ConnParams {
+                min_interval: Duration::from_micros(7500),
+                max_interval: Duration::from_millis(15),
+                latency: 0,
+                timeout: Duration::from_secs(4),
+            },
+            idle: ConnParams {
+                min_interval: Duration::from_millis(30),
+                max_interval: Duration::from_millis(50),
+                latency: 4,
+                timeout: Duration::from_secs(6),
+            },
+            prefer_2m_phy: true,
+            data_length_extension: true,
+        }
+    }
+}
+
+impl LinkParams {
+    /// Checks that the MTU and connection parameters are in range
+    pub(super) fn validate(&self) -> Result<()> {
+        if !(DEFAULT_MTU..=MAX_MTU).contains(&self.mtu) {
+            return Err(anyhow::anyhow!(
+                "MTU {} is outside of {}..={}",
+                self.mtu,
+                DEFAULT_MTU,
+                MAX_MTU
+            ));
+        }
+
+        self.transfer.validate()?;
+        self.idle.validate()
+    }
+
+    /// Sets the MTU offered to clients, before any of them connects
+    pub(super) fn set_local_mtu(&self) -> Result<()> {
+        esp!(unsafe { esp_ble_gatt_set_local_mtu(self.mtu) })?;
+
+        Ok(())
+    }
+
+    /// Speeds up the link of the peer at `addr`, which just started a transfer
+    pub(super) fn start_transfer(&self, addr: BdAddr) -> Result<()> {
+        self.transfer.request(addr)?;
+
+        if self.data_length_extension {
+            set_data_length(addr, MAX_TX_DATA_LEN)?;
+        }
+        if self.prefer_2m_phy {
+            set_2m_phy(addr, true)?;
+        }
+
+        Ok(())
+    }
+
+    /// Relaxes the link of the peer at `addr` once its transfer ended
+    pub(super) fn end_transfer(&self, addr: BdAddr) -> Result<()> {
+        self.idle.request(addr)?;
+
+        if self.data_length_extension {
+            set_data_length(addr, DEFAULT_TX_DATA_LEN)?;
+        }
+        if self.prefer_2m_phy {
+            set_2m_phy(addr, false)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn set_data_length(addr: BdAddr, tx_data_len: u16) -> Result<()> {
+    let mut bda = addr.raw();
+
+    esp!(unsafe { esp_ble_gap_set_pkt_data_len(bda.as_mut_ptr(), tx_data_len) })?;
+
+    Ok(())
+}
+
+/// Prefers the 2M PHY, or the 1M one if `fast` is not set
+#[cfg(esp_idf_bt_ble_50_features_supported)]
+fn set_2m_phy(addr: BdAddr, fast: bool) -> Result<()> {
+    use esp_idf_svc::sys::{
+        esp_ble_gap_set_preferred_phy, ESP_BLE_GAP_PHY_1M_PREF_MASK, ESP_BLE_GAP_PHY_2M_PREF_MASK,
+        ESP_BLE_GAP_PHY_OPTIONS_NO_PREF,
+    };
+
+    let mut bda = addr.raw();
+    let phy = if fast {
+        ESP_BLE_GAP_PHY_2M_PREF_MASK
+    } else {
+        ESP_BLE_GAP_PHY_1M_PREF_MASK
+    } as u8;
+
+    esp!(unsafe {
+        esp_ble_gap_set_preferred_phy(
+            bda.as_mut_ptr(),
+            0,
+            phy,
+            phy,
+            ESP_BLE_GAP_PHY_OPTIONS_NO_PREF as _,
+        )
+    })?;
+
+    Ok(())
+}
+
+/// Chips without BLE 5 (e.g. the ESP32) only have the 1M PHY
+#[cfg(not(esp_idf_bt_ble_50_features_supported))]
+fn set_2m_phy(_addr: BdAddr, _fast: bool) -> Result<()> {
+    Ok(())
+}
+
+fn interval_units(interval: Duration) -> Result<u16> {
+    let units = interval.as_micros() / INTERVAL_UNIT_US;
+
+    if !INTERVAL_UNITS.contains(&units) {
+        return Err(anyhow::anyhow!(
+            "Connection interval {:?} is outside of 7.5 ms..=4 s",
+            interval
+        ));
+    }
+
+    Ok(units as u16)
+}
+
+fn timeout_units(timeout: Duration) -> Result<u16> {
+    let units = timeout.as_millis() / TIMEOUT_UNIT_MS;
+
+    if !TIMEOUT_UNITS.contains(&units) {
+        return Err(anyhow::anyhow!(
+            "Supervision timeout {:?} is outside of 100 ms..=32 s",
+            timeout
+        ));
+    }
+
+    Ok(units as u16)
+}
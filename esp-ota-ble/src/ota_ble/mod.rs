@@ -1,59 +1,105 @@
 use std::{
-    borrow::Borrow,
-    mem::size_of,
     rc::Rc,
     sync::{
         mpsc::{channel, Receiver, Sender},
         Arc, Mutex,
     },
+    time::{Duration, Instant},
 };
 
 use anyhow::Result;
+use esp_bluedroid::{App, Characteristic, Service, GATT, REGISTRY};
 use esp_idf_svc::{
     bt::{
         ble::{
-            gap::{AdvConfiguration, BleGapEvent, EspBleGap},
+            gap::BleGapEvent,
             gatt::{
-                server::{EspGatts, GattsEvent},
-                AutoResponse, GattCharacteristic, GattId, GattServiceId, GattStatus, Permission,
-                Property,
+                server::GattsEvent, AutoResponse, GattResponse, GattStatus, Permission, Property,
             },
         },
-        Ble, BtDriver,
+        BtUuid,
     },
-    hal::modem::BluetoothModem,
+    hal::delay::FreeRtos,
+    nvs::EspDefaultNvsPartition,
     ota::EspOta,
-    sys::{
-        esp_partition_find, esp_partition_get, esp_partition_iterator_release, esp_partition_next,
-        esp_partition_subtype_t_ESP_PARTITION_SUBTYPE_ANY,
-        esp_partition_type_t_ESP_PARTITION_TYPE_APP,
-    },
 };
 use lazy_static::lazy_static;
+use ota_protocol::{
+    advertisement::ServiceData,
+    block::Block,
+    command::Command,
+    hash::ImageHash,
+    image::{AppDescriptor, IMAGE_PREFIX_LEN},
+    info::FirmwareInfo,
+    link::{LinkInfo, DEFAULT_MTU},
+    session::{BlockOutcome, ImageInfo, OtaSession, SessionError, SessionState},
+    signature::{ImageSignature, PublicKey},
+    size::TotalFileSize,
+    slot::{SlotState, SlotStates},
+    status::{FinishedUpload, Status, StatusReport},
+    stream::{StreamAck, StreamBlock, StreamReceiver},
+    version::DowngradePolicy,
+    DecodeError,
+};
 
-use self::uuids::GattUuids;
+#[cfg(feature = "embassy")]
+use self::asynch::AsyncEvents;
+use self::{
+    advertising::AdvertisingParams,
+    characteristic::OtaCharacteristic,
+    connections::{Access, ConnectionPolicy, Peers},
+    events::OtaEvent,
+    link::LinkParams,
+    partitions::OtaSlot,
+    policy::{PolicyDecision, PolicyStage, UpdateRequest},
+    store::NvsStore,
+    updater::OtaUpdater,
+    uuids::GattUuids,
+};
 
+pub mod advertising;
+#[cfg(feature = "embassy")]
+mod asynch;
+mod boot;
 pub mod characteristic;
-mod commands;
+pub mod connections;
+pub mod events;
+pub mod link;
 pub mod macros;
+pub mod partitions;
+pub mod policy;
+mod store;
+mod updater;
 pub mod uuids;
 
-type StaticBtDriver = BtDriver<'static, Ble>;
-type StaticEspGatts = Arc<EspGatts<'static, Ble, &'static BtDriver<'static, Ble>>>;
-type StaticEspBleGap = Arc<EspBleGap<'static, Ble, &'static BtDriver<'static, Ble>>>;
-
 lazy_static! {
-    pub static ref BT_DRIVER: StaticBtDriver =
-        BtDriver::new(unsafe { BluetoothModem::new() }, None).unwrap();
-    pub static ref GAP: StaticEspBleGap = Arc::new(EspBleGap::new((*BT_DRIVER).borrow()).unwrap());
-    pub static ref GATT: StaticEspGatts = Arc::new(EspGatts::new((*BT_DRIVER).borrow()).unwrap());
     static ref OTA_BLE: Arc<Mutex<Option<Arc<OtaBle>>>> = Arc::new(Mutex::new(None));
 }
 
 pub struct BleParams {
-    ota_app_id: u16,
-    service_instance_id: u8,
-    max_block_size: usize,
+    pub ota_app_id: u16,
+    pub service_instance_id: u8,
+    /// Longest `file_block` write accepted, clients write at most `MTU - 3` bytes at once
+    pub max_block_size: usize,
+    /// Only accept images signed with the private half of this key (see `file_signature`)
+    pub public_key: Option<PublicKey>,
+    /// Persist transfers in this NVS partition, so they can be resumed after a reset
+    pub nvs: Option<EspDefaultNvsPartition>,
+    /// How long the health check of `OtaBle::confirm_boot` has to pass after an update
+    pub confirm_window: Duration,
+    /// Which images are refused as a downgrade of the running app, `SecureVersion` requires `nvs`
+    /// to remember the highest confirmed secure version
+    pub downgrade_policy: DowngradePolicy,
+    /// Name, intervals and payloads the device advertises with
+    pub advertising: AdvertisingParams,
+    /// How many peers may connect and which of them may write during a transfer
+    pub connection_policy: ConnectionPolicy,
+    /// MTU offered to clients and connection parameters requested during and after a transfer
+    pub link: LinkParams,
+    /// Blocks a streaming client may have in flight, each one buffered while a gap is filled
+    pub stream_window: u16,
+    /// Blocks between two acks of a stream, at most `stream_window`
+    pub stream_ack_interval: u16,
 }
 
 impl Default for BleParams {
@@ -62,25 +108,174 @@ impl Default for BleParams {
             ota_app_id: 254,
             service_instance_id: 3,
             max_block_size: 512,
+            public_key: None,
+            nvs: None,
+            confirm_window: Duration::from_secs(60),
+            downgrade_policy: DowngradePolicy::AllowAny,
+            advertising: AdvertisingParams::default(),
+            connection_policy: ConnectionPolicy::default(),
+            link: LinkParams::default(),
+            stream_window: 16,
+            stream_ack_interval: 8,
         }
     }
 }
 
+/// Delay between two runs of the health check of `OtaBle::confirm_boot`
+const HEALTH_CHECK_INTERVAL_MS: u32 = 1000;
+
 type GapCallback = Box<dyn FnMut(&BleGapEvent) + Send + 'static>;
 type GattCallback = Box<dyn FnMut(u8, &GattsEvent) + Send + 'static>;
+type OtaCallback = Box<dyn FnMut(&OtaEvent) + Send + 'static>;
+type UpdatePolicy = Box<dyn FnMut(&UpdateRequest) -> PolicyDecision + Send + 'static>;
+
+/// Granularity of `OtaEvent::Progress`, in bytes of the image
+const PROGRESS_INTERVAL: u32 = 16 * 1024;
+
+/// Characteristics of the OTA service, added to it in this order
+struct OtaCharacteristics {
+    file_block: OtaCharacteristic<Vec<u8>>,
+    file_hash: OtaCharacteristic<ImageHash>,
+    finished_upload: OtaCharacteristic<FinishedUpload>,
+    command: OtaCharacteristic<Command>,
+    status: OtaCharacteristic<StatusReport>,
+    total_file_size: OtaCharacteristic<TotalFileSize>,
+    file_signature: OtaCharacteristic<ImageSignature>,
+    slot_states: OtaCharacteristic<SlotStates>,
+    firmware_info: OtaCharacteristic<FirmwareInfo>,
+    link_info: OtaCharacteristic<LinkInfo>,
+    stream_block: OtaCharacteristic<Vec<u8>>,
+    stream_ack: OtaCharacteristic<StreamAck>,
+}
+
+impl OtaCharacteristics {
+    fn new(uuids: &GattUuids, max_block_size: usize) -> Self {
+        Self {
+            file_block: Characteristic::new(
+                uuids.file_block.clone(),
+                Permission::Write.into(),
+                Property::Write.into(),
+                AutoResponse::ByApp,
+            )
+            .with_max_len(max_block_size)
+            .into(),
+            file_hash: OtaCharacteristic::new(
+                uuids.file_hash.clone(),
+                Permission::Read | Permission::Write,
+                Property::Read | Property::Write,
+                AutoResponse::ByApp,
+            ),
+            finished_upload: OtaCharacteristic::new(
+                uuids.finished_upload.clone(),
+                Permission::Read.into(),
+                Property::Read | Property::Notify,
+                AutoResponse::ByGatt,
+            ),
+            command: OtaCharacteristic::new(
+                uuids.command.clone(),
+                Permission::Write.into(),
+                Property::Write.into(),
+                AutoResponse::ByApp,
+            ),
+            status: OtaCharacteristic::new(
+                uuids.status.clone(),
+                Permission::Read.into(),
+                Property::Read | Property::Notify,
+                AutoResponse::ByGatt,
+            ),
+            total_file_size: OtaCharacteristic::new(
+                uuids.total_file_size.clone(),
+                Permission::Read | Permission::Write,
+                Property::Read | Property::Write | Property::Notify,
+                AutoResponse::ByApp,
+            ),
+            file_signature: OtaCharacteristic::new(
+                uuids.file_signature.clone(),
+                Permission::Write.into(),
+                Property::Write.into(),
+                AutoResponse::ByApp,
+            ),
+            slot_states: OtaCharacteristic::new(
+                uuids.slot_states.clone(),
+                Permission::Read.into(),
+                Property::Read.into(),
+                AutoResponse::ByGatt,
+            ),
+            firmware_info: OtaCharacteristic::new(
+                uuids.firmware_info.clone(),
+                Permission::Read.into(),
+                Property::Read.into(),
+                AutoResponse::ByGatt,
+            ),
+            // Answered per peer, each one negotiates its own MTU
+            link_info: OtaCharacteristic::new(
+                uuids.link_info.clone(),
+                Permission::Read.into(),
+                Property::Read.into(),
+                AutoResponse::ByApp,
+            ),
+            stream_block: Characteristic::new(
+                uuids.stream_block.clone(),
+                Permission::Write.into(),
+                Property::WriteNoResponse.into(),
+                AutoResponse::ByApp,
+            )
+            .with_max_len(max_block_size)
+            .into(),
+            stream_ack: OtaCharacteristic::new(
+                uuids.stream_ack.clone(),
+                Permission::Read.into(),
+                Property::Read | Property::Notify,
+                AutoResponse::ByGatt,
+            ),
+        }
+    }
+
+    /// OTA service declaring all characteristics
+    fn service(&self, uuid: BtUuid, instance_id: u8) -> Service {
+        Service::new(uuid)
+            .instance_id(instance_id)
+            .characteristic(self.file_block.characteristic().clone())
+            .characteristic(self.file_hash.characteristic().clone())
+            .characteristic(self.finished_upload.characteristic().clone())
+            .characteristic(self.command.characteristic().clone())
+            .characteristic(self.status.characteristic().clone())
+            .characteristic(self.total_file_size.characteristic().clone())
+            .characteristic(self.file_signature.characteristic().clone())
+            .characteristic(self.slot_states.characteristic().clone())
+            .characteristic(self.firmware_info.characteristic().clone())
+            .characteristic(self.link_info.characteristic().clone())
+            .characteristic(self.stream_block.characteristic().clone())
+            .characteristic(self.stream_ack.characteristic().clone())
+    }
+}
 
 pub struct OtaBle {
-    ble_uuids: GattUuids,
     ble_params: BleParams,
-    service_handle: Mutex<Option<u16>>,
+    service: Arc<Service>,
+    characteristics: OtaCharacteristics,
+    /// Value of `firmware_info`, the running app doesn't change until a reset
+    firmware_info: FirmwareInfo,
+    /// Advertised along with the service UUID, see `AdvertisingParams::service_data`
+    service_data: Mutex<ServiceData>,
 
     gap_callbacks: Mutex<Vec<GapCallback>>,
     gatt_callbacks: Mutex<Vec<GattCallback>>,
+    ota_callbacks: Mutex<Vec<OtaCallback>>,
+    update_policy: Mutex<Option<UpdatePolicy>>,
+
+    peers: Mutex<Peers>,
+
+    esp_ota: Mutex<EspOta>,
 
-    connected_peers: Mutex<Vec<u16>>,
+    session: Mutex<OtaSession<OtaUpdater, Option<NvsStore>>>,
+    /// Reorders `stream_block` writes, see `ota_protocol::stream`
+    stream: Mutex<StreamReceiver>,
+    /// Long write of `file_hash` or `file_signature` waiting for its execute write request
+    prepared: Mutex<Option<PreparedWrite>>,
 
-    // esp_ota
-    esp_ota: EspOta,
+    #[cfg(feature = "embassy")]
+    async_events: AsyncEvents,
 }
 
 impl OtaBle {
@@ -90,36 +285,108 @@ impl OtaBle {
             return Err(anyhow::anyhow!("OtaBle has already been initialized"));
         }
 
+        ble_params.advertising.validate(&ble_uuids.service)?;
+        ble_params.link.validate()?;
+
         // Verify if current runtime is ready for OTA
-        let _max_ota_size = Self::get_max_ota_size()?;
+        let max_ota_size = partitions::max_ota_size()?;
+        let running = updater::running_app()?;
+        let session = Self::init_session(&ble_params, &running, max_ota_size)?;
+        let service_data = ServiceData::new(
+            &running,
+            session.state().is_in_progress(),
+            ble_params.advertising.device_id()?,
+        );
         let esp_ota = EspOta::new()?;
+        let connection_policy = ble_params.connection_policy;
 
         // Initialize blueroid stack
-        lazy_static::initialize(&BT_DRIVER);
-        lazy_static::initialize(&GATT);
-        lazy_static::initialize(&GAP);
+        esp_bluedroid::init();
 
         // let (gat_sender, gat_receiver) = channel::<BleGapEvent>();
         // let (gatt_sender, gatt_receiver) = channel::<GattsEvent>();
 
+        let characteristics = OtaCharacteristics::new(&ble_uuids, ble_params.max_block_size);
+        let service = Arc::new(
+            characteristics.service(ble_uuids.service.clone(), ble_params.service_instance_id),
+        );
+
         let ota_ble = Arc::new(Self {
-            esp_ota,
-            ble_uuids,
+            session: Mutex::new(session),
+            stream: Mutex::new(StreamReceiver::new(
+                ble_params.stream_window,
+                ble_params.stream_ack_interval,
+            )),
+            prepared: Mutex::new(None),
             ble_params,
-            service_handle: Mutex::new(None),
+            service,
+            characteristics,
+            firmware_info: FirmwareInfo::new(&running, max_ota_size),
+            service_data: Mutex::new(service_data),
             gap_callbacks: Mutex::new(Vec::new()),
             gatt_callbacks: Mutex::new(Vec::new()),
-            connected_peers: Mutex::new(Vec::new()),
+            ota_callbacks: Mutex::new(Vec::new()),
+            update_policy: Mutex::new(None),
+            peers: Mutex::new(Peers::new(connection_policy)),
+            esp_ota: Mutex::new(esp_ota),
+            #[cfg(feature = "embassy")]
+            async_events: AsyncEvents::new(),
         });
         Self::init_ble(ota_ble.clone())?;
 
         Ok(ota_ble)
     }
 
+    /// Creates the OTA session, picking up a transfer interrupted by a reset
+    fn init_session(
+        ble_params: &BleParams,
+        running: &AppDescriptor,
+        capacity: u32,
+    ) -> Result<OtaSession<OtaUpdater, Option<NvsStore>>> {
+        let updater = OtaUpdater::new(partitions::next_update_partition()?, capacity);
+        let store = ble_params.nvs.clone().map(NvsStore::new).transpose()?;
+
+        let requirements = updater::image_requirements(running);
+        log::info!(
+            "Accepting images of {} for chip {:#06x} rev {}",
+            requirements.project_name(),
+            requirements.chip_id.0,
+            requirements.chip_revision
+        );
+
+        let mut session = match ble_params.public_key {
+            Some(public_key) => OtaSession::with_public_key(updater, public_key),
+            None => OtaSession::new(updater),
+        }
+        .with_image_requirements(requirements)
+        .with_downgrade_policy(ble_params.downgrade_policy, *running)
+        .with_store(store);
+
+        // Image pending verification may still be rolled back, it's committed once confirmed
+        if boot::slot_states().running != SlotState::PendingVerify {
+            if let Err(error) = session.commit_secure_version() {
+                log::error!("Failed to commit secure version: {:?}", error);
+            }
+        }
+
+        match session.restore() {
+            Ok(Some(record)) => log::info!(
+                "Resuming OTA session {}: {} of {} bytes committed",
+                record.session_id,
+                record.committed,
+                record.size
+            ),
+            Ok(None) => {}
+            Err(error) => log::error!("Failed to restore OTA session: {:?}", error),
+        }
+
+        Ok(session)
+    }
+
     /// Subscribe to BLE (GAP, GATT) events
     fn init_ble(ota_ble: Arc<Self>) -> Result<()> {
         let ota_ble_clone = ota_ble.clone();
-        GAP.subscribe(move |event| {
+        REGISTRY.subscribe_gap_event(move |event| {
             log::info!("GAP Event: {:?}", event);
 
             // First call user defined callbacks
@@ -128,16 +395,19 @@ impl OtaBle {
                 .lock()
                 .unwrap()
                 .iter_mut()
-                .for_each(|cb| cb(&event));
+                .for_each(|cb| cb(event));
 
             // Then call default event handler
-            if let Err(error) = ota_ble_clone.gap_event_handler(&event) {
+            if let Err(error) = ota_ble_clone.gap_event_handler(event) {
                 log::error!("Error handling GAP event: {:?}", error);
             }
         })?;
 
+        // Events of other apps sharing the stack are not routed here
+        let app = App::new(ota_ble.ble_params.ota_app_id).service(ota_ble.service.clone());
+
         let ota_ble_clone = ota_ble.clone();
-        GATT.subscribe(move |(gatt_if, event)| {
+        app.subscribe(move |gatt_if, event| {
             log::info!("GATT Event: {:?} {:?}", gatt_if, event);
 
             // First call user defined callbacks
@@ -146,19 +416,22 @@ impl OtaBle {
                 .lock()
                 .unwrap()
                 .iter_mut()
-                .for_each(|cb| cb(gatt_if, &event));
+                .for_each(|cb| cb(gatt_if, event));
 
             // Then call default event handler
-            if let Err(error) = ota_ble_clone.gatt_event_handler(gatt_if, &event) {
+            if let Err(error) = ota_ble_clone.gatt_event_handler(gatt_if, event) {
                 log::error!("Error handling GATT event: {:?}", error);
             }
-        })?;
+        });
 
-        GAP.set_adv_conf(&AdvConfiguration::default())?;
-        // GAP.set_conn_params_conf(addr, min_int_ms, max_int_ms, latency_ms, timeout_ms)
+        ota_ble.ble_params.advertising.configure(
+            ota_ble.service.uuid(),
+            &ota_ble.service_data.lock().unwrap(),
+        )?;
+        ota_ble.ble_params.link.set_local_mtu()?;
 
-        // Register OTA app
-        GATT.register_app(ota_ble.ble_params.ota_app_id)?;
+        // Register OTA app, the service is set up once the registration completes
+        REGISTRY.register(app)?;
 
         Ok(())
     }
@@ -170,6 +443,7 @@ impl OtaBle {
         self.gap_callbacks.lock().unwrap().push(Box::new(callback));
     }
 
+    /// Subscribes to the GATT events of the OTA app, events of other apps are not included
     pub fn subscribe_gatt_event<F>(&self, callback: F)
     where
         F: FnMut(u8, &GattsEvent) + Send + 'static,
@@ -177,6 +451,28 @@ impl OtaBle {
         self.gatt_callbacks.lock().unwrap().push(Box::new(callback));
     }
 
+    /// Subscribes to the progress of OTA updates
+    ///
+    /// Called from the BLE event handler, long running work should be moved elsewhere.
+    pub fn subscribe_ota_event<F>(&self, callback: F)
+    where
+        F: FnMut(&OtaEvent) + Send + 'static,
+    {
+        self.ota_callbacks.lock().unwrap().push(Box::new(callback));
+    }
+
+    /// Lets the application allow, deny or defer updates, every update is allowed otherwise
+    ///
+    /// `policy` is consulted on `StartTransfer`, once the app description of the image was
+    /// received and once the whole image was, before it's made bootable. Called from the BLE
+    /// event handler, it should answer quickly.
+    pub fn set_update_policy<F>(&self, policy: F)
+    where
+        F: FnMut(&UpdateRequest) -> PolicyDecision + Send + 'static,
+    {
+        *self.update_policy.lock().unwrap() = Some(Box::new(policy));
+    }
+
     fn gap_event_handler(&self, event: &BleGapEvent) -> Result<()> {
         // match event {
         //     // BleGapEvent::
@@ -187,81 +483,181 @@ impl OtaBle {
     }
 
     fn gatt_event_handler(&self, gatt_if: u8, event: &GattsEvent) -> Result<()> {
+        // Service setup, handle resolution and subscriptions were already handled by `App`
         match event {
-            GattsEvent::ServiceRegistered { status, app_id } => {
-                if *app_id == self.ble_params.ota_app_id {
-                    if *status != GattStatus::Ok {
-                        return Err(anyhow::anyhow!("Failed to register OTA GATT service"));
-                    }
-
-                    log::info!("OTA service registered");
-
-                    GATT.create_service(
-                        gatt_if,
-                        &GattServiceId {
-                            id: GattId {
-                                uuid: self.ble_uuids.service.clone(),
-                                inst_id: self.ble_params.service_instance_id,
-                            },
-                            is_primary: true,
-                        },
-                        8,
-                    )?;
-                }
-            }
-            GattsEvent::ServiceCreated {
-                status,
+            GattsEvent::ServiceStarted {
+                status: GattStatus::Ok,
                 service_handle,
-                service_id,
             } => {
-                if service_id.id.uuid == self.ble_uuids.service {
-                    if *status != GattStatus::Ok {
-                        return Err(anyhow::anyhow!("Failed to create OTA GATT service"));
-                    }
-
-                    log::info!("OTA service created");
+                if Some(*service_handle) == self.service.handle() {
+                    log::info!("OTA service started");
+
+                    // Clients reconnecting after a reset read where to resume from
+                    let session = self.session.lock().unwrap();
+                    let report = session.report(session.state().status());
+                    drop(session);
+
+                    self.characteristics.status.set_value(&report)?;
+                    self.characteristics
+                        .finished_upload
+                        .set_value(&FinishedUpload(false))?;
+                    self.characteristics
+                        .firmware_info
+                        .set_value(&self.firmware_info)?;
+                    self.update_slot_states()?;
+                }
+            }
+            GattsEvent::PeerConnected { conn_id, addr, .. } => {
+                let mut peers = self.peers.lock().unwrap();
+                peers.connect(*conn_id, *addr);
 
-                    self.service_handle.lock().unwrap().replace(*service_handle);
+                // The stack stops advertising once a peer connects
+                let advertise = peers.should_advertise();
+                drop(peers);
 
-                    // Create characteristics
-                    self.add_ote_characteristics()?;
+                if advertise {
+                    self.ble_params.advertising.start()?;
                 }
+
+                self.emit(OtaEvent::PeerConnected { conn_id: *conn_id });
             }
-            GattsEvent::CharacteristicAdded {
-                status,
-                attr_handle,
-                service_handle,
-                char_uuid,
-            } => {
-                if let Some(ota_handle) = self.service_handle.lock().unwrap().as_ref() {
-                    if *service_handle == *ota_handle {
-                        if *status != GattStatus::Ok {
-                            return Err(anyhow::anyhow!("Failed to add OTA characteristic"));
-                        }
+            GattsEvent::PeerDisconnected { conn_id, .. } => {
+                let mut peers = self.peers.lock().unwrap();
+                let was_advertising = peers.should_advertise();
+                peers.disconnect(*conn_id);
+                self.take_prepared_write(*conn_id);
 
-                        log::info!("OTA characteristic added: {:?}", char_uuid);
+                let advertise = !was_advertising && peers.should_advertise();
+                drop(peers);
 
-                        // GATT.start_service(*service_handle)?;
-                    }
+                if advertise {
+                    self.ble_params.advertising.start()?;
                 }
+
+                self.emit(OtaEvent::PeerDisconnected { conn_id: *conn_id });
             }
-            GattsEvent::ServiceStarted {
-                status,
-                service_handle,
+            GattsEvent::Mtu { conn_id, mtu } => {
+                log::info!("Peer {} negotiated an MTU of {} bytes", conn_id, mtu);
+
+                self.peers.lock().unwrap().set_mtu(*conn_id, *mtu);
+            }
+            GattsEvent::Read {
+                conn_id,
+                trans_id,
+                handle,
+                offset,
+                need_rsp,
+                ..
+            } => {
+                let characteristics = &self.characteristics;
+
+                // Attributes handled by the app are answered here, the others by the stack
+                let value = if Some(*handle) == characteristics.link_info.handle() {
+                    self.link_info(*conn_id).encode().to_vec()
+                } else if Some(*handle) == characteristics.total_file_size.handle() {
+                    // Stored by `write_total_file_size`, empty until it is written
+                    characteristics
+                        .total_file_size
+                        .characteristic()
+                        .get_value()?
+                } else if Some(*handle) == characteristics.file_hash.handle() {
+                    // Stored by `write_image_info`
+                    characteristics.file_hash.characteristic().get_value()?
+                } else {
+                    return Ok(());
+                };
+
+                if *need_rsp {
+                    self.reply_read(gatt_if, *conn_id, *trans_id, *handle, *offset, &value)?;
+                }
+            }
+            GattsEvent::Write {
+                conn_id,
+                trans_id,
+                handle,
+                offset,
+                need_rsp,
+                is_prep,
+                value,
+                ..
             } => {
-                if let Some(ota_handle) = self.service_handle.lock().unwrap().as_ref() {
-                    if *service_handle == *ota_handle {
-                        if *status != GattStatus::Ok {
-                            return Err(anyhow::anyhow!("Failed to start OTA service"));
+                let characteristics = &self.characteristics;
+
+                if Some(*handle) == characteristics.file_block.handle() {
+                    let result = if *is_prep {
+                        // Blocks are expected to fit into a single ATT packet
+                        Err(OtaError::InvalidLength)
+                    } else {
+                        self.check_access(*conn_id, Access::Transfer)
+                            .and_then(|()| self.write_file_block(gatt_if, value))
+                    };
+
+                    self.reply(gatt_if, *conn_id, *trans_id, *need_rsp, result)?;
+                } else if Some(*handle) == characteristics.command.handle() {
+                    let result = self.run_command(gatt_if, *conn_id, value);
+
+                    self.reply(gatt_if, *conn_id, *trans_id, *need_rsp, result)?;
+                } else if Some(*handle) == characteristics.stream_block.handle() {
+                    let result = if *is_prep {
+                        Err(OtaError::InvalidLength)
+                    } else {
+                        self.check_access(*conn_id, Access::Transfer)
+                            .and_then(|()| self.write_stream_block(gatt_if, value))
+                    };
+
+                    self.reply(gatt_if, *conn_id, *trans_id, *need_rsp, result)?;
+                } else if Some(*handle) == characteristics.total_file_size.handle() {
+                    let result = if *is_prep {
+                        Err(OtaError::InvalidLength)
+                    } else {
+                        self.check_access(*conn_id, Access::Transfer)
+                            .and_then(|()| self.write_total_file_size(value))
+                    };
+
+                    self.reply(gatt_if, *conn_id, *trans_id, *need_rsp, result)?;
+                } else if Some(*handle) == characteristics.file_hash.handle()
+                    || Some(*handle) == characteristics.file_signature.handle()
+                {
+                    let access = self.check_access(*conn_id, Access::Transfer);
+
+                    if *is_prep {
+                        // Both are longer than a write at the default MTU, clients split them
+                        match access
+                            .and_then(|()| self.prepare_write(*conn_id, *handle, *offset, value))
+                        {
+                            // The queued part is echoed, as the spec requires
+                            Ok(()) if *need_rsp => self.reply_value(
+                                gatt_if, *conn_id, *trans_id, *handle, *offset, value,
+                            )?,
+                            result => {
+                                self.reply(gatt_if, *conn_id, *trans_id, *need_rsp, result)?
+                            }
                         }
+                    } else {
+                        let result = access.and_then(|()| self.write_image_info(*handle, value));
 
-                        log::info!("OTA service started");
+                        self.reply(gatt_if, *conn_id, *trans_id, *need_rsp, result)?;
                     }
                 }
             }
-            GattsEvent::PeerConnected { .. } => {
-                // TODO: check if max connections reached before starting advertising
-                // GAP.start_advertising().unwrap();
+            GattsEvent::ExecWrite {
+                conn_id,
+                trans_id,
+                canceled,
+                ..
+            } => {
+                // Every writable attribute is handled by the app, so is every execute write
+                let prepared = self.take_prepared_write(*conn_id);
+
+                // Transfer may have been taken over by another peer since the write was queued
+                let result = match prepared {
+                    Some(prepared) if !*canceled => self
+                        .check_access(*conn_id, Access::Transfer)
+                        .and_then(|()| self.write_image_info(prepared.handle, &prepared.value)),
+                    _ => Ok(()),
+                };
+
+                self.reply(gatt_if, *conn_id, *trans_id, true, result)?;
             }
             _ => {}
         }
@@ -269,128 +665,884 @@ impl OtaBle {
         Ok(())
     }
 
-    pub fn start_service(&self) -> Result<()> {
-        GAP.start_advertising()?;
+    /// Answers a write request handled by the app, failures are also reported through `status`
+    fn reply(
+        &self,
+        gatt_if: u8,
+        conn_id: u16,
+        trans_id: u32,
+        need_rsp: bool,
+        result: Result<(), OtaError>,
+    ) -> Result<()> {
+        let status = match result {
+            Ok(()) => GattStatus::Ok,
+            // Only the refused peer is told, the others keep following the owner's transfer
+            Err(error @ OtaError::Busy { .. }) => {
+                self.report_busy(gatt_if, conn_id, &error);
+                error.gatt_status()
+            }
+            Err(error) => {
+                self.report_error(gatt_if, &error);
+                error.gatt_status()
+            }
+        };
+
+        if need_rsp {
+            GATT.send_response(gatt_if, conn_id, trans_id, status, None)?;
+        }
 
         Ok(())
     }
 
-    fn add_ote_characteristics(&self) -> Result<()> {
-        let Some(service_handle) = *self.service_handle.lock().unwrap() else {
-            return Err(anyhow::anyhow!("Service handle not set yet"));
+    fn report_error(&self, gatt_if: u8, error: &OtaError) {
+        log::error!("OTA request failed: {:?}", error);
+
+        let report = StatusReport {
+            reason: error.reason(),
+            ..self.session.lock().unwrap().report(error.ota_status())
         };
 
-        GATT.add_characteristic(
-            service_handle,
-            &GattCharacteristic {
-                uuid: self.ble_uuids.file_block.clone(),
-                permissions: Permission::Read | Permission::Write,
-                properties: Property::Read | Property::Write,
-                max_len: self.ble_params.max_block_size,
-                auto_rsp: AutoResponse::ByGatt,
-            },
-            &[],
-        )?;
+        if let Err(error) = self.notify_status(gatt_if, &report) {
+            log::error!("Failed to update OTA status: {:?}", error);
+        }
+    }
 
-        GATT.add_characteristic(
-            service_handle,
-            &GattCharacteristic {
-                uuid: self.ble_uuids.file_hash.clone(),
-                permissions: Permission::Read | Permission::Write,
-                properties: Property::Read | Property::Write,
-                max_len: 32,
-                auto_rsp: AutoResponse::ByGatt,
-            },
-            &[],
-        )?;
+    /// Value of `link_info` for `conn_id`, each peer negotiates its own MTU
+    fn link_info(&self, conn_id: u16) -> LinkInfo {
+        LinkInfo {
+            mtu: self.mtu(conn_id).unwrap_or(DEFAULT_MTU),
+            max_block_size: self.ble_params.max_block_size as u16,
+        }
+    }
 
-        GATT.add_characteristic(
-            service_handle,
-            &GattCharacteristic {
-                uuid: self.ble_uuids.finished_upload.clone(),
-                permissions: Permission::Read.into(),
-                properties: Property::Read | Property::Notify,
-                max_len: 8,
-                auto_rsp: AutoResponse::ByGatt,
-            },
-            &[],
-        )?;
+    /// Answers a read of an attribute handled by the app with `value`, from `offset` on for the
+    /// follow-up reads of a long value
+    fn reply_read(
+        &self,
+        gatt_if: u8,
+        conn_id: u16,
+        trans_id: u32,
+        handle: u16,
+        offset: u16,
+        value: &[u8],
+    ) -> Result<()> {
+        let Some(value) = value.get(offset as usize..) else {
+            GATT.send_response(gatt_if, conn_id, trans_id, GattStatus::InvalidOffset, None)?;
+
+            return Ok(());
+        };
 
-        // GATT.set_attr(attr_handle, data)
-        GATT.add_characteristic(
-            service_handle,
-            &GattCharacteristic {
-                uuid: self.ble_uuids.status.clone(),
-                permissions: Permission::Read.into(),
-                properties: Property::Read | Property::Notify,
-                max_len: 1,
-                auto_rsp: AutoResponse::ByGatt,
-            },
-            &[],
-        )?;
+        self.reply_value(gatt_if, conn_id, trans_id, handle, offset, value)
+    }
 
-        GATT.add_characteristic(
-            service_handle,
-            &GattCharacteristic {
-                uuid: self.ble_uuids.total_file_size.clone(),
-                permissions: Permission::Read.into(),
-                properties: Property::Read | Property::Notify,
-                max_len: size_of::<usize>(),
-                auto_rsp: AutoResponse::ByGatt,
-            },
-            &[],
-        )?;
+    /// Answers a request with the part of the value of `handle` starting at `offset`
+    fn reply_value(
+        &self,
+        gatt_if: u8,
+        conn_id: u16,
+        trans_id: u32,
+        handle: u16,
+        offset: u16,
+        value: &[u8],
+    ) -> Result<()> {
+        let mut response = GattResponse::new();
+        response
+            .attr_handle(handle)
+            .auth_req(0)
+            .offset(offset)
+            .value(value)?;
+
+        GATT.send_response(gatt_if, conn_id, trans_id, GattStatus::Ok, Some(&response))?;
+
+        Ok(())
+    }
+
+    fn report_busy(&self, gatt_if: u8, conn_id: u16, error: &OtaError) {
+        log::warn!("OTA request of peer {} refused: {:?}", conn_id, error);
+
+        let report = self.session.lock().unwrap().report(error.ota_status());
+
+        if let Err(error) = self
+            .characteristics
+            .status
+            .notify_peer(gatt_if, conn_id, &report)
+        {
+            log::error!("Failed to notify OTA status: {:?}", error);
+        }
+    }
+
+    /// Refuses writes of `conn_id` while another peer owns the transfer, see `ConnectionPolicy`
+    fn check_access(&self, conn_id: u16, access: Access) -> Result<(), OtaError> {
+        self.peers
+            .lock()
+            .unwrap()
+            .check(conn_id, access)
+            .map_err(|owner| OtaError::Busy { owner })
+    }
+
+    /// Stores the size of the next image, refusing images which don't fit into an OTA slot
+    fn write_total_file_size(&self, value: &[u8]) -> Result<(), OtaError> {
+        let size = TotalFileSize::decode(value).map_err(|_| OtaError::InvalidLength)?;
+        let capacity = self.firmware_info.max_ota_size;
+
+        if size.0 > capacity {
+            return Err(OtaError::Session(SessionError::TooLarge {
+                size: size.0,
+                capacity,
+            }));
+        }
+
+        self.characteristics
+            .total_file_size
+            .set_value(&size)
+            .map_err(OtaError::Gatt)?;
+
+        log::info!("Total file size set to: {}", size.0);
 
         Ok(())
     }
 
-    /// Verifies that partitions table was correctly set up
-    /// and return the maximum allowed OTA size (detected by the smallest OTA partition size)
-    /// If no OTA partitions are found, an error is returned
-    fn get_max_ota_size() -> Result<usize> {
-        log::info!("Checking OTA partitions");
-        let mut max_ota_size: usize = 0;
-
-        unsafe {
-            // Iterate over all OTA partitions
-            let mut it = esp_partition_find(
-                esp_partition_type_t_ESP_PARTITION_TYPE_APP,
-                esp_partition_subtype_t_ESP_PARTITION_SUBTYPE_ANY,
-                std::ptr::null(),
-            );
-
-            while !it.is_null() {
-                let partition = esp_partition_get(it);
-
-                if !partition.is_null() {
-                    let label = (*partition).label.as_ptr();
-                    let size = (*partition).size as usize;
-
-                    log::info!(
-                        "Found OTA partition: {:?}, size: {}",
-                        std::ffi::CStr::from_ptr(label).to_str()?,
-                        size
-                    );
-
-                    if size > max_ota_size {
-                        max_ota_size = size;
+    /// Stores the `file_hash` or `file_signature` of the next image, depending on `handle`
+    fn write_image_info(&self, handle: u16, value: &[u8]) -> Result<(), OtaError> {
+        let characteristics = &self.characteristics;
+
+        if Some(handle) == characteristics.file_hash.handle() {
+            let hash = ImageHash::decode(value).map_err(|_| OtaError::InvalidLength)?;
+
+            characteristics
+                .file_hash
+                .set_value(&hash)
+                .map_err(OtaError::Gatt)?;
+
+            log::info!("File hash set to: {:02x?}", hash.0);
+        } else {
+            let signature = ImageSignature::decode(value).map_err(|_| OtaError::InvalidLength)?;
+
+            characteristics
+                .file_signature
+                .set_value(&signature)
+                .map_err(OtaError::Gatt)?;
+
+            log::info!("File signature set");
+        }
+
+        Ok(())
+    }
+
+    /// Queues a part of a long `file_hash` or `file_signature` write of `conn_id`
+    ///
+    /// Parts are expected in order, a long write starting at offset 0 replaces the queued one.
+    fn prepare_write(
+        &self,
+        conn_id: u16,
+        handle: u16,
+        offset: u16,
+        value: &[u8],
+    ) -> Result<(), OtaError> {
+        let mut prepared = self.prepared.lock().unwrap();
+
+        if offset == 0 {
+            *prepared = Some(PreparedWrite {
+                conn_id,
+                handle,
+                value: Vec::new(),
+            });
+        }
+
+        match prepared.as_mut() {
+            Some(prepared)
+                if prepared.conn_id == conn_id
+                    && prepared.handle == handle
+                    && prepared.value.len() == offset as usize
+                    && prepared.value.len() + value.len() <= ImageSignature::LEN =>
+            {
+                prepared.value.extend_from_slice(value);
+
+                Ok(())
+            }
+            _ => Err(OtaError::InvalidLength),
+        }
+    }
+
+    /// Takes the long write queued by `conn_id`, if any
+    fn take_prepared_write(&self, conn_id: u16) -> Option<PreparedWrite> {
+        let mut prepared = self.prepared.lock().unwrap();
+
+        match prepared.as_ref() {
+            Some(queued) if queued.conn_id == conn_id => prepared.take(),
+            _ => None,
+        }
+    }
+
+    /// Drops the long write queued by a peer other than `conn_id`, once `conn_id` owns the
+    /// transfer
+    fn drop_prepared_write_of_others(&self, conn_id: u16) {
+        let mut prepared = self.prepared.lock().unwrap();
+
+        if prepared
+            .as_ref()
+            .is_some_and(|queued| queued.conn_id != conn_id)
+        {
+            *prepared = None;
+        }
+    }
+
+    /// Decodes and runs a single `command` write
+    fn run_command(&self, gatt_if: u8, conn_id: u16, value: &[u8]) -> Result<(), OtaError> {
+        let command = Command::decode(value).map_err(|error| match error {
+            DecodeError::UnknownCommand(opcode) => OtaError::UnknownCommand(opcode),
+            _ => OtaError::InvalidLength,
+        })?;
+
+        log::info!("OTA command: {:?}", command);
+
+        let access = match command {
+            Command::StartTransfer => Access::Transfer,
+            Command::ClearTransfer | Command::ResetDevice | Command::StartForceTransfer => {
+                Access::Control
+            }
+        };
+        self.check_access(conn_id, access)?;
+
+        match command {
+            Command::StartTransfer => self.start_transfer(gatt_if, conn_id, false),
+            Command::ClearTransfer => self.clear_transfer(gatt_if),
+            Command::ResetDevice => {
+                self.schedule_reset();
+                Ok(())
+            }
+            Command::StartForceTransfer => self.start_transfer(gatt_if, conn_id, true),
+        }
+    }
+
+    /// Initiates a new update of the image described by `total_file_size`, `file_hash` and
+    /// `file_signature` for `conn_id`, `force` drops the ongoing one first
+    fn start_transfer(&self, gatt_if: u8, conn_id: u16, force: bool) -> Result<(), OtaError> {
+        let characteristics = &self.characteristics;
+
+        // Values are stored by `write_total_file_size` and `write_image_info`, an attribute which
+        // was never written is empty
+        let image = ImageInfo {
+            size: characteristics
+                .total_file_size
+                .get_value()
+                .map_err(|_| OtaError::UnknownSize)?
+                .0,
+            hash: characteristics
+                .file_hash
+                .get_value()
+                .map_err(|_| OtaError::UnknownHash)?,
+            signature: characteristics.file_signature.get_value().ok(),
+        };
+
+        log::info!("Starting OTA update, image size: {}", image.size);
+
+        self.consult_policy(&UpdateRequest {
+            stage: PolicyStage::Start,
+            size: image.size,
+            hash: image.hash,
+            version: None,
+        })?;
+
+        let started = tolerate_store_error(self.session.lock().unwrap().start(image, force), 0);
+        let offset = started.map_err(|error| self.session_error(error))?;
+
+        if offset > 0 {
+            log::info!("Resuming OTA update at {} bytes", offset);
+        }
+
+        // Transfers resumed past the app description aren't reviewed by `write_block` again
+        let state = self.session.lock().unwrap().state();
+        self.review_headers(0, state)?;
+
+        let mut peers = self.peers.lock().unwrap();
+        peers.take_transfer(conn_id);
+        self.drop_prepared_write_of_others(conn_id);
+        let owner = peers.transfer_owner().copied();
+        drop(peers);
+
+        if let Some(owner) = owner {
+            if let Err(error) = self.ble_params.link.start_transfer(owner.addr) {
+                log::warn!(
+                    "Failed to speed up the link of peer {}: {:?}",
+                    conn_id,
+                    error
+                );
+            }
+        }
+
+        // Streams restart at the offset of the session
+        let mut stream = self.stream.lock().unwrap();
+        stream.reset();
+        let ack = stream.ack();
+        drop(stream);
+
+        self.characteristics
+            .stream_ack
+            .set_value(&ack)
+            .map_err(OtaError::Gatt)?;
+        self.characteristics
+            .finished_upload
+            .set_value(&FinishedUpload(false))
+            .map_err(OtaError::Gatt)?;
+        self.set_status(gatt_if, Status::Receiving)?;
+        self.emit(OtaEvent::TransferStarted { size: image.size });
+
+        Ok(())
+    }
+
+    fn clear_transfer(&self, gatt_if: u8) -> Result<(), OtaError> {
+        log::info!("Clearing OTA update");
+
+        self.session
+            .lock()
+            .unwrap()
+            .clear()
+            .map_err(OtaError::Session)?;
+
+        self.set_status(gatt_if, Status::Idle)?;
+        self.emit(OtaEvent::Aborted {
+            reason: Status::Idle,
+        });
+
+        Ok(())
+    }
+
+    /// Resets the device after a short delay, so the command response still reaches the client
+    fn schedule_reset(&self) {
+        self.emit(OtaEvent::RebootScheduled);
+
+        std::thread::spawn(|| {
+            log::info!("Resetting device");
+
+            esp_idf_svc::hal::delay::FreeRtos::delay_ms(500);
+            esp_idf_svc::hal::reset::restart();
+        });
+    }
+
+    /// Applies a single `file_block` write
+    ///
+    /// Once the whole image was written, the update is activated and `finished_upload` notified.
+    fn write_file_block(&self, gatt_if: u8, value: &[u8]) -> Result<(), OtaError> {
+        let block = Block::decode(value).map_err(|_| OtaError::InvalidLength)?;
+
+        let mut session = self.session.lock().unwrap();
+
+        let previously_received = session.state().received();
+        let written = tolerate_store_error(
+            session.write_block(block.header.offset, block.data),
+            BlockOutcome::Accepted,
+        );
+        let state = session.state();
+        drop(session);
+
+        // Events are emitted with the session unlocked, subscribers may call into `OtaBle`
+        let outcome = written.map_err(|error| self.session_error(error))?;
+        self.report_progress(previously_received, state);
+        self.review_headers(previously_received, state)?;
+
+        if outcome == BlockOutcome::Complete {
+            self.activate(gatt_if)?;
+        }
+
+        Ok(())
+    }
+
+    /// Applies a single `stream_block` write, blocks past a gap are buffered until it's filled
+    ///
+    /// Acks are notified through `stream_ack`, the block completing the image is always acked.
+    fn write_stream_block(&self, gatt_if: u8, value: &[u8]) -> Result<(), OtaError> {
+        let block = StreamBlock::decode(value).map_err(|_| OtaError::InvalidLength)?;
+
+        let mut stream = self.stream.lock().unwrap();
+        let mut session = self.session.lock().unwrap();
+
+        let previously_received = session.state().received();
+        let received = stream.receive(block.header.seq, block.data, |data| {
+            let offset = session.state().received();
+            tolerate_store_error(session.write_block(offset, data), BlockOutcome::Accepted)
+                .map(|_| ())
+        });
+        let state = session.state();
+        drop(session);
+
+        let complete = matches!(state, SessionState::Verifying { .. });
+        let ack = received.map(|ack| ack.or_else(|| complete.then(|| stream.ack())));
+        drop(stream);
+
+        // Events are emitted with the session unlocked, subscribers may call into `OtaBle`
+        let ack = ack.map_err(|error| self.session_error(error))?;
+        self.report_progress(previously_received, state);
+        self.review_headers(previously_received, state)?;
+
+        if let Some(ack) = ack {
+            self.characteristics
+                .stream_ack
+                .notify(gatt_if, &ack)
+                .map_err(OtaError::Gatt)?;
+        }
+
+        if complete {
+            self.activate(gatt_if)?;
+        }
+
+        Ok(())
+    }
+
+    /// Consults the `UpdatePolicy` at `PolicyStage::Headers` once the app description of the
+    /// image was written, the transfer is dropped unless it's allowed
+    fn review_headers(
+        &self,
+        previously_received: u32,
+        state: SessionState,
+    ) -> Result<(), OtaError> {
+        let (size, received) = match state {
+            SessionState::Receiving { size, received } => (size, received),
+            SessionState::Verifying { size } => (size, size),
+            _ => return Ok(()),
+        };
+        if previously_received >= IMAGE_PREFIX_LEN as u32 || received < IMAGE_PREFIX_LEN as u32 {
+            return Ok(());
+        }
+
+        let session = self.session.lock().unwrap();
+        let Some(hash) = session.expected_hash() else {
+            return Err(OtaError::Session(SessionError::NoTransfer));
+        };
+        let version = session
+            .sink()
+            .image_version()
+            .map_err(|error| log::warn!("Failed to read image version: {:?}", error))
+            .ok();
+        drop(session);
+
+        let request = UpdateRequest {
+            stage: PolicyStage::Headers,
+            size,
+            hash,
+            version,
+        };
+
+        self.consult_policy(&request).map_err(|error| {
+            if let Err(error) = self.session.lock().unwrap().clear() {
+                log::warn!("Failed to drop refused transfer: {:?}", error);
+            }
+
+            self.emit(OtaEvent::Aborted {
+                reason: error.ota_status(),
+            });
+
+            error
+        })
+    }
+
+    /// Verifies the received image and makes it bootable, once the `UpdatePolicy` allows it
+    fn activate(&self, gatt_if: u8) -> Result<(), OtaError> {
+        let session = self.session.lock().unwrap();
+        let (SessionState::Verifying { size }, Some(hash)) =
+            (session.state(), session.expected_hash())
+        else {
+            return Err(OtaError::Session(SessionError::NoTransfer));
+        };
+        let version = session
+            .sink()
+            .image_version()
+            .map_err(|error| log::warn!("Failed to read image version: {:?}", error))
+            .ok();
+        drop(session);
+
+        let request = UpdateRequest {
+            stage: PolicyStage::Activation,
+            size,
+            // Hash the transfer was started with, `file_hash` may have been rewritten since
+            hash,
+            version,
+        };
+
+        if let Err(error) = self.consult_policy(&request) {
+            if let OtaError::Denied(_) = error {
+                self.session
+                    .lock()
+                    .unwrap()
+                    .clear()
+                    .map_err(OtaError::Session)?;
+
+                self.emit(OtaEvent::Aborted {
+                    reason: Status::UpdateDenied,
+                });
+            }
+
+            return Err(error);
+        }
+
+        self.finish_update(gatt_if)
+    }
+
+    fn finish_update(&self, gatt_if: u8) -> Result<(), OtaError> {
+        let finished = tolerate_store_error(self.session.lock().unwrap().finish(), ());
+        finished.map_err(|error| {
+            self.emit(OtaEvent::VerificationFailed);
+            self.session_error(error)
+        })?;
+
+        log::info!("OTA update completed");
+
+        self.set_status(gatt_if, Status::Finished)?;
+        self.characteristics
+            .finished_upload
+            .notify(gatt_if, &FinishedUpload(true))
+            .map_err(OtaError::Gatt)?;
+
+        self.emit(OtaEvent::UpdateReady);
+
+        Ok(())
+    }
+
+    /// Activates an update the `UpdatePolicy` deferred, without consulting it again
+    ///
+    /// Meant to be called once the job which made the application defer the update is over.
+    pub fn activate_deferred_update(&self) -> Result<()> {
+        let gatt_if = self
+            .service
+            .gatt_if()
+            .ok_or_else(|| anyhow::anyhow!("OTA service is not registered"))?;
+
+        let state = self.session.lock().unwrap().state();
+        if !matches!(state, SessionState::Verifying { .. }) {
+            return Err(anyhow::anyhow!("No update awaiting activation"));
+        }
+
+        self.finish_update(gatt_if).map_err(|error| {
+            self.report_error(gatt_if, &error);
+            anyhow::anyhow!("Failed to activate deferred update: {:?}", error)
+        })
+    }
+
+    /// Asks the `UpdatePolicy` whether `request` may proceed
+    fn consult_policy(&self, request: &UpdateRequest) -> Result<(), OtaError> {
+        let decision = match self.update_policy.lock().unwrap().as_mut() {
+            Some(policy) => policy(request),
+            None => PolicyDecision::Allow,
+        };
+
+        match decision {
+            PolicyDecision::Allow => Ok(()),
+            PolicyDecision::Deny { reason } => {
+                log::warn!("Update denied at {:?}, reason: {}", request.stage, reason);
+                Err(OtaError::Denied(reason))
+            }
+            PolicyDecision::Defer => {
+                log::warn!("Update deferred at {:?}", request.stage);
+                Err(OtaError::Deferred)
+            }
+        }
+    }
+
+    /// Emits `OtaEvent::Progress` every `PROGRESS_INTERVAL` bytes and once the image is received
+    fn report_progress(&self, previously_received: u32, state: SessionState) {
+        let (received, total) = match state {
+            SessionState::Receiving { size, received } => (received, size),
+            SessionState::Verifying { size } => (size, size),
+            _ => return,
+        };
+
+        if received / PROGRESS_INTERVAL != previously_received / PROGRESS_INTERVAL
+            || received == total
+        {
+            self.emit(OtaEvent::Progress { received, total });
+        }
+    }
+
+    /// Wraps an error of the session, reporting the transfer as aborted if it failed it
+    fn session_error(&self, error: SessionError<anyhow::Error>) -> OtaError {
+        if matches!(
+            error,
+            SessionError::HashMismatch
+                | SessionError::InvalidImage(_)
+                | SessionError::Downgrade(_)
+                | SessionError::Sink(_)
+        ) {
+            self.emit(OtaEvent::Aborted {
+                reason: error.status(),
+            });
+        }
+
+        OtaError::Session(error)
+    }
+
+    fn emit(&self, event: OtaEvent) {
+        log::info!("OTA event: {:?}", event);
+
+        self.ota_callbacks
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .for_each(|cb| cb(&event));
+
+        #[cfg(feature = "embassy")]
+        self.async_events.push(event);
+
+        let transfer_in_progress = match event {
+            OtaEvent::TransferStarted { .. } => true,
+            OtaEvent::UpdateReady | OtaEvent::Aborted { .. } => {
+                self.end_transfer();
+                false
+            }
+            _ => return,
+        };
+
+        if let Err(error) = self.advertise_transfer(transfer_in_progress) {
+            log::error!("Failed to update advertised service data: {:?}", error);
+        }
+    }
+
+    /// Releases the transfer and relaxes the link of its owner, if it's still connected
+    fn end_transfer(&self) {
+        let mut peers = self.peers.lock().unwrap();
+        let owner = peers.transfer_owner().copied();
+        peers.release_transfer();
+        drop(peers);
+
+        if let Some(owner) = owner {
+            if let Err(error) = self.ble_params.link.end_transfer(owner.addr) {
+                log::warn!(
+                    "Failed to relax the link of peer {}: {:?}",
+                    owner.conn_id,
+                    error
+                );
+            }
+        }
+    }
+
+    /// Updates the transfer flag of the advertised `ServiceData`
+    fn advertise_transfer(&self, transfer_in_progress: bool) -> Result<()> {
+        let mut service_data = self.service_data.lock().unwrap();
+        if service_data.transfer_in_progress == transfer_in_progress {
+            return Ok(());
+        }
+
+        service_data.transfer_in_progress = transfer_in_progress;
+
+        self.ble_params
+            .advertising
+            .set_service_data(self.service.uuid(), &service_data)
+    }
+
+    /// Updates `status` with the progress of the session and notifies subscribed peers about it
+    fn set_status(&self, gatt_if: u8, status: Status) -> Result<(), OtaError> {
+        let report = self.session.lock().unwrap().report(status);
+
+        self.notify_status(gatt_if, &report)
+    }
+
+    fn notify_status(&self, gatt_if: u8, report: &StatusReport) -> Result<(), OtaError> {
+        self.characteristics
+            .status
+            .notify(gatt_if, report)
+            .map_err(OtaError::Gatt)
+    }
+
+    /// States of the running slot and of the one the next update is written to
+    pub fn slot_states(&self) -> SlotStates {
+        boot::slot_states()
+    }
+
+    /// ATT MTU negotiated by a connected peer, as exposed by `link_info`
+    pub fn mtu(&self, conn_id: u16) -> Option<u16> {
+        self.peers.lock().unwrap().get(conn_id).map(|peer| peer.mtu)
+    }
+
+    /// Running app and the largest image it accepts, as exposed by `firmware_info`
+    pub fn firmware_info(&self) -> &FirmwareInfo {
+        &self.firmware_info
+    }
+
+    /// OTA slots of the partition table with their current states
+    pub fn ota_slots(&self) -> Vec<OtaSlot> {
+        partitions::ota_slots()
+    }
+
+    /// Confirms the running image once `check` passes, rolling back if it doesn't in time
+    ///
+    /// Only has an effect on the first boot after an update, while the image is pending
+    /// verification: `check` is then polled until it returns `true` and the image is marked
+    /// valid. If it doesn't pass within `BleParams::confirm_window`, the image is marked invalid
+    /// and the device reboots into the previous one. Requires
+    /// `CONFIG_BOOTLOADER_APP_ROLLBACK_ENABLE`.
+    pub fn confirm_boot<F>(self: &Arc<Self>, mut check: F) -> Result<()>
+    where
+        F: FnMut() -> bool + Send + 'static,
+    {
+        if boot::slot_states().running != SlotState::PendingVerify {
+            log::info!("Running image doesn't need confirmation");
+            return Ok(());
+        }
+
+        let ota_ble = self.clone();
+        let window = self.ble_params.confirm_window;
+
+        std::thread::Builder::new()
+            .name("ota-confirm".into())
+            .stack_size(8 * 1024)
+            .spawn(move || {
+                let started = Instant::now();
+
+                while started.elapsed() < window {
+                    if check() {
+                        if let Err(error) = ota_ble.mark_running_slot_valid() {
+                            log::error!("Failed to confirm running image: {:?}", error);
+                        }
+
+                        return;
                     }
+
+                    FreeRtos::delay_ms(HEALTH_CHECK_INTERVAL_MS);
                 }
 
-                it = esp_partition_next(it);
-            }
+                log::error!("Health check didn't pass within {:?}, rolling back", window);
 
-            esp_partition_iterator_release(it);
+                // Only returns if there is no image to roll back to
+                let error = ota_ble
+                    .esp_ota
+                    .lock()
+                    .unwrap()
+                    .mark_running_slot_invalid_and_reboot();
+                log::error!("Failed to roll back: {:?}", error);
+            })?;
+
+        Ok(())
+    }
+
+    fn mark_running_slot_valid(&self) -> Result<()> {
+        self.esp_ota.lock().unwrap().mark_running_slot_valid()?;
+
+        log::info!("Running image confirmed");
+
+        if let Err(error) = self.session.lock().unwrap().commit_secure_version() {
+            log::error!("Failed to commit secure version: {:?}", error);
         }
 
-        if max_ota_size == 0 {
-            log::error!("No OTA partitions found");
-            Err(anyhow::anyhow!(
-                "No OTA partitions found, verify partition table"
-            ))
-        } else {
-            log::info!("Max OTA size: {}", max_ota_size);
-            Ok(max_ota_size)
+        self.update_slot_states()
+    }
+
+    fn update_slot_states(&self) -> Result<()> {
+        // Set once the service is started otherwise
+        if self.characteristics.slot_states.handle().is_some() {
+            self.characteristics
+                .slot_states
+                .set_value(&boot::slot_states())?;
+        }
+
+        Ok(())
+    }
+
+    pub fn start_service(&self) -> Result<()> {
+        self.ble_params.advertising.start()?;
+
+        Ok(())
+    }
+}
+
+/// Value of a long write, queued by prepare write requests until the execute write request
+struct PreparedWrite {
+    conn_id: u16,
+    handle: u16,
+    value: Vec<u8>,
+}
+
+/// Passes `result` through, unless the session store failed after the change was applied
+///
+/// A failed checkpoint only means the transfer can't be resumed from it: a started transfer is
+/// reported as `applied`, so is a block, so the client doesn't retransmit it at an offset the
+/// session already moved past.
+fn tolerate_store_error<T>(
+    result: Result<T, SessionError<anyhow::Error>>,
+    applied: T,
+) -> Result<T, SessionError<anyhow::Error>> {
+    match result {
+        Err(SessionError::Store(error)) => {
+            log::warn!("Failed to persist the transfer: {:?}", error);
+
+            Ok(applied)
+        }
+        result => result,
+    }
+}
+
+/// Reasons a `command` or `file_block` write can't be applied
+#[derive(Debug)]
+enum OtaError {
+    /// Value is too short or too long for the characteristic
+    InvalidLength,
+    /// Command opcode is not a known `Command`
+    UnknownCommand(u8),
+    /// `total_file_size` wasn't written before the transfer
+    UnknownSize,
+    /// `file_hash` wasn't written before the transfer
+    UnknownHash,
+    /// OTA session rejected the request
+    Session(SessionError<anyhow::Error>),
+    /// GATT server failed to update a characteristic
+    Gatt(anyhow::Error),
+    /// `UpdatePolicy` denied the update with an application defined reason
+    Denied(u8),
+    /// `UpdatePolicy` deferred the update
+    Deferred,
+    /// Another peer owns the transfer, see `ConnectionPolicy`
+    Busy { owner: u16 },
+}
+
+impl OtaError {
+    /// Status of the write response
+    fn gatt_status(&self) -> GattStatus {
+        match self {
+            Self::InvalidLength => GattStatus::InvalidAttrLen,
+            Self::UnknownCommand(_) => GattStatus::ReqNotSupported,
+            Self::UnknownSize | Self::UnknownHash => GattStatus::WrongState,
+            Self::Session(error) => match error {
+                SessionError::TransferInProgress => GattStatus::Busy,
+                SessionError::NoTransfer | SessionError::Incomplete { .. } => {
+                    GattStatus::WrongState
+                }
+                SessionError::Empty => GattStatus::InvalidAttrLen,
+                SessionError::TooLarge { .. } | SessionError::Overflow => GattStatus::OutOfRange,
+                SessionError::InvalidOffset { .. } => GattStatus::InvalidOffset,
+                SessionError::MissingSignature | SessionError::InvalidSignature => {
+                    GattStatus::InsufAuthentication
+                }
+                SessionError::HashMismatch
+                | SessionError::InvalidImage(_)
+                | SessionError::Downgrade(_)
+                | SessionError::Sink(_) => GattStatus::Error,
+                // Only reaches the client when clearing a transfer, the ones of a started one
+                // are tolerated by `tolerate_store_error`
+                SessionError::Store(_) => GattStatus::Error,
+            },
+            Self::Gatt(_) | Self::Denied(_) => GattStatus::Error,
+            Self::Deferred | Self::Busy { .. } => GattStatus::Busy,
+        }
+    }
+
+    /// Value reported through the `status` characteristic
+    fn ota_status(&self) -> Status {
+        match self {
+            Self::InvalidLength => Status::InvalidLength,
+            Self::UnknownCommand(_) => Status::UnknownCommand,
+            Self::UnknownSize => Status::UnknownFileSize,
+            Self::UnknownHash => Status::UnknownFileHash,
+            Self::Session(error) => error.status(),
+            Self::Gatt(_) => Status::UpdateFailed,
+            Self::Denied(_) => Status::UpdateDenied,
+            Self::Deferred => Status::UpdateDeferred,
+            Self::Busy { .. } => Status::Busy,
+        }
+    }
+
+    /// `StatusReport::reason` reported along with `ota_status`
+    fn reason(&self) -> u8 {
+        match self {
+            Self::Denied(reason) => *reason,
+            _ => 0,
         }
     }
 }
@@ -0,0 +1,150 @@
+//! OTA slots of the partition table
+
+use std::ffi::CStr;
+
+use anyhow::Result;
+use esp_idf_svc::sys::{
+    esp_ota_get_next_update_partition, esp_ota_get_running_partition, esp_partition_find,
+    esp_partition_get, esp_partition_iterator_release, esp_partition_iterator_t,
+    esp_partition_next, esp_partition_subtype_t_ESP_PARTITION_SUBTYPE_ANY,
+    esp_partition_subtype_t_ESP_PARTITION_SUBTYPE_APP_OTA_MAX,
+    esp_partition_subtype_t_ESP_PARTITION_SUBTYPE_APP_OTA_MIN, esp_partition_t,
+    esp_partition_type_t_ESP_PARTITION_TYPE_APP,
+};
+use ota_protocol::slot::SlotState;
+
+use super::boot;
+
+/// App partition of subtype `ota_<index>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OtaSlot {
+    pub label: String,
+    pub index: u8,
+    pub offset: u32,
+    pub size: u32,
+    pub state: SlotState,
+    /// App is running from this slot
+    pub running: bool,
+    /// Next update is written to this slot
+    pub next_update: bool,
+}
+
+/// OTA slots in partition table order, the factory app is not included
+pub fn ota_slots() -> Vec<OtaSlot> {
+    let running = unsafe { esp_ota_get_running_partition() };
+    let next_update = unsafe { esp_ota_get_next_update_partition(std::ptr::null()) };
+
+    app_partitions()
+        .filter_map(|partition| {
+            let index = ota_index(partition)?;
+
+            Some(OtaSlot {
+                label: unsafe { CStr::from_ptr(partition.label.as_ptr()) }
+                    .to_string_lossy()
+                    .into_owned(),
+                index,
+                offset: partition.address,
+                size: partition.size,
+                state: boot::slot_state(partition),
+                running: std::ptr::eq(partition, running),
+                next_update: std::ptr::eq(partition, next_update),
+            })
+        })
+        .collect()
+}
+
+/// Slot the next update is written to, picked by the OTA API after the running one
+pub fn next_update_partition() -> Result<&'static esp_partition_t> {
+    let partition = unsafe { esp_ota_get_next_update_partition(std::ptr::null()).as_ref() }
+        .ok_or_else(|| anyhow::anyhow!("No OTA partition to update"))?;
+
+    if ota_index(partition).is_none() {
+        return Err(anyhow::anyhow!("Next update partition is not an OTA slot"));
+    }
+
+    Ok(partition)
+}
+
+/// Verifies that the partition table has OTA slots and logs them
+///
+/// Returns the largest image an update can hold: the size of the smallest OTA slot, so the
+/// same image can be written to whichever slot comes next.
+pub fn max_ota_size() -> Result<u32> {
+    let slots = ota_slots();
+
+    for slot in &slots {
+        log::info!(
+            "Found OTA slot {} (ota_{}) at {:#x}, size: {}, state: {:?}{}{}",
+            slot.label,
+            slot.index,
+            slot.offset,
+            slot.size,
+            slot.state,
+            if slot.running { ", running" } else { "" },
+            if slot.next_update {
+                ", next update"
+            } else {
+                ""
+            },
+        );
+    }
+
+    let max_ota_size = slots
+        .iter()
+        .map(|slot| slot.size)
+        .min()
+        .ok_or_else(|| anyhow::anyhow!("No OTA partitions found, verify partition table"))?;
+
+    log::info!("Max OTA size: {}", max_ota_size);
+
+    Ok(max_ota_size)
+}
+
+/// Index of an `ota_<index>` partition, `None` for the factory app and test partitions
+fn ota_index(partition: &esp_partition_t) -> Option<u8> {
+    let ota_subtypes = esp_partition_subtype_t_ESP_PARTITION_SUBTYPE_APP_OTA_MIN
+        ..esp_partition_subtype_t_ESP_PARTITION_SUBTYPE_APP_OTA_MAX;
+
+    ota_subtypes
+        .contains(&partition.subtype)
+        .then(|| (partition.subtype - ota_subtypes.start) as u8)
+}
+
+fn app_partitions() -> Partitions {
+    Partitions(unsafe {
+        esp_partition_find(
+            esp_partition_type_t_ESP_PARTITION_TYPE_APP,
+            esp_partition_subtype_t_ESP_PARTITION_SUBTYPE_ANY,
+            std::ptr::null(),
+        )
+    })
+}
+
+/// Owns an `esp_partition_iterator_t`, null once all partitions were returned
+struct Partitions(esp_partition_iterator_t);
+
+impl Iterator for Partitions {
+    type Item = &'static esp_partition_t;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.0.is_null() {
+            return None;
+        }
+
+        let partition = unsafe { esp_partition_get(self.0).as_ref() };
+
+        // Frees the iterator and returns null after the last partition
+        self.0 = unsafe { esp_partition_next(self.0) };
+
+        partition
+    }
+}
+
+impl Drop for Partitions {
+    fn drop(&mut self) {
+        // Iteration stopped early
+        if !self.0.is_null() {
+            unsafe { esp_partition_iterator_release(self.0) };
+        }
+    }
+}
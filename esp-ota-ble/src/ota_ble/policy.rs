@@ -0,0 +1,47 @@
+use ota_protocol::hash::ImageHash;
+
+/// When the `UpdatePolicy` is consulted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyStage {
+    /// Client sent `StartTransfer`, nothing was written yet
+    Start,
+    /// First blocks carrying the app description of the image were written
+    ///
+    /// Also consulted when a transfer is resumed past them, e.g. after a reboot.
+    Headers,
+    /// Whole image was received, it's about to be verified and made bootable
+    Activation,
+}
+
+/// Image an update is requested for, see `OtaBle::set_update_policy`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpdateRequest {
+    pub stage: PolicyStage,
+    pub size: u32,
+    /// Digest declared by the client in `file_hash`
+    pub hash: ImageHash,
+    /// Version of the app description of the image
+    ///
+    /// Always `None` at `PolicyStage::Start`, the client doesn't send it: policies deciding on
+    /// the version answer at `PolicyStage::Headers`, once it was written. Also `None` if the
+    /// written app description can't be read.
+    pub version: Option<String>,
+}
+
+/// Answer of the `UpdatePolicy`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyDecision {
+    Allow,
+    /// Refuses the update, `reason` is reported to the client as `StatusReport::reason`
+    ///
+    /// At `PolicyStage::Headers` and `PolicyStage::Activation` the received image is dropped.
+    Deny {
+        reason: u8,
+    },
+    /// Refuses the update for now, the client is told to retry later
+    ///
+    /// At `PolicyStage::Headers` the transfer is dropped, it's retried from the start.
+    /// At `PolicyStage::Activation` the received image is kept, it's made bootable by
+    /// `OtaBle::activate_deferred_update`.
+    Defer,
+}
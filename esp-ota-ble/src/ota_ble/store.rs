@@ -0,0 +1,35 @@
+use anyhow::Result;
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use ota_protocol::store::KeyValueStore;
+
+/// NVS namespace the OTA session is persisted in
+const NAMESPACE: &str = "esp_ota_ble";
+
+/// `KeyValueStore` persisting OTA sessions in NVS, so transfers survive a reset
+pub struct NvsStore(EspNvs<NvsDefault>);
+
+impl NvsStore {
+    pub fn new(partition: EspDefaultNvsPartition) -> Result<Self> {
+        Ok(Self(EspNvs::new(partition, NAMESPACE, true)?))
+    }
+}
+
+impl KeyValueStore for NvsStore {
+    type Error = anyhow::Error;
+
+    fn load(&mut self, key: &str, buf: &mut [u8]) -> Result<Option<usize>> {
+        Ok(self.0.get_raw(key, buf)?.map(|value| value.len()))
+    }
+
+    fn store(&mut self, key: &str, value: &[u8]) -> Result<()> {
+        self.0.set_raw(key, value)?;
+
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &str) -> Result<()> {
+        self.0.remove(key)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,281 @@
+use std::ffi::CStr;
+
+use anyhow::Result;
+use esp_idf_svc::sys::{
+    esp, esp_app_desc_t, esp_app_get_description, esp_chip_info, esp_chip_info_t, esp_ota_abort,
+    esp_ota_begin, esp_ota_end, esp_ota_get_partition_description, esp_ota_handle_t,
+    esp_ota_set_boot_partition, esp_ota_write, esp_partition_erase_range, esp_partition_read,
+    esp_partition_t, esp_partition_write,
+};
+use ota_protocol::{
+    image::{AppDescriptor, ChipId, ImageRequirements},
+    sink::FirmwareSink,
+};
+
+/// `image_size` of `esp_ota_begin` making it skip the erase, sectors are erased while writing
+///
+/// Erasing a whole image up front blocks the caller, the BLE stack, for seconds.
+const OTA_WITH_SEQUENTIAL_WRITES: usize = 0xffff_fffe;
+
+const FLASH_SECTOR_SIZE: u32 = 4096;
+
+/// Encrypted partitions are written in blocks of 16 bytes
+const ENCRYPTED_WRITE_ALIGN: usize = 16;
+
+/// App descriptor of the running app, as embedded in its image
+pub fn running_app() -> Result<AppDescriptor> {
+    let desc = unsafe {
+        std::slice::from_raw_parts(
+            esp_app_get_description().cast::<u8>(),
+            std::mem::size_of::<esp_app_desc_t>(),
+        )
+    };
+
+    Ok(AppDescriptor::decode(desc)?)
+}
+
+/// Chip and project of the running app, images built for anything else are rejected
+pub fn image_requirements(running: &AppDescriptor) -> ImageRequirements {
+    let mut chip_info: esp_chip_info_t = unsafe { std::mem::zeroed() };
+    unsafe { esp_chip_info(&mut chip_info) };
+
+    ImageRequirements::new(
+        ChipId(esp_idf_svc::sys::CONFIG_IDF_FIRMWARE_CHIP_ID as u16),
+        // `major * 100 + minor`, same as the chip revisions of the image header
+        chip_info.revision,
+        running.project_name(),
+    )
+}
+
+/// `FirmwareSink` writing to the next OTA partition
+///
+/// Talks to the OTA API directly rather than through `EspOta`: an update interrupted by a reset
+/// has to be continued without erasing what was already written, which `EspOtaUpdate` can't do.
+pub struct OtaUpdater {
+    partition: &'static esp_partition_t,
+    capacity: u32,
+    update: Option<Update>,
+}
+
+struct Update {
+    writer: Writer,
+    /// Offset in the image of the next byte received
+    offset: u32,
+}
+
+enum Writer {
+    /// Update started by `begin`, `esp_ota_write` erases each sector as it reaches it
+    Ota(esp_ota_handle_t),
+    /// Update continued by `resume`, written to the partition directly
+    ///
+    /// `esp_ota_write_with_offset` refuses handles opened with `OTA_WITH_SEQUENTIAL_WRITES`, and
+    /// any other mode of `esp_ota_begin` erases what was already written.
+    Partition {
+        /// End of the erased area, sectors are erased as writes reach them
+        erased_until: u32,
+        /// Tail of the received bytes not written yet, shorter than `ENCRYPTED_WRITE_ALIGN`,
+        /// checkpoints are sector aligned so they never cover it
+        pending: Vec<u8>,
+    },
+}
+
+// `partition` points into the partition table, which is never freed
+unsafe impl Send for OtaUpdater {}
+
+impl OtaUpdater {
+    /// Writes to `partition`, images are limited to `capacity` bytes
+    pub fn new(partition: &'static esp_partition_t, capacity: u32) -> Self {
+        Self {
+            partition,
+            capacity,
+            update: None,
+        }
+    }
+
+    /// Version of the app description of the image written to the partition
+    pub fn image_version(&self) -> Result<String> {
+        let mut desc: esp_app_desc_t = unsafe { std::mem::zeroed() };
+        esp!(unsafe { esp_ota_get_partition_description(self.partition, &mut desc) })?;
+
+        let version = unsafe { CStr::from_ptr(desc.version.as_ptr()) };
+
+        Ok(version.to_string_lossy().into_owned())
+    }
+
+    fn update(&mut self) -> Result<&mut Update> {
+        self.update
+            .as_mut()
+            .ok_or_else(|| anyhow::anyhow!("No update in progress"))
+    }
+
+    fn start(&mut self, writer: Writer, offset: u32) -> Result<()> {
+        if self.update.is_some() {
+            return Err(anyhow::anyhow!("Update is already in progress"));
+        }
+
+        self.update = Some(Update { writer, offset });
+
+        Ok(())
+    }
+}
+
+impl FirmwareSink for OtaUpdater {
+    type Error = anyhow::Error;
+
+    fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    /// Initiates a new update, the partition is erased sector by sector while writing
+    fn begin(&mut self, _size: u32) -> Result<()> {
+        let mut handle: esp_ota_handle_t = 0;
+        esp!(unsafe { esp_ota_begin(self.partition, OTA_WITH_SEQUENTIAL_WRITES, &mut handle) })?;
+
+        self.start(Writer::Ota(handle), 0)
+    }
+
+    /// Initiates an update continuing at `committed`, which has to be on a sector boundary
+    fn resume(&mut self, _size: u32, committed: u32) -> Result<()> {
+        if committed % FLASH_SECTOR_SIZE != 0 {
+            return Err(anyhow::anyhow!(
+                "Resume offset {} is not sector aligned",
+                committed
+            ));
+        }
+
+        self.start(
+            Writer::Partition {
+                erased_until: committed,
+                pending: Vec::new(),
+            },
+            committed,
+        )
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        let partition = self.partition;
+        let update = self.update()?;
+
+        match &mut update.writer {
+            Writer::Ota(handle) => {
+                esp!(unsafe { esp_ota_write(*handle, data.as_ptr().cast(), data.len()) })?
+            }
+            Writer::Partition {
+                erased_until,
+                pending,
+            } => {
+                let end = update.offset + data.len() as u32;
+
+                if end > *erased_until {
+                    let erase_end =
+                        (end + FLASH_SECTOR_SIZE - 1) / FLASH_SECTOR_SIZE * FLASH_SECTOR_SIZE;
+
+                    esp!(unsafe {
+                        esp_partition_erase_range(
+                            partition,
+                            *erased_until as usize,
+                            (erase_end - *erased_until) as usize,
+                        )
+                    })?;
+                    *erased_until = erase_end;
+                }
+
+                let pending_offset = update.offset - pending.len() as u32;
+                pending.extend_from_slice(data);
+                write_pending(partition, pending_offset, pending, false)?;
+            }
+        }
+
+        update.offset += data.len() as u32;
+
+        Ok(())
+    }
+
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<()> {
+        esp!(unsafe {
+            esp_partition_read(
+                self.partition,
+                offset as usize,
+                buf.as_mut_ptr().cast(),
+                buf.len(),
+            )
+        })?;
+
+        Ok(())
+    }
+
+    /// Validates the written image and marks the update partition as the boot one
+    fn finalize(&mut self) -> Result<()> {
+        let update = self
+            .update
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("No update in progress"))?;
+
+        match update.writer {
+            Writer::Ota(handle) => esp!(unsafe { esp_ota_end(handle) })?,
+            Writer::Partition { mut pending, .. } => {
+                let pending_offset = update.offset - pending.len() as u32;
+                write_pending(self.partition, pending_offset, &mut pending, true)?;
+            }
+        }
+
+        // Verifies the image as well, which `esp_ota_end` didn't for a resumed update
+        esp!(unsafe { esp_ota_set_boot_partition(self.partition) })?;
+
+        Ok(())
+    }
+
+    /// Drops the ongoing update, leaving the boot partition untouched
+    fn abort(&mut self) -> Result<()> {
+        let Some(update) = self.update.take() else {
+            return Ok(());
+        };
+
+        if let Writer::Ota(handle) = update.writer {
+            esp!(unsafe { esp_ota_abort(handle) })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes the whole blocks of `pending` to `partition` at `offset`, or all of it padded with
+/// `0xff` if `flush` is set, leaving the rest in `pending`
+///
+/// Encrypted partitions only take writes of whole `ENCRYPTED_WRITE_ALIGN` blocks, the others
+/// are written as is.
+fn write_pending(
+    partition: &esp_partition_t,
+    offset: u32,
+    pending: &mut Vec<u8>,
+    flush: bool,
+) -> Result<()> {
+    let len = if !partition.encrypted {
+        pending.len()
+    } else if flush {
+        let len = (pending.len() + ENCRYPTED_WRITE_ALIGN - 1) / ENCRYPTED_WRITE_ALIGN
+            * ENCRYPTED_WRITE_ALIGN;
+        pending.resize(len, 0xff);
+        len
+    } else {
+        pending.len() / ENCRYPTED_WRITE_ALIGN * ENCRYPTED_WRITE_ALIGN
+    };
+
+    if len > 0 {
+        esp!(unsafe {
+            esp_partition_write(partition, offset as usize, pending.as_ptr().cast(), len)
+        })?;
+        pending.drain(..len);
+    }
+
+    Ok(())
+}
+
+impl Drop for OtaUpdater {
+    fn drop(&mut self) {
+        // `OtaBle` was dropped mid transfer
+        if let Err(error) = self.abort() {
+            log::error!("Failed to abort OTA update: {:?}", error);
+        }
+    }
+}
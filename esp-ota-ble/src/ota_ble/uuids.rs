@@ -1,4 +1,5 @@
 use esp_idf_svc::bt::BtUuid;
+use ota_protocol::uuids;
 
 use crate::uuid128;
 
@@ -10,18 +11,30 @@ pub struct GattUuids {
     pub status: BtUuid,
     pub command: BtUuid,
     pub finished_upload: BtUuid,
+    pub file_signature: BtUuid,
+    pub slot_states: BtUuid,
+    pub firmware_info: BtUuid,
+    pub link_info: BtUuid,
+    pub stream_block: BtUuid,
+    pub stream_ack: BtUuid,
 }
 
 impl Default for GattUuids {
     fn default() -> Self {
         Self {
-            service: uuid128!("81ea96fb-1117-4ea4-9df0-d30cd73e0e76"),
-            file_block: uuid128!("075e8648-5b20-42c9-a492-b0ce7548be7c"),
-            total_file_size: uuid128!("92e8d217-f306-418e-b75b-894b288b6664"),
-            file_hash: uuid128!("923930e3-686a-409e-a1e0-c7bbd8bb3d50"),
-            status: uuid128!("e4ccad22-e983-42a9-9c95-7f4909ff885f"),
-            command: uuid128!("92fa0fe8-35ff-442f-a00c-010ebd91ef6a"),
-            finished_upload: uuid128!("e6b7ae4f-d7ff-43f6-a378-86cf740040db"),
+            service: BtUuid::uuid128(uuids::SERVICE),
+            file_block: BtUuid::uuid128(uuids::FILE_BLOCK),
+            total_file_size: BtUuid::uuid128(uuids::TOTAL_FILE_SIZE),
+            file_hash: BtUuid::uuid128(uuids::FILE_HASH),
+            status: BtUuid::uuid128(uuids::STATUS),
+            command: BtUuid::uuid128(uuids::COMMAND),
+            finished_upload: BtUuid::uuid128(uuids::FINISHED_UPLOAD),
+            file_signature: BtUuid::uuid128(uuids::FILE_SIGNATURE),
+            slot_states: BtUuid::uuid128(uuids::SLOT_STATES),
+            firmware_info: BtUuid::uuid128(uuids::FIRMWARE_INFO),
+            link_info: BtUuid::uuid128(uuids::LINK_INFO),
+            stream_block: BtUuid::uuid128(uuids::STREAM_BLOCK),
+            stream_ack: BtUuid::uuid128(uuids::STREAM_ACK),
         }
     }
 }
@@ -36,6 +49,12 @@ impl GattUuids {
             status: uuid128!(),
             command: uuid128!(),
             finished_upload: uuid128!(),
+            file_signature: uuid128!(),
+            slot_states: uuid128!(),
+            firmware_info: uuid128!(),
+            link_info: uuid128!(),
+            stream_block: uuid128!(),
+            stream_ack: uuid128!(),
         }
     }
 }
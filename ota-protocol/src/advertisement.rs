@@ -0,0 +1,110 @@
+//! Service data advertised under `uuids::SERVICE`, so clients can tell devices apart without
+//! connecting: `[protocol_version: u8][flags: u8][major: u8][minor: u8][patch: u8]
+//! [device_id: u32 LE]`
+
+use core::fmt;
+
+use crate::{image::AppDescriptor, version::Version, DecodeError};
+
+/// Version of the OTA protocol spoken by this crate, bumped on incompatible changes
+pub const PROTOCOL_VERSION: u8 = 1;
+
+/// Running app has a semantic version, `firmware_version` is set
+const FLAG_VERSION: u8 = 0x01;
+/// Device is receiving an image
+const FLAG_TRANSFER: u8 = 0x02;
+
+/// Version of the running app, components above 255 are saturated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ShortVersion {
+    pub major: u8,
+    pub minor: u8,
+    pub patch: u8,
+}
+
+impl ShortVersion {
+    /// Parses the version of `app`, `None` if it isn't a semantic version
+    pub fn of(app: &AppDescriptor) -> Option<Self> {
+        let version = Version::parse(app.version())?;
+        let short = |number: u32| number.min(u8::MAX as u32) as u8;
+
+        Some(Self {
+            major: short(version.major),
+            minor: short(version.minor),
+            patch: short(version.patch),
+        })
+    }
+}
+
+impl fmt::Display for ShortVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// Service data of the OTA service UUID
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServiceData {
+    pub protocol_version: u8,
+    /// `None` if the running app has no semantic version
+    pub firmware_version: Option<ShortVersion>,
+    pub transfer_in_progress: bool,
+    /// Short identifier of the device, e.g. derived from its MAC address, which some platforms
+    /// hide from clients
+    pub device_id: u32,
+}
+
+impl ServiceData {
+    pub const LEN: usize = 9;
+
+    /// Service data of a device running `app`
+    pub fn new(app: &AppDescriptor, transfer_in_progress: bool, device_id: u32) -> Self {
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            firmware_version: ShortVersion::of(app),
+            transfer_in_progress,
+            device_id,
+        }
+    }
+
+    pub fn encode(&self) -> [u8; Self::LEN] {
+        let mut bytes = [0; Self::LEN];
+        let mut flags = 0;
+
+        if let Some(version) = self.firmware_version {
+            flags |= FLAG_VERSION;
+            bytes[2..5].copy_from_slice(&[version.major, version.minor, version.patch]);
+        }
+
+        if self.transfer_in_progress {
+            flags |= FLAG_TRANSFER;
+        }
+
+        bytes[0] = self.protocol_version;
+        bytes[1] = flags;
+        bytes[5..9].copy_from_slice(&self.device_id.to_le_bytes());
+
+        bytes
+    }
+
+    /// Decodes service data, bytes after `LEN` are left to later protocol versions
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let bytes = bytes.get(..Self::LEN).ok_or(DecodeError::InvalidLength {
+            expected: Self::LEN,
+            actual: bytes.len(),
+        })?;
+
+        let flags = bytes[1];
+
+        Ok(Self {
+            protocol_version: bytes[0],
+            firmware_version: (flags & FLAG_VERSION != 0).then(|| ShortVersion {
+                major: bytes[2],
+                minor: bytes[3],
+                patch: bytes[4],
+            }),
+            transfer_in_progress: flags & FLAG_TRANSFER != 0,
+            device_id: u32::from_le_bytes(bytes[5..9].try_into().unwrap()),
+        })
+    }
+}
@@ -0,0 +1,77 @@
+use crate::DecodeError;
+
+/// Header prepended to every `file_block` write: `[offset: u32 LE]`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockHeader {
+    /// Image offset of the first data byte in the block
+    pub offset: u32,
+}
+
+impl BlockHeader {
+    pub const LEN: usize = 4;
+
+    pub fn encode(&self) -> [u8; Self::LEN] {
+        self.offset.to_le_bytes()
+    }
+}
+
+/// Single `file_block` write: header followed by a non-empty chunk of the image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Block<'a> {
+    pub header: BlockHeader,
+    pub data: &'a [u8],
+}
+
+impl<'a> Block<'a> {
+    pub fn new(offset: u32, data: &'a [u8]) -> Self {
+        Self {
+            header: BlockHeader { offset },
+            data,
+        }
+    }
+
+    /// Length of the encoded block
+    pub fn encoded_len(&self) -> usize {
+        BlockHeader::LEN + self.data.len()
+    }
+
+    /// Largest chunk of the image that fits into a block of `max_block_len` bytes
+    pub fn max_data_len(max_block_len: usize) -> usize {
+        max_block_len.saturating_sub(BlockHeader::LEN)
+    }
+
+    /// Encodes the block into `buf`, returns the number of bytes written
+    pub fn encode_into(&self, buf: &mut [u8]) -> Result<usize, DecodeError> {
+        let len = self.encoded_len();
+
+        if buf.len() < len {
+            return Err(DecodeError::InvalidLength {
+                expected: len,
+                actual: buf.len(),
+            });
+        }
+
+        buf[..BlockHeader::LEN].copy_from_slice(&self.header.encode());
+        buf[BlockHeader::LEN..len].copy_from_slice(self.data);
+
+        Ok(len)
+    }
+
+    pub fn decode(bytes: &'a [u8]) -> Result<Self, DecodeError> {
+        if bytes.len() <= BlockHeader::LEN {
+            return Err(DecodeError::InvalidLength {
+                expected: BlockHeader::LEN + 1,
+                actual: bytes.len(),
+            });
+        }
+
+        let (header, data) = bytes.split_at(BlockHeader::LEN);
+
+        Ok(Self {
+            header: BlockHeader {
+                offset: u32::from_le_bytes(header.try_into().unwrap()),
+            },
+            data,
+        })
+    }
+}
@@ -0,0 +1,81 @@
+//! Typed values of GATT characteristics
+//!
+//! Every OTA characteristic value implements `Codec`, so attribute storage on the device
+//! (`OtaCharacteristic<T>` of `esp-ota-ble`) can be written once for all of them.
+
+use crate::{
+    command::Command,
+    hash::ImageHash,
+    info::FirmwareInfo,
+    link::LinkInfo,
+    signature::ImageSignature,
+    size::TotalFileSize,
+    slot::SlotStates,
+    status::{FinishedUpload, StatusReport},
+    stream::StreamAck,
+    DecodeError,
+};
+
+/// Encoding of a characteristic value
+pub trait Codec: Sized {
+    /// Encoded value, a fixed-size array for most characteristics
+    type Encoded: AsRef<[u8]>;
+
+    /// Longest encoded value, used as the attribute length
+    const MAX_LEN: usize;
+
+    fn encode(&self) -> Self::Encoded;
+
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError>;
+}
+
+/// Implements `Codec` through the inherent `LEN`, `encode` and `decode` of fixed-size values
+macro_rules! fixed_len_codec {
+    ($($value:ty),* $(,)?) => {
+        $(
+            impl Codec for $value {
+                type Encoded = [u8; <$value>::LEN];
+
+                const MAX_LEN: usize = <$value>::LEN;
+
+                fn encode(&self) -> Self::Encoded {
+                    <$value>::encode(self)
+                }
+
+                fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+                    <$value>::decode(bytes)
+                }
+            }
+        )*
+    };
+}
+
+fixed_len_codec!(
+    Command,
+    TotalFileSize,
+    ImageHash,
+    ImageSignature,
+    StatusReport,
+    FinishedUpload,
+    SlotStates,
+    FirmwareInfo,
+    LinkInfo,
+    StreamAck,
+);
+
+/// Raw bytes, e.g. `file_block` writes, which are decoded into a `Block` by the receiver
+#[cfg(feature = "alloc")]
+impl Codec for alloc::vec::Vec<u8> {
+    type Encoded = Self;
+
+    /// Longest attribute value allowed by ATT
+    const MAX_LEN: usize = 512;
+
+    fn encode(&self) -> Self::Encoded {
+        self.clone()
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        Ok(bytes.into())
+    }
+}
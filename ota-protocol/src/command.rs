@@ -0,0 +1,47 @@
+use crate::{error::expect_len, DecodeError};
+
+/// Opcodes written to the `command` characteristic
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Command {
+    // Starts new file transfer, in case of an ongoing transfer, will result in an error
+    StartTransfer = 0x01,
+
+    // Clears the ongoing transfer, in case of no ongoing transfer, will result in an error
+    ClearTransfer = 0x02,
+
+    // Resets the device
+    ResetDevice = 0x03,
+
+    // ClearTransfer + StartTransfer in one command
+    StartForceTransfer = 0x04,
+}
+
+impl Command {
+    /// Length of an encoded command frame: `[opcode: u8]`
+    pub const LEN: usize = 1;
+
+    pub fn encode(&self) -> [u8; Self::LEN] {
+        [*self as u8]
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        expect_len(bytes, Self::LEN)?;
+
+        Self::try_from(bytes[0])
+    }
+}
+
+impl TryFrom<u8> for Command {
+    type Error = DecodeError;
+
+    fn try_from(opcode: u8) -> Result<Self, Self::Error> {
+        match opcode {
+            0x01 => Ok(Self::StartTransfer),
+            0x02 => Ok(Self::ClearTransfer),
+            0x03 => Ok(Self::ResetDevice),
+            0x04 => Ok(Self::StartForceTransfer),
+            _ => Err(DecodeError::UnknownCommand(opcode)),
+        }
+    }
+}
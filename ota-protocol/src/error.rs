@@ -0,0 +1,42 @@
+use core::fmt;
+
+/// Reasons a characteristic value can't be decoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// Value length doesn't match the encoded structure
+    InvalidLength { expected: usize, actual: usize },
+    /// Opcode is not a known `Command`
+    UnknownCommand(u8),
+    /// Byte is not a known `Status`
+    UnknownStatus(u8),
+    /// Byte is not a known `SlotState`
+    UnknownSlotState(u8),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidLength { expected, actual } => {
+                write!(f, "Invalid length: expected {}, got {}", expected, actual)
+            }
+            Self::UnknownCommand(opcode) => write!(f, "Unknown command: {:#04x}", opcode),
+            Self::UnknownStatus(status) => write!(f, "Unknown status: {:#04x}", status),
+            Self::UnknownSlotState(state) => write!(f, "Unknown slot state: {:#04x}", state),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DecodeError {}
+
+/// Checks that `bytes` is exactly `expected` bytes long
+pub(crate) fn expect_len(bytes: &[u8], expected: usize) -> Result<(), DecodeError> {
+    if bytes.len() != expected {
+        return Err(DecodeError::InvalidLength {
+            expected,
+            actual: bytes.len(),
+        });
+    }
+
+    Ok(())
+}
@@ -0,0 +1,26 @@
+use sha2::{Digest, Sha256};
+
+use crate::{error::expect_len, DecodeError};
+
+/// Value of the `file_hash` characteristic: SHA-256 of the whole image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageHash(pub [u8; ImageHash::LEN]);
+
+impl ImageHash {
+    pub const LEN: usize = 32;
+
+    /// Hashes a whole image at once, see `OtaSession` for the incremental variant
+    pub fn of(image: &[u8]) -> Self {
+        Self(Sha256::digest(image).into())
+    }
+
+    pub fn encode(&self) -> [u8; Self::LEN] {
+        self.0
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        expect_len(bytes, Self::LEN)?;
+
+        Ok(Self(bytes.try_into().unwrap()))
+    }
+}
@@ -0,0 +1,272 @@
+//! Header of an ESP-IDF app image, checked while the first blocks of a transfer arrive
+//!
+//! An app image starts with `esp_image_header_t`, followed by the header of its first segment,
+//! whose data begins with `esp_app_desc_t`:
+//! `[image header: 24][segment header: 8][app descriptor: 256]...`
+
+use core::{fmt, str};
+
+/// Number of bytes at the start of an image `FirmwareImage::parse` needs
+pub const IMAGE_PREFIX_LEN: usize = APP_DESC_OFFSET + AppDescriptor::LEN;
+
+/// Length of `esp_image_segment_header_t`: `[load_addr: u32][data_len: u32]`
+const SEGMENT_HEADER_LEN: usize = 8;
+
+const APP_DESC_OFFSET: usize = ImageHeader::LEN + SEGMENT_HEADER_LEN;
+
+/// `chip_id` of the image header, see `esp_chip_id_t`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChipId(pub u16);
+
+impl ChipId {
+    pub const ESP32: Self = Self(0x0000);
+    pub const ESP32_S2: Self = Self(0x0002);
+    pub const ESP32_C3: Self = Self(0x0005);
+    pub const ESP32_S3: Self = Self(0x0009);
+    pub const ESP32_C2: Self = Self(0x000c);
+    pub const ESP32_C6: Self = Self(0x000d);
+    pub const ESP32_H2: Self = Self(0x0010);
+}
+
+/// `esp_image_header_t`, chip revisions are encoded as `major * 100 + minor`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageHeader {
+    pub segment_count: u8,
+    pub entry_addr: u32,
+    pub chip_id: ChipId,
+    /// Lowest chip revision the image runs on
+    pub min_chip_rev: u16,
+    /// Highest chip revision the image runs on, `0` or `0xffff` if there is no limit
+    pub max_chip_rev: u16,
+    /// SHA-256 of the image is appended after its checksum
+    pub hash_appended: bool,
+}
+
+impl ImageHeader {
+    pub const MAGIC: u8 = 0xe9;
+    pub const LEN: usize = 24;
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, ImageError> {
+        let bytes = bytes
+            .get(..Self::LEN)
+            .ok_or(ImageError::Truncated { len: bytes.len() })?;
+
+        if bytes[0] != Self::MAGIC {
+            return Err(ImageError::InvalidMagic(bytes[0]));
+        }
+
+        Ok(Self {
+            segment_count: bytes[1],
+            entry_addr: u32::from_le_bytes(bytes[4..8].try_into().unwrap()),
+            chip_id: ChipId(u16::from_le_bytes([bytes[12], bytes[13]])),
+            min_chip_rev: u16::from_le_bytes([bytes[15], bytes[16]]),
+            max_chip_rev: u16::from_le_bytes([bytes[17], bytes[18]]),
+            hash_appended: bytes[23] == 1,
+        })
+    }
+
+    /// Whether the image runs on a chip of `revision`
+    pub fn supports_revision(&self, revision: u16) -> bool {
+        let below_max = matches!(self.max_chip_rev, 0 | 0xffff) || revision <= self.max_chip_rev;
+
+        revision >= self.min_chip_rev && below_max
+    }
+}
+
+/// `esp_app_desc_t`, strings are NUL terminated in the image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AppDescriptor {
+    /// Anti-rollback counter, compared against eFuses by the bootloader
+    pub secure_version: u32,
+    pub(crate) version: [u8; 32],
+    pub(crate) project_name: [u8; 32],
+    pub(crate) time: [u8; 16],
+    pub(crate) date: [u8; 16],
+    pub(crate) idf_version: [u8; 32],
+    /// SHA-256 of the ELF the image was built from
+    pub elf_sha256: [u8; 32],
+}
+
+impl AppDescriptor {
+    pub const MAGIC: u32 = 0xabcd_5432;
+    pub const LEN: usize = 256;
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, ImageError> {
+        let bytes = bytes
+            .get(..Self::LEN)
+            .ok_or(ImageError::Truncated { len: bytes.len() })?;
+
+        let magic = u32::from_le_bytes(bytes[0..4].try_into().unwrap());
+        if magic != Self::MAGIC {
+            return Err(ImageError::InvalidAppDescMagic(magic));
+        }
+
+        Ok(Self {
+            secure_version: u32::from_le_bytes(bytes[4..8].try_into().unwrap()),
+            version: bytes[16..48].try_into().unwrap(),
+            project_name: bytes[48..80].try_into().unwrap(),
+            time: bytes[80..96].try_into().unwrap(),
+            date: bytes[96..112].try_into().unwrap(),
+            idf_version: bytes[112..144].try_into().unwrap(),
+            elf_sha256: bytes[144..176].try_into().unwrap(),
+        })
+    }
+
+    pub fn version(&self) -> &str {
+        c_str(&self.version)
+    }
+
+    pub fn project_name(&self) -> &str {
+        c_str(&self.project_name)
+    }
+
+    /// Build time, e.g. `12:34:56`
+    pub fn time(&self) -> &str {
+        c_str(&self.time)
+    }
+
+    /// Build date, e.g. `Jan  1 2024`
+    pub fn date(&self) -> &str {
+        c_str(&self.date)
+    }
+
+    pub fn idf_version(&self) -> &str {
+        c_str(&self.idf_version)
+    }
+}
+
+/// Headers at the start of an app image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FirmwareImage {
+    pub header: ImageHeader,
+    pub app: AppDescriptor,
+}
+
+impl FirmwareImage {
+    /// Parses the first `IMAGE_PREFIX_LEN` bytes of an image, the rest is ignored
+    pub fn parse(bytes: &[u8]) -> Result<Self, ImageError> {
+        let header = ImageHeader::decode(bytes)?;
+
+        if bytes.len() < IMAGE_PREFIX_LEN {
+            return Err(ImageError::Truncated { len: bytes.len() });
+        }
+
+        Ok(Self {
+            header,
+            app: AppDescriptor::decode(&bytes[APP_DESC_OFFSET..])?,
+        })
+    }
+}
+
+/// Device images are accepted for, see `OtaSession::with_image_requirements`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageRequirements {
+    pub chip_id: ChipId,
+    /// Revision of the chip, `major * 100 + minor`
+    pub chip_revision: u16,
+    project_name: [u8; 32],
+}
+
+impl ImageRequirements {
+    /// `project_name` of the running app, truncated to 31 bytes as in `esp_app_desc_t`
+    pub fn new(chip_id: ChipId, chip_revision: u16, project_name: &str) -> Self {
+        let mut name = [0; 32];
+        let len = project_name.len().min(name.len() - 1);
+        name[..len].copy_from_slice(&project_name.as_bytes()[..len]);
+
+        Self {
+            chip_id,
+            chip_revision,
+            project_name: name,
+        }
+    }
+
+    pub fn project_name(&self) -> &str {
+        c_str(&self.project_name)
+    }
+
+    /// Checks that `image` is built for this chip and project
+    pub fn check(&self, image: &FirmwareImage) -> Result<(), ImageError> {
+        let header = &image.header;
+
+        if header.chip_id != self.chip_id {
+            return Err(ImageError::ChipMismatch {
+                expected: self.chip_id,
+                actual: header.chip_id,
+            });
+        }
+
+        if !header.supports_revision(self.chip_revision) {
+            return Err(ImageError::UnsupportedRevision {
+                revision: self.chip_revision,
+                min: header.min_chip_rev,
+                max: header.max_chip_rev,
+            });
+        }
+
+        if until_nul(&image.app.project_name) != until_nul(&self.project_name) {
+            return Err(ImageError::ProjectMismatch);
+        }
+
+        Ok(())
+    }
+}
+
+/// Reasons an image is not accepted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageError {
+    /// Image is shorter than its headers, only `len` bytes are available
+    Truncated { len: usize },
+    /// First byte is not `ImageHeader::MAGIC`, it's not an app image
+    InvalidMagic(u8),
+    /// Image has no `AppDescriptor`, e.g. it's a bootloader
+    InvalidAppDescMagic(u32),
+    /// Image is built for another chip
+    ChipMismatch { expected: ChipId, actual: ChipId },
+    /// Chip `revision` is outside of `min..=max` of the image
+    UnsupportedRevision { revision: u16, min: u16, max: u16 },
+    /// Image is built from another project than the running app
+    ProjectMismatch,
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Truncated { len } => write!(f, "Image headers are truncated at {} bytes", len),
+            Self::InvalidMagic(magic) => write!(f, "Invalid image magic: {:#04x}", magic),
+            Self::InvalidAppDescMagic(magic) => {
+                write!(f, "Invalid app descriptor magic: {:#010x}", magic)
+            }
+            Self::ChipMismatch { expected, actual } => write!(
+                f,
+                "Image is built for chip {:#06x}, expected {:#06x}",
+                actual.0, expected.0
+            ),
+            Self::UnsupportedRevision { revision, min, max } => write!(
+                f,
+                "Chip revision {} is not supported by the image ({}..={})",
+                revision, min, max
+            ),
+            Self::ProjectMismatch => write!(f, "Image is built from another project"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ImageError {}
+
+fn until_nul(bytes: &[u8]) -> &[u8] {
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+
+    &bytes[..len]
+}
+
+/// String of a NUL terminated field, cut at the first invalid UTF-8 sequence
+pub(crate) fn c_str(bytes: &[u8]) -> &str {
+    let bytes = until_nul(bytes);
+
+    match str::from_utf8(bytes) {
+        Ok(s) => s,
+        Err(error) => str::from_utf8(&bytes[..error.valid_up_to()]).unwrap(),
+    }
+}
@@ -0,0 +1,93 @@
+use crate::{
+    error::expect_len,
+    image::{c_str, AppDescriptor},
+    DecodeError,
+};
+
+/// Value of the read-only `firmware_info` characteristic, describing the running app:
+/// `[version: 32][project_name: 32][idf_version: 32][date: 16][time: 16][elf_sha256: 32]
+/// [secure_version: u32 LE][max_ota_size: u32 LE]`
+///
+/// Strings are NUL padded, as in `esp_app_desc_t`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FirmwareInfo {
+    version: [u8; 32],
+    project_name: [u8; 32],
+    idf_version: [u8; 32],
+    date: [u8; 16],
+    time: [u8; 16],
+    /// SHA-256 of the ELF the running app was built from
+    pub elf_sha256: [u8; 32],
+    pub secure_version: u32,
+    /// Largest image the device accepts, see `TotalFileSize`
+    pub max_ota_size: u32,
+}
+
+impl FirmwareInfo {
+    pub const LEN: usize = 168;
+
+    pub fn new(app: &AppDescriptor, max_ota_size: u32) -> Self {
+        Self {
+            version: app.version,
+            project_name: app.project_name,
+            idf_version: app.idf_version,
+            date: app.date,
+            time: app.time,
+            elf_sha256: app.elf_sha256,
+            secure_version: app.secure_version,
+            max_ota_size,
+        }
+    }
+
+    pub fn version(&self) -> &str {
+        c_str(&self.version)
+    }
+
+    pub fn project_name(&self) -> &str {
+        c_str(&self.project_name)
+    }
+
+    pub fn idf_version(&self) -> &str {
+        c_str(&self.idf_version)
+    }
+
+    /// Build date, e.g. `Jan  1 2024`
+    pub fn date(&self) -> &str {
+        c_str(&self.date)
+    }
+
+    /// Build time, e.g. `12:34:56`
+    pub fn time(&self) -> &str {
+        c_str(&self.time)
+    }
+
+    pub fn encode(&self) -> [u8; Self::LEN] {
+        let mut bytes = [0; Self::LEN];
+
+        bytes[0..32].copy_from_slice(&self.version);
+        bytes[32..64].copy_from_slice(&self.project_name);
+        bytes[64..96].copy_from_slice(&self.idf_version);
+        bytes[96..112].copy_from_slice(&self.date);
+        bytes[112..128].copy_from_slice(&self.time);
+        bytes[128..160].copy_from_slice(&self.elf_sha256);
+        bytes[160..164].copy_from_slice(&self.secure_version.to_le_bytes());
+        bytes[164..168].copy_from_slice(&self.max_ota_size.to_le_bytes());
+
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        expect_len(bytes, Self::LEN)?;
+
+        Ok(Self {
+            version: bytes[0..32].try_into().unwrap(),
+            project_name: bytes[32..64].try_into().unwrap(),
+            idf_version: bytes[64..96].try_into().unwrap(),
+            date: bytes[96..112].try_into().unwrap(),
+            time: bytes[112..128].try_into().unwrap(),
+            elf_sha256: bytes[128..160].try_into().unwrap(),
+            secure_version: u32::from_le_bytes(bytes[160..164].try_into().unwrap()),
+            max_ota_size: u32::from_le_bytes(bytes[164..168].try_into().unwrap()),
+        })
+    }
+}
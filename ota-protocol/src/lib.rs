@@ -0,0 +1,34 @@
+//! Wire format of the OTA GATT service, shared by the firmware (`esp-ota-ble`)
+//! and the host uploader (`esp-ota-ble-cli`)
+//!
+//! All multi-byte integers are little-endian and have a fixed width, so the encoding
+//! doesn't depend on the pointer size of either side.
+#![no_std]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
+pub mod advertisement;
+pub mod block;
+pub mod codec;
+pub mod command;
+pub mod error;
+pub mod hash;
+pub mod image;
+pub mod info;
+pub mod link;
+pub mod resume;
+pub mod session;
+pub mod signature;
+pub mod sink;
+pub mod size;
+pub mod slot;
+pub mod status;
+pub mod store;
+pub mod stream;
+pub mod uuids;
+pub mod version;
+
+pub use error::DecodeError;
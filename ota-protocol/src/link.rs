@@ -0,0 +1,49 @@
+use crate::{error::expect_len, DecodeError};
+
+/// ATT MTU of a connection until the client negotiates a larger one
+pub const DEFAULT_MTU: u16 = 23;
+
+/// Largest ATT MTU allowed by the spec
+pub const MAX_MTU: u16 = 517;
+
+/// Opcode and handle of an ATT write, the rest of the MTU is left to the value
+pub const ATT_WRITE_HEADER_LEN: u16 = 3;
+
+/// Value of the read-only `link_info` characteristic, describing the connection of the client
+/// reading it: `[mtu: u16 LE][max_block_size: u16 LE]`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinkInfo {
+    /// ATT MTU negotiated with the client
+    pub mtu: u16,
+    /// Longest `file_block` write accepted by the device, header included
+    pub max_block_size: u16,
+}
+
+impl LinkInfo {
+    pub const LEN: usize = 4;
+
+    /// Longest `file_block` write fitting a single ATT packet of this connection
+    pub fn block_size(&self) -> usize {
+        self.mtu
+            .saturating_sub(ATT_WRITE_HEADER_LEN)
+            .min(self.max_block_size) as usize
+    }
+
+    pub fn encode(&self) -> [u8; Self::LEN] {
+        let mut bytes = [0; Self::LEN];
+
+        bytes[0..2].copy_from_slice(&self.mtu.to_le_bytes());
+        bytes[2..4].copy_from_slice(&self.max_block_size.to_le_bytes());
+
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        expect_len(bytes, Self::LEN)?;
+
+        Ok(Self {
+            mtu: u16::from_le_bytes(bytes[0..2].try_into().unwrap()),
+            max_block_size: u16::from_le_bytes(bytes[2..4].try_into().unwrap()),
+        })
+    }
+}
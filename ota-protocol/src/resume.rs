@@ -0,0 +1,53 @@
+use crate::{error::expect_len, hash::ImageHash, DecodeError};
+
+/// Store key of the `ResumeRecord` of the ongoing transfer
+pub const RECORD_KEY: &str = "ota_session";
+
+/// Store key of the last assigned session ID, kept after the record is removed
+pub const SESSION_ID_KEY: &str = "ota_session_id";
+
+/// Progress is persisted every time this many more bytes were written, a multiple of the flash
+/// sector size so a resumed transfer starts on an erase boundary
+pub const CHECKPOINT_INTERVAL: u32 = 4096;
+
+/// Persisted state of an interrupted transfer:
+/// `[session_id: u32 LE][size: u32 LE][committed: u32 LE][hash: 32 bytes]`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResumeRecord {
+    /// ID assigned to the transfer when it was started
+    pub session_id: u32,
+    /// Size of the whole image
+    pub size: u32,
+    /// Bytes known to be in flash, the transfer resumes from here
+    pub committed: u32,
+    /// Expected SHA-256 of the whole image
+    pub hash: ImageHash,
+}
+
+impl ResumeRecord {
+    pub const LEN: usize = 12 + ImageHash::LEN;
+
+    pub fn encode(&self) -> [u8; Self::LEN] {
+        let mut bytes = [0; Self::LEN];
+
+        bytes[0..4].copy_from_slice(&self.session_id.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.size.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.committed.to_le_bytes());
+        bytes[12..].copy_from_slice(&self.hash.encode());
+
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        expect_len(bytes, Self::LEN)?;
+
+        let u32_at = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+
+        Ok(Self {
+            session_id: u32_at(0),
+            size: u32_at(4),
+            committed: u32_at(8),
+            hash: ImageHash::decode(&bytes[12..])?,
+        })
+    }
+}
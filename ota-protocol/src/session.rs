@@ -0,0 +1,679 @@
+use core::fmt;
+
+use sha2::{Digest, Sha256};
+
+use crate::{
+    hash::ImageHash,
+    image::{AppDescriptor, FirmwareImage, ImageError, ImageRequirements, IMAGE_PREFIX_LEN},
+    resume::{ResumeRecord, CHECKPOINT_INTERVAL, RECORD_KEY, SESSION_ID_KEY},
+    signature::{ImageSignature, PublicKey},
+    sink::FirmwareSink,
+    status::{Status, StatusReport},
+    store::{KeyValueStore, NoStore},
+    version::{DowngradeError, DowngradePolicy, SECURE_VERSION_KEY},
+};
+
+/// Metadata of an image, provided by the client before the transfer is started
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageInfo {
+    /// Size of the whole image, see `TotalFileSize`
+    pub size: u32,
+    /// SHA-256 of the whole image, see `ImageHash`
+    pub hash: ImageHash,
+    /// Signature of `hash`, required if the session has a `PublicKey`
+    pub signature: Option<ImageSignature>,
+}
+
+/// Lifecycle of a firmware transfer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionState {
+    /// No transfer in progress
+    Idle,
+    /// Transfer was started, `received` bytes out of `size` were written to the sink
+    Receiving { size: u32, received: u32 },
+    /// Whole image was received, waiting for `OtaSession::finish`
+    Verifying { size: u32 },
+    /// Image was finalized, device has to be reset to boot into it
+    ReadyToReboot { size: u32 },
+    /// Transfer was aborted, `Status` tells why
+    Failed(Status),
+}
+
+impl SessionState {
+    /// Value reported through the `status` characteristic while in this state
+    pub fn status(&self) -> Status {
+        match self {
+            Self::Idle => Status::Idle,
+            Self::Receiving { .. } => Status::Receiving,
+            Self::Verifying { .. } => Status::Verifying,
+            Self::ReadyToReboot { .. } => Status::Finished,
+            Self::Failed(status) => *status,
+        }
+    }
+
+    /// Whether a transfer was started and not yet finished or aborted
+    pub fn is_in_progress(&self) -> bool {
+        matches!(self, Self::Receiving { .. } | Self::Verifying { .. })
+    }
+
+    /// Bytes of the current transfer already written to the sink
+    pub fn received(&self) -> u32 {
+        match self {
+            Self::Receiving { received, .. } => *received,
+            Self::Verifying { size } | Self::ReadyToReboot { size } => *size,
+            Self::Idle | Self::Failed(_) => 0,
+        }
+    }
+}
+
+/// Reasons a session request can't be applied
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SessionError<E> {
+    /// Transfer was started while another one is in progress
+    TransferInProgress,
+    /// Request requires a started transfer
+    NoTransfer,
+    /// Image or block is empty
+    Empty,
+    /// Image is larger than the sink capacity
+    TooLarge { size: u32, capacity: u32 },
+    /// Block doesn't continue the image where the previous one ended
+    InvalidOffset { expected: u32, actual: u32 },
+    /// Block goes past the image size
+    Overflow,
+    /// Transfer was finished before the whole image was received
+    Incomplete { received: u32, size: u32 },
+    /// SHA-256 of the received image doesn't match `ImageInfo::hash`, the transfer was aborted
+    HashMismatch,
+    /// Session requires signed images, but `ImageInfo::signature` is missing
+    MissingSignature,
+    /// `ImageInfo::signature` wasn't made with the trusted `PublicKey`
+    InvalidSignature,
+    /// Image headers don't meet the `ImageRequirements`, the transfer was aborted
+    InvalidImage(ImageError),
+    /// `DowngradePolicy` refused the image, the transfer was aborted
+    Downgrade(DowngradeError),
+    /// Sink failed, the transfer was aborted
+    Sink(E),
+    /// Session couldn't be persisted, the transfer goes on but may not be resumable after a reset
+    Store(E),
+}
+
+impl<E> SessionError<E> {
+    /// Value reported through the `status` characteristic
+    pub fn status(&self) -> Status {
+        match self {
+            Self::TransferInProgress => Status::TransferInProgress,
+            Self::NoTransfer => Status::NoTransfer,
+            Self::Empty => Status::InvalidLength,
+            Self::TooLarge { .. } => Status::ImageTooLarge,
+            Self::InvalidOffset { .. } => Status::InvalidOffset,
+            Self::Overflow => Status::Overflow,
+            Self::Incomplete { .. } => Status::Incomplete,
+            Self::HashMismatch => Status::HashMismatch,
+            Self::MissingSignature => Status::MissingSignature,
+            Self::InvalidSignature => Status::InvalidSignature,
+            Self::InvalidImage(_) => Status::InvalidImage,
+            Self::Downgrade(_) => Status::Downgrade,
+            Self::Sink(_) | Self::Store(_) => Status::UpdateFailed,
+        }
+    }
+}
+
+impl<E: fmt::Debug> fmt::Display for SessionError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TransferInProgress => write!(f, "Transfer is already in progress"),
+            Self::NoTransfer => write!(f, "No transfer in progress"),
+            Self::Empty => write!(f, "Image or block is empty"),
+            Self::TooLarge { size, capacity } => {
+                write!(f, "Image of {} bytes exceeds {} bytes", size, capacity)
+            }
+            Self::InvalidOffset { expected, actual } => {
+                write!(f, "Invalid offset: expected {}, got {}", expected, actual)
+            }
+            Self::Overflow => write!(f, "Block goes past the image size"),
+            Self::Incomplete { received, size } => {
+                write!(f, "Only {} out of {} bytes received", received, size)
+            }
+            Self::HashMismatch => write!(f, "Image hash mismatch"),
+            Self::MissingSignature => write!(f, "Image signature is required"),
+            Self::InvalidSignature => write!(f, "Image signature is invalid"),
+            Self::InvalidImage(error) => write!(f, "Image is rejected: {}", error),
+            Self::Downgrade(error) => write!(f, "Downgrade is refused: {}", error),
+            Self::Sink(error) => write!(f, "Firmware sink failed: {:?}", error),
+            Self::Store(error) => write!(f, "Session store failed: {:?}", error),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<E: fmt::Debug> std::error::Error for SessionError<E> {}
+
+/// Result of a successfully applied block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockOutcome {
+    /// Block was written to the sink
+    Accepted,
+    /// Block was already written (e.g. retransmitted after a lost response) and was skipped
+    Duplicate,
+    /// Block completed the image, the session is now verifying
+    Complete,
+}
+
+/// Transport-agnostic OTA transfer state machine
+///
+/// `Idle` -> `Receiving` -> `Verifying` -> `ReadyToReboot`, with any sink failure moving the
+/// session to `Failed`. Blocks have to be written in order, the transport is responsible for
+/// decoding them and reporting errors back to the client.
+///
+/// The image is hashed as blocks arrive and only finalized if the digest matches the one
+/// announced by the client, so a corrupted upload never becomes bootable. With a `PublicKey`
+/// the announced digest also has to be signed, which is checked before anything is written.
+/// With `ImageRequirements`, images built for another chip or project are aborted as soon as
+/// their headers arrive, as are downgrades refused by the `DowngradePolicy`.
+///
+/// Progress is checkpointed to the `KeyValueStore` every `CHECKPOINT_INTERVAL` bytes, so a
+/// transfer interrupted by a reset can be picked up again with `restore`.
+pub struct OtaSession<S, K = NoStore> {
+    sink: S,
+    store: K,
+    public_key: Option<PublicKey>,
+    requirements: Option<ImageRequirements>,
+    /// Policy and descriptor of the running app
+    downgrade: Option<(DowngradePolicy, AppDescriptor)>,
+    /// Current transfer was forced by an authenticated client, it may be a downgrade
+    allow_downgrade: bool,
+    state: SessionState,
+    session_id: u32,
+    expected_hash: ImageHash,
+    hasher: Sha256,
+}
+
+impl<S: FirmwareSink> OtaSession<S> {
+    pub fn new(sink: S) -> Self {
+        Self {
+            sink,
+            store: NoStore,
+            public_key: None,
+            requirements: None,
+            downgrade: None,
+            allow_downgrade: false,
+            state: SessionState::Idle,
+            session_id: 0,
+            expected_hash: ImageHash([0; ImageHash::LEN]),
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// Session only accepting images signed with the private half of `public_key`
+    pub fn with_public_key(sink: S, public_key: PublicKey) -> Self {
+        Self {
+            public_key: Some(public_key),
+            ..Self::new(sink)
+        }
+    }
+}
+
+impl<S: FirmwareSink, K: KeyValueStore> OtaSession<S, K>
+where
+    K::Error: Into<S::Error>,
+{
+    /// Persists the session to `store`, call `restore` to pick up a transfer saved in it
+    pub fn with_store<T: KeyValueStore>(self, store: T) -> OtaSession<S, T> {
+        OtaSession {
+            sink: self.sink,
+            store,
+            public_key: self.public_key,
+            requirements: self.requirements,
+            downgrade: self.downgrade,
+            allow_downgrade: self.allow_downgrade,
+            state: self.state,
+            session_id: self.session_id,
+            expected_hash: self.expected_hash,
+            hasher: self.hasher,
+        }
+    }
+
+    /// Only accepts images whose headers meet `requirements`
+    ///
+    /// Headers are checked as soon as the first `IMAGE_PREFIX_LEN` bytes were written, a
+    /// mismatching transfer is aborted without waiting for the rest of the image.
+    pub fn with_image_requirements(self, requirements: ImageRequirements) -> Self {
+        Self {
+            requirements: Some(requirements),
+            ..self
+        }
+    }
+
+    /// Refuses images `policy` considers a downgrade of the `running` app
+    ///
+    /// A transfer started with `force` by a client whose signature was verified with the
+    /// `PublicKey` skips the check, so authenticated clients can still roll back on purpose.
+    pub fn with_downgrade_policy(self, policy: DowngradePolicy, running: AppDescriptor) -> Self {
+        Self {
+            downgrade: Some((policy, running)),
+            ..self
+        }
+    }
+
+    pub fn store(&self) -> &K {
+        &self.store
+    }
+
+    pub fn public_key(&self) -> Option<&PublicKey> {
+        self.public_key.as_ref()
+    }
+
+    pub fn state(&self) -> SessionState {
+        self.state
+    }
+
+    /// ID of the current (or last) transfer
+    pub fn session_id(&self) -> u32 {
+        self.session_id
+    }
+
+    /// Hash the image of the current transfer has to match, `None` without a transfer
+    ///
+    /// Fixed when the transfer is started, unlike the `file_hash` a client may rewrite meanwhile.
+    /// Once the session is `ReadyToReboot`, it's the hash of the verified image.
+    pub fn expected_hash(&self) -> Option<ImageHash> {
+        match self.state {
+            SessionState::Receiving { .. }
+            | SessionState::Verifying { .. }
+            | SessionState::ReadyToReboot { .. } => Some(self.expected_hash),
+            SessionState::Idle | SessionState::Failed(_) => None,
+        }
+    }
+
+    /// Value of the `status` characteristic reporting `status` for the current transfer
+    pub fn report(&self, status: Status) -> StatusReport {
+        StatusReport {
+            status,
+            session_id: self.session_id,
+            offset: self.state.received(),
+            reason: 0,
+        }
+    }
+
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+
+    pub fn sink_mut(&mut self) -> &mut S {
+        &mut self.sink
+    }
+
+    /// Picks up the transfer persisted in the store before a reset
+    ///
+    /// The already written part of the image is read back from the sink to rebuild the hash.
+    /// Returns the restored record, `None` if there was nothing to restore.
+    pub fn restore(&mut self) -> Result<Option<ResumeRecord>, SessionError<S::Error>> {
+        if self.state.is_in_progress() {
+            return Err(SessionError::TransferInProgress);
+        }
+
+        let mut buf = [0; ResumeRecord::LEN];
+
+        if let Some(len) = self.load_value(SESSION_ID_KEY, &mut buf[..4])? {
+            if len == 4 {
+                self.session_id = u32::from_le_bytes(buf[..4].try_into().unwrap());
+            }
+        }
+
+        let record = match self.load_value(RECORD_KEY, &mut buf)? {
+            Some(ResumeRecord::LEN) => ResumeRecord::decode(&buf).ok(),
+            Some(_) => None,
+            None => return Ok(None),
+        };
+
+        let Some(record) = record.filter(|record| {
+            record.size > 0 && record.size <= self.sink.capacity() && record.committed < record.size
+        }) else {
+            // Written by an incompatible version or for a different partition layout
+            self.forget()?;
+            return Ok(None);
+        };
+
+        if let Err(error) = self.rehash(&record) {
+            return Err(self.fail(SessionError::Sink(error)));
+        }
+
+        self.session_id = record.session_id;
+        self.expected_hash = record.hash;
+        self.allow_downgrade = false;
+        self.state = SessionState::Receiving {
+            size: record.size,
+            received: record.committed,
+        };
+
+        Ok(Some(record))
+    }
+
+    /// Starts a transfer of `image`, `force` aborts the ongoing one first
+    ///
+    /// Starting the image that is already being received resumes its transfer instead, unless
+    /// `force` is set. Returns the offset the client has to continue sending blocks from.
+    ///
+    /// A `SessionError::Store` is reported after the transfer was started from offset 0, it
+    /// goes on without being resumable.
+    pub fn start(&mut self, image: ImageInfo, force: bool) -> Result<u32, SessionError<S::Error>> {
+        let ImageInfo {
+            size,
+            hash,
+            signature,
+        } = image;
+
+        if self.state.is_in_progress() {
+            if let SessionState::Receiving {
+                size: current_size,
+                received,
+            } = self.state
+            {
+                // Signature was already checked for this hash when the transfer was started
+                if !force && current_size == size && self.expected_hash == hash {
+                    return Ok(received);
+                }
+            }
+
+            if !force {
+                return Err(SessionError::TransferInProgress);
+            }
+
+            match self.abort() {
+                // Record of the aborted transfer is replaced by the one of the new transfer
+                Ok(()) | Err(SessionError::Store(_)) => {}
+                Err(error) => return Err(error),
+            }
+        }
+
+        if size == 0 {
+            return Err(SessionError::Empty);
+        }
+
+        let capacity = self.sink.capacity();
+        if size > capacity {
+            return Err(SessionError::TooLarge { size, capacity });
+        }
+
+        if let Some(public_key) = &self.public_key {
+            let signature = signature.ok_or(SessionError::MissingSignature)?;
+
+            if !public_key.verify(&hash, &signature) {
+                return Err(SessionError::InvalidSignature);
+            }
+        }
+
+        self.sink
+            .begin(size)
+            .map_err(|error| self.fail(SessionError::Sink(error)))?;
+
+        self.state = SessionState::Receiving { size, received: 0 };
+        // Only a verified signature authenticates the client
+        self.allow_downgrade = force && self.public_key.is_some();
+        self.session_id = self.session_id.wrapping_add(1);
+        self.expected_hash = hash;
+        self.hasher = Sha256::new();
+
+        self.store_value(SESSION_ID_KEY, &self.session_id.to_le_bytes())?;
+        self.checkpoint(0)?;
+
+        Ok(0)
+    }
+
+    /// Aborts the ongoing transfer
+    ///
+    /// A failed session is reset as well, clearing a finished one is an error, as the image
+    /// was already made bootable.
+    pub fn clear(&mut self) -> Result<(), SessionError<S::Error>> {
+        match self.state {
+            SessionState::Receiving { .. } | SessionState::Verifying { .. } => self.abort(),
+            SessionState::Failed(_) => {
+                self.state = SessionState::Idle;
+                self.forget()
+            }
+            SessionState::Idle | SessionState::ReadyToReboot { .. } => {
+                Err(SessionError::NoTransfer)
+            }
+        }
+    }
+
+    /// Writes a block of the image starting at `offset`
+    ///
+    /// A `SessionError::Store` is reported after the block was applied, the transfer goes on.
+    pub fn write_block(
+        &mut self,
+        offset: u32,
+        data: &[u8],
+    ) -> Result<BlockOutcome, SessionError<S::Error>> {
+        let SessionState::Receiving { size, received } = self.state else {
+            return Err(SessionError::NoTransfer);
+        };
+
+        if data.is_empty() {
+            return Err(SessionError::Empty);
+        }
+
+        let end = offset
+            .checked_add(data.len() as u32)
+            .ok_or(SessionError::Overflow)?;
+
+        if end <= received {
+            return Ok(BlockOutcome::Duplicate);
+        }
+
+        if offset != received {
+            return Err(SessionError::InvalidOffset {
+                expected: received,
+                actual: offset,
+            });
+        }
+
+        if end > size {
+            return Err(SessionError::Overflow);
+        }
+
+        if let Err(error) = self.sink.write(data) {
+            // Sink is left in an unknown state, the transfer can't be continued
+            let _ = self.sink.abort();
+
+            return Err(self.fail(SessionError::Sink(error)));
+        }
+
+        self.hasher.update(data);
+
+        let prefix_len = IMAGE_PREFIX_LEN as u32;
+        if received < prefix_len && (end >= prefix_len || end == size) {
+            if let Err(error) = self.check_image(end) {
+                let _ = self.sink.abort();
+
+                return Err(self.fail(error));
+            }
+        }
+
+        if end == size {
+            self.state = SessionState::Verifying { size };
+
+            Ok(BlockOutcome::Complete)
+        } else {
+            self.state = SessionState::Receiving {
+                size,
+                received: end,
+            };
+
+            if end / CHECKPOINT_INTERVAL != received / CHECKPOINT_INTERVAL {
+                self.checkpoint(end - end % CHECKPOINT_INTERVAL)?;
+            }
+
+            Ok(BlockOutcome::Accepted)
+        }
+    }
+
+    /// Verifies the hash of the received image and finalizes it, making it bootable
+    pub fn finish(&mut self) -> Result<(), SessionError<S::Error>> {
+        let size = match self.state {
+            SessionState::Verifying { size } => size,
+            SessionState::Receiving { size, received } => {
+                return Err(SessionError::Incomplete { received, size })
+            }
+            _ => return Err(SessionError::NoTransfer),
+        };
+
+        let hash = ImageHash(self.hasher.finalize_reset().into());
+        if hash != self.expected_hash {
+            let _ = self.sink.abort();
+
+            return Err(self.fail(SessionError::HashMismatch));
+        }
+
+        self.sink
+            .finalize()
+            .map_err(|error| self.fail(SessionError::Sink(error)))?;
+
+        self.state = SessionState::ReadyToReboot { size };
+
+        self.forget()
+    }
+
+    /// Raises the stored secure version to the one of the running app
+    ///
+    /// Call once the running app is confirmed: with `DowngradePolicy::SecureVersion`, images
+    /// below it are refused from then on, even after rolling back to an older app.
+    pub fn commit_secure_version(&mut self) -> Result<(), SessionError<S::Error>> {
+        let Some((DowngradePolicy::SecureVersion, running)) = self.downgrade else {
+            return Ok(());
+        };
+
+        if running.secure_version > self.secure_version()? {
+            self.store_value(SECURE_VERSION_KEY, &running.secure_version.to_le_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    /// Highest secure version committed to the store, `0` if there is none
+    pub fn secure_version(&mut self) -> Result<u32, SessionError<S::Error>> {
+        let mut buf = [0; 4];
+
+        match self.load_value(SECURE_VERSION_KEY, &mut buf)? {
+            Some(4) => Ok(u32::from_le_bytes(buf)),
+            _ => Ok(0),
+        }
+    }
+
+    /// Checks the headers of the image against the requirements and the downgrade policy,
+    /// reading them back from the sink
+    fn check_image(&mut self, written: u32) -> Result<(), SessionError<S::Error>> {
+        let downgrade = self
+            .downgrade
+            .filter(|(policy, _)| *policy != DowngradePolicy::AllowAny && !self.allow_downgrade);
+
+        if self.requirements.is_none() && downgrade.is_none() {
+            return Ok(());
+        }
+
+        let mut prefix = [0; IMAGE_PREFIX_LEN];
+        let len = (written as usize).min(IMAGE_PREFIX_LEN);
+
+        self.sink
+            .read(0, &mut prefix[..len])
+            .map_err(SessionError::Sink)?;
+
+        let image = FirmwareImage::parse(&prefix[..len]).map_err(SessionError::InvalidImage)?;
+
+        if let Some(requirements) = &self.requirements {
+            requirements
+                .check(&image)
+                .map_err(SessionError::InvalidImage)?;
+        }
+
+        if let Some((policy, running)) = downgrade {
+            let min_secure_version = self.secure_version()?;
+
+            policy
+                .check(&running, &image.app, min_secure_version)
+                .map_err(SessionError::Downgrade)?;
+        }
+
+        Ok(())
+    }
+
+    fn abort(&mut self) -> Result<(), SessionError<S::Error>> {
+        self.state = SessionState::Idle;
+
+        self.sink
+            .abort()
+            .map_err(|error| self.fail(SessionError::Sink(error)))?;
+
+        self.forget()
+    }
+
+    /// Moves the session to `Failed`, passing `error` through
+    fn fail(&mut self, error: SessionError<S::Error>) -> SessionError<S::Error> {
+        self.state = SessionState::Failed(error.status());
+
+        // Failed transfer can't be resumed, a store error is secondary to `error`
+        let _ = self.store.remove(RECORD_KEY);
+
+        error
+    }
+
+    /// Resumes the sink at `record.committed` and feeds the committed part to the hasher
+    fn rehash(&mut self, record: &ResumeRecord) -> Result<(), S::Error> {
+        self.sink.resume(record.size, record.committed)?;
+
+        self.hasher = Sha256::new();
+
+        let mut buf = [0; 256];
+        let mut offset = 0;
+        while offset < record.committed {
+            let len = (record.committed - offset).min(buf.len() as u32);
+
+            self.sink.read(offset, &mut buf[..len as usize])?;
+            self.hasher.update(&buf[..len as usize]);
+
+            offset += len;
+        }
+
+        Ok(())
+    }
+
+    /// Persists the current transfer with `committed` bytes known to be in the sink
+    fn checkpoint(&mut self, committed: u32) -> Result<(), SessionError<S::Error>> {
+        let record = ResumeRecord {
+            session_id: self.session_id,
+            size: match self.state {
+                SessionState::Receiving { size, .. } => size,
+                _ => return Ok(()),
+            },
+            committed,
+            hash: self.expected_hash,
+        };
+
+        self.store_value(RECORD_KEY, &record.encode())
+    }
+
+    /// Removes the persisted transfer, it can't be resumed anymore
+    fn forget(&mut self) -> Result<(), SessionError<S::Error>> {
+        self.store
+            .remove(RECORD_KEY)
+            .map_err(|error| SessionError::Store(error.into()))
+    }
+
+    fn load_value(
+        &mut self,
+        key: &str,
+        buf: &mut [u8],
+    ) -> Result<Option<usize>, SessionError<S::Error>> {
+        self.store
+            .load(key, buf)
+            .map_err(|error| SessionError::Store(error.into()))
+    }
+
+    fn store_value(&mut self, key: &str, value: &[u8]) -> Result<(), SessionError<S::Error>> {
+        self.store
+            .store(key, value)
+            .map_err(|error| SessionError::Store(error.into()))
+    }
+}
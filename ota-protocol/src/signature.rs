@@ -0,0 +1,41 @@
+use ed25519_dalek::{Signature, VerifyingKey};
+
+use crate::{error::expect_len, hash::ImageHash, DecodeError};
+
+/// Value of the `file_signature` characteristic: detached Ed25519 signature of the `ImageHash`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageSignature(pub [u8; ImageSignature::LEN]);
+
+impl ImageSignature {
+    pub const LEN: usize = 64;
+
+    pub fn encode(&self) -> [u8; Self::LEN] {
+        self.0
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        expect_len(bytes, Self::LEN)?;
+
+        Ok(Self(bytes.try_into().unwrap()))
+    }
+}
+
+/// Ed25519 public key trusted to sign firmware images
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PublicKey(pub [u8; PublicKey::LEN]);
+
+impl PublicKey {
+    pub const LEN: usize = 32;
+
+    /// Whether `signature` was made over `hash` by the private half of this key
+    ///
+    /// Uses strict verification, so malleable signatures and weak keys are rejected as well.
+    pub fn verify(&self, hash: &ImageHash, signature: &ImageSignature) -> bool {
+        let Ok(key) = VerifyingKey::from_bytes(&self.0) else {
+            return false;
+        };
+
+        key.verify_strict(&hash.0, &Signature::from_bytes(&signature.0))
+            .is_ok()
+    }
+}
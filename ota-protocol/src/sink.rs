@@ -0,0 +1,159 @@
+use core::fmt::Debug;
+
+/// Destination of the received firmware image
+///
+/// On the device this is the next OTA partition, in tests it is an in-memory buffer.
+/// `OtaSession` guarantees that calls follow `begin` | `resume` -> `write`* -> `finalize` | `abort`.
+pub trait FirmwareSink {
+    type Error: Debug;
+
+    /// Largest image the sink can hold
+    fn capacity(&self) -> u32;
+
+    /// Prepares the sink for a new image of `size` bytes
+    fn begin(&mut self, size: u32) -> Result<(), Self::Error>;
+
+    /// Continues an image of `size` bytes after a reset, its first `committed` bytes were
+    /// already written by a previous `begin` -> `write`* sequence
+    fn resume(&mut self, size: u32, committed: u32) -> Result<(), Self::Error>;
+
+    /// Appends next chunk of the image
+    fn write(&mut self, data: &[u8]) -> Result<(), Self::Error>;
+
+    /// Reads back already written bytes of the image starting at `offset`
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), Self::Error>;
+
+    /// Validates the written image and makes it bootable
+    fn finalize(&mut self) -> Result<(), Self::Error>;
+
+    /// Drops the written image
+    fn abort(&mut self) -> Result<(), Self::Error>;
+}
+
+#[cfg(feature = "alloc")]
+pub use memory::{MemorySink, MemorySinkError};
+
+#[cfg(feature = "alloc")]
+mod memory {
+    use alloc::vec::Vec;
+    use core::convert::Infallible;
+
+    use super::FirmwareSink;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum MemorySinkError {
+        /// Write was injected to fail with `MemorySink::fail_next_write`
+        WriteFailed,
+        /// Resumed or read past the written image
+        OutOfBounds,
+    }
+
+    /// Lets sessions use an infallible `KeyValueStore` next to `MemorySink`
+    impl From<Infallible> for MemorySinkError {
+        fn from(never: Infallible) -> Self {
+            match never {}
+        }
+    }
+
+    /// `FirmwareSink` keeping the image in memory, used to run sessions on the host
+    #[derive(Debug, Default)]
+    pub struct MemorySink {
+        capacity: u32,
+        image: Vec<u8>,
+        finalized: bool,
+        fail_next_write: bool,
+    }
+
+    impl MemorySink {
+        pub fn new(capacity: u32) -> Self {
+            Self {
+                capacity,
+                ..Default::default()
+            }
+        }
+
+        /// Sink holding the partially written `image` of a previous run, see `FirmwareSink::resume`
+        pub fn with_image(capacity: u32, image: &[u8]) -> Self {
+            Self {
+                capacity,
+                image: image.into(),
+                ..Default::default()
+            }
+        }
+
+        /// Bytes written since the last `begin`
+        pub fn image(&self) -> &[u8] {
+            &self.image
+        }
+
+        /// Whether the image was finalized, i.e. would be booted on a device
+        pub fn is_finalized(&self) -> bool {
+            self.finalized
+        }
+
+        /// Makes the next `write` fail, as a flash error would
+        pub fn fail_next_write(&mut self) {
+            self.fail_next_write = true;
+        }
+    }
+
+    impl FirmwareSink for MemorySink {
+        type Error = MemorySinkError;
+
+        fn capacity(&self) -> u32 {
+            self.capacity
+        }
+
+        fn begin(&mut self, _size: u32) -> Result<(), Self::Error> {
+            self.image.clear();
+            self.finalized = false;
+
+            Ok(())
+        }
+
+        fn resume(&mut self, _size: u32, committed: u32) -> Result<(), Self::Error> {
+            // Whatever follows the committed part would be erased on a device
+            if committed as usize > self.image.len() {
+                return Err(MemorySinkError::OutOfBounds);
+            }
+
+            self.image.truncate(committed as usize);
+            self.finalized = false;
+
+            Ok(())
+        }
+
+        fn write(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+            if core::mem::take(&mut self.fail_next_write) {
+                return Err(MemorySinkError::WriteFailed);
+            }
+
+            self.image.extend_from_slice(data);
+
+            Ok(())
+        }
+
+        fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), Self::Error> {
+            let data = self
+                .image
+                .get(offset as usize..offset as usize + buf.len())
+                .ok_or(MemorySinkError::OutOfBounds)?;
+
+            buf.copy_from_slice(data);
+
+            Ok(())
+        }
+
+        fn finalize(&mut self) -> Result<(), Self::Error> {
+            self.finalized = true;
+
+            Ok(())
+        }
+
+        fn abort(&mut self) -> Result<(), Self::Error> {
+            self.image.clear();
+
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,22 @@
+use crate::{error::expect_len, DecodeError};
+
+/// Value of the `total_file_size` characteristic: `[size: u32 LE]`
+///
+/// Written by the client before `StartTransfer`, fixed to 32 bits so the firmware (32-bit `usize`)
+/// and the host (usually 64-bit `usize`) agree on the encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TotalFileSize(pub u32);
+
+impl TotalFileSize {
+    pub const LEN: usize = 4;
+
+    pub fn encode(&self) -> [u8; Self::LEN] {
+        self.0.to_le_bytes()
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        expect_len(bytes, Self::LEN)?;
+
+        Ok(Self(u32::from_le_bytes(bytes.try_into().unwrap())))
+    }
+}
@@ -0,0 +1,88 @@
+use crate::{error::expect_len, DecodeError};
+
+/// State of an OTA slot, as tracked by the bootloader when app rollback is enabled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum SlotState {
+    // Image was written and activated, but wasn't booted yet
+    New = 0x00,
+
+    // Image was booted once and waits for the application to confirm it
+    PendingVerify = 0x01,
+
+    // Image was confirmed by the application
+    Valid = 0x02,
+
+    // Image failed the confirmation and was rolled back
+    Invalid = 0x03,
+
+    // Image wasn't confirmed before the next reset and was rolled back
+    Aborted = 0x04,
+
+    // Slot isn't tracked, e.g. rollback is disabled or it is the factory app
+    Undefined = 0xff,
+}
+
+impl TryFrom<u8> for SlotState {
+    type Error = DecodeError;
+
+    fn try_from(state: u8) -> Result<Self, Self::Error> {
+        match state {
+            0x00 => Ok(Self::New),
+            0x01 => Ok(Self::PendingVerify),
+            0x02 => Ok(Self::Valid),
+            0x03 => Ok(Self::Invalid),
+            0x04 => Ok(Self::Aborted),
+            0xff => Ok(Self::Undefined),
+            _ => Err(DecodeError::UnknownSlotState(state)),
+        }
+    }
+}
+
+/// What happened to the last update, derived from `SlotStates`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootOutcome {
+    /// Running image was confirmed by the application
+    Confirmed,
+    /// Running image was booted from an update and isn't confirmed yet
+    PendingVerify,
+    /// Update failed the confirmation, the device went back to the previous image
+    RolledBack,
+    /// Bootloader doesn't track the slots, updates can't be confirmed nor rolled back
+    Untracked,
+}
+
+/// Value of the read-only `slot_states` characteristic: `[running: u8][next: u8]`
+///
+/// `next` is the slot the next update is written to, after a rollback it holds the rejected image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlotStates {
+    pub running: SlotState,
+    pub next: SlotState,
+}
+
+impl SlotStates {
+    pub const LEN: usize = 2;
+
+    pub fn outcome(&self) -> BootOutcome {
+        match (self.running, self.next) {
+            (SlotState::New | SlotState::PendingVerify, _) => BootOutcome::PendingVerify,
+            (_, SlotState::Invalid | SlotState::Aborted) => BootOutcome::RolledBack,
+            (SlotState::Valid, _) => BootOutcome::Confirmed,
+            _ => BootOutcome::Untracked,
+        }
+    }
+
+    pub fn encode(&self) -> [u8; Self::LEN] {
+        [self.running as u8, self.next as u8]
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        expect_len(bytes, Self::LEN)?;
+
+        Ok(Self {
+            running: SlotState::try_from(bytes[0])?,
+            next: SlotState::try_from(bytes[1])?,
+        })
+    }
+}
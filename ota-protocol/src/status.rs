@@ -0,0 +1,189 @@
+use crate::{error::expect_len, DecodeError};
+
+/// Outcome of the last request or state of the transfer, see `StatusReport`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Status {
+    // No transfer in progress
+    Idle = 0x00,
+
+    // Transfer was started and the device is waiting for file blocks
+    Receiving = 0x01,
+
+    // Whole image was written and activated, device has to be reset to boot into it
+    Finished = 0x02,
+
+    // Whole image was received and is being validated
+    Verifying = 0x03,
+
+    // StartTransfer while a transfer is in progress
+    TransferInProgress = 0x80,
+
+    // ClearTransfer or a file block without a started transfer
+    NoTransfer = 0x81,
+
+    // Command opcode is not known
+    UnknownCommand = 0x82,
+
+    // StartTransfer before total_file_size was written
+    UnknownFileSize = 0x83,
+
+    // Malformed command or file block
+    InvalidLength = 0x84,
+
+    // File block doesn't continue the image where the previous one ended
+    InvalidOffset = 0x85,
+
+    // File block goes past total_file_size
+    Overflow = 0x86,
+
+    // Flash write or image validation failed, transfer was aborted
+    UpdateFailed = 0x87,
+
+    // Transfer was finished before the whole image was received
+    Incomplete = 0x88,
+
+    // StartTransfer with an image larger than the update partition
+    ImageTooLarge = 0x89,
+
+    // StartTransfer before file_hash was written
+    UnknownFileHash = 0x8a,
+
+    // SHA-256 of the received image doesn't match file_hash, transfer was aborted
+    HashMismatch = 0x8b,
+
+    // StartTransfer without file_signature while the device requires signed images
+    MissingSignature = 0x8c,
+
+    // file_signature wasn't made over file_hash with the trusted key
+    InvalidSignature = 0x8d,
+
+    // Application refused the update, StatusReport::reason tells why
+    UpdateDenied = 0x8e,
+
+    // Application can't take the update right now, the client should retry later
+    UpdateDeferred = 0x8f,
+
+    // Image header doesn't match the device (chip, revision or project), transfer was aborted
+    InvalidImage = 0x90,
+
+    // Image is a downgrade refused by the device's anti-downgrade policy, transfer was aborted
+    Downgrade = 0x91,
+
+    // Another client owns the transfer in progress, only it may write
+    Busy = 0x92,
+}
+
+impl Status {
+    /// Length of the encoded status: `[status: u8]`
+    pub const LEN: usize = 1;
+
+    /// Whether the status reports a failed request rather than a transfer state
+    pub fn is_error(&self) -> bool {
+        (*self as u8) & 0x80 != 0
+    }
+
+    pub fn encode(&self) -> [u8; Self::LEN] {
+        [*self as u8]
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        expect_len(bytes, Self::LEN)?;
+
+        Self::try_from(bytes[0])
+    }
+}
+
+impl TryFrom<u8> for Status {
+    type Error = DecodeError;
+
+    fn try_from(status: u8) -> Result<Self, Self::Error> {
+        match status {
+            0x00 => Ok(Self::Idle),
+            0x01 => Ok(Self::Receiving),
+            0x02 => Ok(Self::Finished),
+            0x03 => Ok(Self::Verifying),
+            0x80 => Ok(Self::TransferInProgress),
+            0x81 => Ok(Self::NoTransfer),
+            0x82 => Ok(Self::UnknownCommand),
+            0x83 => Ok(Self::UnknownFileSize),
+            0x84 => Ok(Self::InvalidLength),
+            0x85 => Ok(Self::InvalidOffset),
+            0x86 => Ok(Self::Overflow),
+            0x87 => Ok(Self::UpdateFailed),
+            0x88 => Ok(Self::Incomplete),
+            0x89 => Ok(Self::ImageTooLarge),
+            0x8a => Ok(Self::UnknownFileHash),
+            0x8b => Ok(Self::HashMismatch),
+            0x8c => Ok(Self::MissingSignature),
+            0x8d => Ok(Self::InvalidSignature),
+            0x8e => Ok(Self::UpdateDenied),
+            0x8f => Ok(Self::UpdateDeferred),
+            0x90 => Ok(Self::InvalidImage),
+            0x91 => Ok(Self::Downgrade),
+            0x92 => Ok(Self::Busy),
+            _ => Err(DecodeError::UnknownStatus(status)),
+        }
+    }
+}
+
+/// Value of the `status` characteristic, updated and notified after every command and block:
+/// `[status: u8][session_id: u32 LE][offset: u32 LE][reason: u8]`
+///
+/// `offset` is the number of bytes of the current transfer the device already has, after a
+/// reconnect the client continues sending blocks from there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatusReport {
+    pub status: Status,
+    /// ID of the current (or last) transfer, changes with every started transfer
+    pub session_id: u32,
+    /// Bytes received in the current transfer
+    pub offset: u32,
+    /// Application defined code of an `UpdateDenied` status, `0` otherwise
+    pub reason: u8,
+}
+
+impl StatusReport {
+    pub const LEN: usize = Status::LEN + 9;
+
+    pub fn encode(&self) -> [u8; Self::LEN] {
+        let mut bytes = [0; Self::LEN];
+
+        bytes[0] = self.status as u8;
+        bytes[1..5].copy_from_slice(&self.session_id.to_le_bytes());
+        bytes[5..9].copy_from_slice(&self.offset.to_le_bytes());
+        bytes[9] = self.reason;
+
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        expect_len(bytes, Self::LEN)?;
+
+        Ok(Self {
+            status: Status::try_from(bytes[0])?,
+            session_id: u32::from_le_bytes(bytes[1..5].try_into().unwrap()),
+            offset: u32::from_le_bytes(bytes[5..9].try_into().unwrap()),
+            reason: bytes[9],
+        })
+    }
+}
+
+/// Value of the `finished_upload` characteristic, notified once the image was activated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FinishedUpload(pub bool);
+
+impl FinishedUpload {
+    /// Length of the encoded flag: `[finished: u8]`
+    pub const LEN: usize = 1;
+
+    pub fn encode(&self) -> [u8; Self::LEN] {
+        [self.0 as u8]
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        expect_len(bytes, Self::LEN)?;
+
+        Ok(Self(bytes[0] != 0))
+    }
+}
@@ -0,0 +1,117 @@
+use core::{convert::Infallible, fmt::Debug};
+
+/// Persistent key-value storage surviving a device reset
+///
+/// On the device this is a NVS namespace, in tests it is an in-memory map. Keys are short ASCII
+/// strings (NVS limits them to 15 characters).
+pub trait KeyValueStore {
+    type Error: Debug;
+
+    /// Reads the value of `key` into `buf`, returning its length or `None` if `key` isn't set
+    fn load(&mut self, key: &str, buf: &mut [u8]) -> Result<Option<usize>, Self::Error>;
+
+    /// Sets `key` to `value`, replacing the previous one
+    fn store(&mut self, key: &str, value: &[u8]) -> Result<(), Self::Error>;
+
+    /// Removes `key`, doing nothing if it isn't set
+    fn remove(&mut self, key: &str) -> Result<(), Self::Error>;
+}
+
+/// Store keeping nothing, so sessions can't be resumed after a reset
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoStore;
+
+impl KeyValueStore for NoStore {
+    type Error = Infallible;
+
+    fn load(&mut self, _key: &str, _buf: &mut [u8]) -> Result<Option<usize>, Self::Error> {
+        Ok(None)
+    }
+
+    fn store(&mut self, _key: &str, _value: &[u8]) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn remove(&mut self, _key: &str) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Optional store, `None` behaves like `NoStore`
+impl<K: KeyValueStore> KeyValueStore for Option<K> {
+    type Error = K::Error;
+
+    fn load(&mut self, key: &str, buf: &mut [u8]) -> Result<Option<usize>, Self::Error> {
+        match self {
+            Some(store) => store.load(key, buf),
+            None => Ok(None),
+        }
+    }
+
+    fn store(&mut self, key: &str, value: &[u8]) -> Result<(), Self::Error> {
+        match self {
+            Some(store) => store.store(key, value),
+            None => Ok(()),
+        }
+    }
+
+    fn remove(&mut self, key: &str) -> Result<(), Self::Error> {
+        match self {
+            Some(store) => store.remove(key),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+pub use memory::MemoryStore;
+
+#[cfg(feature = "alloc")]
+mod memory {
+    use alloc::{collections::BTreeMap, string::String, vec::Vec};
+    use core::convert::Infallible;
+
+    use super::KeyValueStore;
+
+    /// `KeyValueStore` standing in for NVS on the host
+    ///
+    /// Clones share nothing, keep a clone around to simulate the content surviving a reset.
+    #[derive(Debug, Default, Clone)]
+    pub struct MemoryStore {
+        values: BTreeMap<String, Vec<u8>>,
+    }
+
+    impl MemoryStore {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        pub fn get(&self, key: &str) -> Option<&[u8]> {
+            self.values.get(key).map(Vec::as_slice)
+        }
+    }
+
+    impl KeyValueStore for MemoryStore {
+        type Error = Infallible;
+
+        fn load(&mut self, key: &str, buf: &mut [u8]) -> Result<Option<usize>, Self::Error> {
+            Ok(self.values.get(key).map(|value| {
+                let len = value.len().min(buf.len());
+                buf[..len].copy_from_slice(&value[..len]);
+                value.len()
+            }))
+        }
+
+        fn store(&mut self, key: &str, value: &[u8]) -> Result<(), Self::Error> {
+            self.values.insert(key.into(), value.into());
+
+            Ok(())
+        }
+
+        fn remove(&mut self, key: &str) -> Result<(), Self::Error> {
+            self.values.remove(key);
+
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,374 @@
+//! Streaming of the image with writes without response, acknowledged every few blocks
+//!
+//! The client numbers the blocks of a transfer from 0 (the offset reported by `StartTransfer`)
+//! and writes them to `stream_block`, keeping at most `window` blocks past the last
+//! acknowledged one in flight. The device notifies a `StreamAck` on `stream_ack` every
+//! `ack_interval` blocks, as soon as it notices a gap, and whenever it receives a block it
+//! doesn't expect. The client retransmits the missing ranges, and the first unacknowledged
+//! block if no ack arrives in time.
+
+use crate::{error::expect_len, DecodeError};
+
+#[cfg(feature = "alloc")]
+pub use window::{StreamReceiver, StreamSender};
+
+/// Header prepended to every `stream_block` write: `[seq: u32 LE]`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamHeader {
+    /// Position of the block in the stream, blocks hold the image in sequence order
+    pub seq: u32,
+}
+
+impl StreamHeader {
+    pub const LEN: usize = 4;
+
+    pub fn encode(&self) -> [u8; Self::LEN] {
+        self.seq.to_le_bytes()
+    }
+}
+
+/// Single `stream_block` write: header followed by a non-empty chunk of the image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamBlock<'a> {
+    pub header: StreamHeader,
+    pub data: &'a [u8],
+}
+
+impl<'a> StreamBlock<'a> {
+    pub fn new(seq: u32, data: &'a [u8]) -> Self {
+        Self {
+            header: StreamHeader { seq },
+            data,
+        }
+    }
+
+    /// Length of the encoded block
+    pub fn encoded_len(&self) -> usize {
+        StreamHeader::LEN + self.data.len()
+    }
+
+    /// Largest chunk of the image that fits into a block of `max_block_len` bytes
+    pub fn max_data_len(max_block_len: usize) -> usize {
+        max_block_len.saturating_sub(StreamHeader::LEN)
+    }
+
+    /// Encodes the block into `buf`, returns the number of bytes written
+    pub fn encode_into(&self, buf: &mut [u8]) -> Result<usize, DecodeError> {
+        let len = self.encoded_len();
+
+        if buf.len() < len {
+            return Err(DecodeError::InvalidLength {
+                expected: len,
+                actual: buf.len(),
+            });
+        }
+
+        buf[..StreamHeader::LEN].copy_from_slice(&self.header.encode());
+        buf[StreamHeader::LEN..len].copy_from_slice(self.data);
+
+        Ok(len)
+    }
+
+    pub fn decode(bytes: &'a [u8]) -> Result<Self, DecodeError> {
+        if bytes.len() <= StreamHeader::LEN {
+            return Err(DecodeError::InvalidLength {
+                expected: StreamHeader::LEN + 1,
+                actual: bytes.len(),
+            });
+        }
+
+        let (header, data) = bytes.split_at(StreamHeader::LEN);
+
+        Ok(Self {
+            header: StreamHeader {
+                seq: u32::from_le_bytes(header.try_into().unwrap()),
+            },
+            data,
+        })
+    }
+}
+
+/// Blocks `start..end` the device is missing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MissingRange {
+    pub start: u32,
+    pub end: u32,
+}
+
+impl MissingRange {
+    pub fn len(&self) -> u32 {
+        self.end.saturating_sub(self.start)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Value of the `stream_ack` characteristic, a cumulative ACK, or a NAK if blocks are missing:
+/// `[next_seq: u32 LE][window: u16 LE]` followed by `MAX_RANGES` times
+/// `[start: u32 LE][len: u16 LE]`, unused ranges have a length of 0
+///
+/// Fits a notification at the default ATT MTU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamAck {
+    /// Every block before `next_seq` was received
+    pub next_seq: u32,
+    /// How many blocks past `next_seq` the client may have in flight
+    pub window: u16,
+    /// First gaps after `next_seq`, blocks past them were received
+    pub missing: [Option<MissingRange>; StreamAck::MAX_RANGES],
+}
+
+impl StreamAck {
+    pub const LEN: usize = 18;
+
+    /// Gaps reported at once, later ones are reported once these are filled
+    pub const MAX_RANGES: usize = 2;
+
+    pub fn new(next_seq: u32, window: u16) -> Self {
+        Self {
+            next_seq,
+            window,
+            missing: [None; Self::MAX_RANGES],
+        }
+    }
+
+    /// Whether blocks past a gap were received
+    pub fn is_nak(&self) -> bool {
+        self.missing.iter().any(Option::is_some)
+    }
+
+    pub fn missing(&self) -> impl Iterator<Item = MissingRange> + '_ {
+        self.missing.iter().flatten().copied()
+    }
+
+    pub fn encode(&self) -> [u8; Self::LEN] {
+        let mut bytes = [0; Self::LEN];
+
+        bytes[0..4].copy_from_slice(&self.next_seq.to_le_bytes());
+        bytes[4..6].copy_from_slice(&self.window.to_le_bytes());
+
+        for (range, bytes) in self.missing.iter().zip(bytes[6..].chunks_exact_mut(6)) {
+            if let Some(range) = range {
+                let len = range.len().min(u16::MAX as u32) as u16;
+
+                bytes[0..4].copy_from_slice(&range.start.to_le_bytes());
+                bytes[4..6].copy_from_slice(&len.to_le_bytes());
+            }
+        }
+
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        expect_len(bytes, Self::LEN)?;
+
+        let mut ack = Self::new(
+            u32::from_le_bytes(bytes[0..4].try_into().unwrap()),
+            u16::from_le_bytes(bytes[4..6].try_into().unwrap()),
+        );
+
+        for (range, bytes) in ack.missing.iter_mut().zip(bytes[6..].chunks_exact(6)) {
+            let start = u32::from_le_bytes(bytes[0..4].try_into().unwrap());
+            let len = u16::from_le_bytes(bytes[4..6].try_into().unwrap());
+
+            *range = (len > 0).then(|| MissingRange {
+                start,
+                end: start.saturating_add(len as u32),
+            });
+        }
+
+        Ok(ack)
+    }
+}
+
+#[cfg(feature = "alloc")]
+mod window {
+    use alloc::{collections::BTreeMap, collections::VecDeque, vec::Vec};
+
+    use super::{MissingRange, StreamAck};
+
+    /// Device side of a stream: reorders blocks and decides when to acknowledge them
+    ///
+    /// Blocks past a gap are buffered (at most `window` of them) until the gap is filled, so
+    /// the image is delivered in order.
+    pub struct StreamReceiver {
+        window: u16,
+        ack_interval: u16,
+        next_seq: u32,
+        buffered: BTreeMap<u32, Vec<u8>>,
+        /// Blocks received since the last ack
+        since_ack: u16,
+    }
+
+    impl StreamReceiver {
+        /// `ack_interval` is clamped to `1..=window`, so the client never stalls on a full
+        /// window without an ack on its way
+        pub fn new(window: u16, ack_interval: u16) -> Self {
+            let window = window.max(1);
+
+            Self {
+                window,
+                ack_interval: ack_interval.clamp(1, window),
+                next_seq: 0,
+                buffered: BTreeMap::new(),
+                since_ack: 0,
+            }
+        }
+
+        /// Next block expected in order
+        pub fn next_seq(&self) -> u32 {
+            self.next_seq
+        }
+
+        /// Drops buffered blocks and expects block 0 again, for a new transfer
+        pub fn reset(&mut self) {
+            self.next_seq = 0;
+            self.buffered.clear();
+            self.since_ack = 0;
+        }
+
+        /// Takes block `seq`, handing it and the buffered blocks it unblocks to `deliver` in
+        /// order, returns the ack to notify if one is due
+        ///
+        /// On a `deliver` error the stream stays at the failed block, with the blocks after it
+        /// still buffered.
+        pub fn receive<E>(
+            &mut self,
+            seq: u32,
+            data: &[u8],
+            mut deliver: impl FnMut(&[u8]) -> Result<(), E>,
+        ) -> Result<Option<StreamAck>, E> {
+            // Retransmission of a delivered block, or a client ahead of the window, which
+            // missed an ack
+            if seq < self.next_seq || seq - self.next_seq >= self.window as u32 {
+                return Ok(Some(self.ack()));
+            }
+
+            self.since_ack += 1;
+
+            if seq > self.next_seq {
+                let opens_gap = self.buffered.is_empty();
+                self.buffered.entry(seq).or_insert_with(|| data.into());
+
+                return Ok((opens_gap || self.since_ack >= self.ack_interval).then(|| self.ack()));
+            }
+
+            let filled_gap = !self.buffered.is_empty();
+
+            deliver(data)?;
+            self.next_seq += 1;
+
+            while let Some(data) = self.buffered.remove(&self.next_seq) {
+                if let Err(error) = deliver(&data) {
+                    self.buffered.insert(self.next_seq, data);
+                    return Err(error);
+                }
+
+                self.next_seq += 1;
+            }
+
+            Ok((filled_gap || self.since_ack >= self.ack_interval).then(|| self.ack()))
+        }
+
+        /// Acknowledges the blocks received so far, reporting the first gaps
+        pub fn ack(&mut self) -> StreamAck {
+            let mut ack = StreamAck::new(self.next_seq, self.window);
+            let mut expected = self.next_seq;
+            let mut ranges = ack.missing.iter_mut();
+
+            for &seq in self.buffered.keys() {
+                if seq > expected {
+                    match ranges.next() {
+                        Some(range) => {
+                            *range = Some(MissingRange {
+                                start: expected,
+                                end: seq,
+                            })
+                        }
+                        None => break,
+                    }
+                }
+
+                expected = seq + 1;
+            }
+
+            self.since_ack = 0;
+
+            ack
+        }
+    }
+
+    /// Client side of a stream of `total` blocks: picks the blocks to send within the window
+    /// and what to retransmit
+    pub struct StreamSender {
+        total: u32,
+        window: u16,
+        /// Every block before it was acknowledged
+        acked: u32,
+        /// First block never sent
+        next_new: u32,
+        retransmit: VecDeque<u32>,
+    }
+
+    impl StreamSender {
+        /// `window` is the one of the device, as reported by its first `StreamAck`
+        pub fn new(total: u32, window: u16) -> Self {
+            Self {
+                total,
+                window: window.max(1),
+                acked: 0,
+                next_new: 0,
+                retransmit: VecDeque::new(),
+            }
+        }
+
+        /// Blocks acknowledged by the device
+        pub fn acked(&self) -> u32 {
+            self.acked
+        }
+
+        /// Whether the device acknowledged every block
+        pub fn is_done(&self) -> bool {
+            self.acked >= self.total
+        }
+
+        /// Next block to write, `None` once the window is full or every block was sent
+        pub fn next_block(&mut self) -> Option<u32> {
+            if let Some(seq) = self.retransmit.pop_front() {
+                return Some(seq);
+            }
+
+            let window_end = self.acked.saturating_add(self.window as u32);
+            if self.next_new < self.total && self.next_new < window_end {
+                self.next_new += 1;
+
+                return Some(self.next_new - 1);
+            }
+
+            None
+        }
+
+        /// Moves the window and queues the missing ranges of `ack` for retransmission
+        pub fn on_ack(&mut self, ack: &StreamAck) {
+            self.window = ack.window.max(1);
+            self.acked = self.acked.max(ack.next_seq.min(self.total));
+            self.next_new = self.next_new.max(self.acked);
+
+            self.retransmit.clear();
+            for range in ack.missing() {
+                let end = range.end.min(self.next_new);
+                self.retransmit.extend(range.start.max(self.acked)..end);
+            }
+        }
+
+        /// Retransmits the first unacknowledged block when no ack arrived in time, the device
+        /// answers it with an ack
+        pub fn on_timeout(&mut self) {
+            if self.retransmit.is_empty() && !self.is_done() {
+                self.retransmit.push_back(self.acked);
+            }
+        }
+    }
+}
@@ -0,0 +1,15 @@
+//! 128-bit UUIDs of the OTA service and its characteristics
+
+pub const SERVICE: u128 = 0x81ea96fb_1117_4ea4_9df0_d30cd73e0e76;
+pub const FILE_BLOCK: u128 = 0x075e8648_5b20_42c9_a492_b0ce7548be7c;
+pub const TOTAL_FILE_SIZE: u128 = 0x92e8d217_f306_418e_b75b_894b288b6664;
+pub const FILE_HASH: u128 = 0x923930e3_686a_409e_a1e0_c7bbd8bb3d50;
+pub const STATUS: u128 = 0xe4ccad22_e983_42a9_9c95_7f4909ff885f;
+pub const COMMAND: u128 = 0x92fa0fe8_35ff_442f_a00c_010ebd91ef6a;
+pub const FINISHED_UPLOAD: u128 = 0xe6b7ae4f_d7ff_43f6_a378_86cf740040db;
+pub const FILE_SIGNATURE: u128 = 0x3b1c0b7e_5a0d_4c1e_8f4a_6e2d9c7b1a53;
+pub const SLOT_STATES: u128 = 0x5d2a41c6_0e8b_4f37_b1d9_7c3e6a2f8b04;
+pub const FIRMWARE_INFO: u128 = 0x2f6c9e1d_8a47_4b5e_9c03_d1e7a5b46f28;
+pub const LINK_INFO: u128 = 0x6a1f4c83_2d9e_4b70_a5c6_0f3b8e7d1c92;
+pub const STREAM_BLOCK: u128 = 0xc4e1a9b2_7f3d_4a86_9b50_e2d6f1a83c17;
+pub const STREAM_ACK: u128 = 0x1b8d5e6f_a243_4c9e_8d71_5f0c3b9a2e64;
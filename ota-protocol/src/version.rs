@@ -0,0 +1,198 @@
+//! Anti-downgrade checks, comparing the app descriptor of an incoming image with the running one
+
+use core::{cmp::Ordering, fmt};
+
+use crate::image::AppDescriptor;
+
+/// Store key of the highest `AppDescriptor::secure_version` confirmed on the device
+pub const SECURE_VERSION_KEY: &str = "ota_secure_ver";
+
+/// Semantic version of an `AppDescriptor`, e.g. `v1.2.3-rc.1+build.5`
+///
+/// Ordered by semver precedence: build metadata is ignored and a pre-release is older than the
+/// release it precedes.
+#[derive(Debug, Clone, Copy)]
+pub struct Version<'a> {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+    /// Pre-release identifiers, empty for a release
+    pub pre: &'a str,
+}
+
+impl<'a> Version<'a> {
+    /// Parses `major.minor.patch` with optional `v` prefix, pre-release and build metadata
+    pub fn parse(version: &'a str) -> Option<Self> {
+        let version = version.strip_prefix('v').unwrap_or(version);
+        let version = version
+            .split_once('+')
+            .map_or(version, |(version, _)| version);
+        let (core, pre) = match version.split_once('-') {
+            Some((_, "")) => return None,
+            Some((core, pre)) => (core, pre),
+            None => (version, ""),
+        };
+
+        let mut numbers = core.split('.').map(parse_number);
+        let version = Self {
+            major: numbers.next()??,
+            minor: numbers.next()??,
+            patch: numbers.next()??,
+            pre,
+        };
+
+        if numbers.next().is_some() {
+            return None;
+        }
+
+        Some(version)
+    }
+}
+
+impl Ord for Version<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.major, self.minor, self.patch)
+            .cmp(&(other.major, other.minor, other.patch))
+            .then_with(|| match (self.pre.is_empty(), other.pre.is_empty()) {
+                (true, true) => Ordering::Equal,
+                (true, false) => Ordering::Greater,
+                (false, true) => Ordering::Less,
+                (false, false) => {
+                    let mut ours = self.pre.split('.');
+                    let mut theirs = other.pre.split('.');
+
+                    loop {
+                        match (ours.next(), theirs.next()) {
+                            (Some(a), Some(b)) => match cmp_identifier(a, b) {
+                                Ordering::Equal => {}
+                                ordering => return ordering,
+                            },
+                            (a, b) => return a.is_some().cmp(&b.is_some()),
+                        }
+                    }
+                }
+            })
+    }
+}
+
+impl PartialOrd for Version<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Version<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Version<'_> {}
+
+fn parse_number(number: &str) -> Option<u32> {
+    if number.is_empty() || !number.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    number.parse().ok()
+}
+
+/// Numeric identifiers are compared numerically and are older than alphanumeric ones
+fn cmp_identifier(a: &str, b: &str) -> Ordering {
+    let numeric = |identifier: &str| identifier.bytes().all(|b| b.is_ascii_digit());
+
+    match (numeric(a), numeric(b)) {
+        (true, true) => {
+            let a = a.trim_start_matches('0');
+            let b = b.trim_start_matches('0');
+
+            a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+        }
+        (true, false) => Ordering::Less,
+        (false, true) => Ordering::Greater,
+        (false, false) => a.cmp(b),
+    }
+}
+
+/// Which images are refused as a downgrade of the running app
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DowngradePolicy {
+    /// Every image is accepted
+    #[default]
+    AllowAny,
+    /// Images with an older `Version` than the running app are refused
+    RejectOlderVersion,
+    /// Images with a `secure_version` below the running app's, or below the highest one ever
+    /// confirmed on the device, are refused
+    SecureVersion,
+}
+
+impl DowngradePolicy {
+    /// Checks `incoming` against the `running` app
+    ///
+    /// `min_secure_version` is the highest secure version confirmed on the device, see
+    /// `SECURE_VERSION_KEY`. Any image, with or without a semantic version, passes
+    /// `RejectOlderVersion` if the running app has none: there is nothing to compare it with.
+    pub fn check(
+        &self,
+        running: &AppDescriptor,
+        incoming: &AppDescriptor,
+        min_secure_version: u32,
+    ) -> Result<(), DowngradeError> {
+        match self {
+            Self::AllowAny => Ok(()),
+            Self::RejectOlderVersion => {
+                let Some(running) = Version::parse(running.version()) else {
+                    return Ok(());
+                };
+
+                let version =
+                    Version::parse(incoming.version()).ok_or(DowngradeError::InvalidVersion)?;
+
+                if version < running {
+                    return Err(DowngradeError::OlderVersion);
+                }
+
+                Ok(())
+            }
+            Self::SecureVersion => {
+                let min = min_secure_version.max(running.secure_version);
+
+                if incoming.secure_version < min {
+                    return Err(DowngradeError::SecureVersionTooLow {
+                        version: incoming.secure_version,
+                        min,
+                    });
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Reasons the `DowngradePolicy` refuses an image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DowngradeError {
+    /// Version of the image isn't a semantic version, so it can't be compared
+    InvalidVersion,
+    /// Image has an older version than the running app
+    OlderVersion,
+    /// Secure `version` of the image is below `min`
+    SecureVersionTooLow { version: u32, min: u32 },
+}
+
+impl fmt::Display for DowngradeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidVersion => write!(f, "Image version is not a semantic version"),
+            Self::OlderVersion => write!(f, "Image is older than the running app"),
+            Self::SecureVersionTooLow { version, min } => {
+                write!(f, "Secure version {} is below {}", version, min)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DowngradeError {}
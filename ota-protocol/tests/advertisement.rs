@@ -0,0 +1,69 @@
+mod common;
+
+use common::SampleImage;
+use ota_protocol::{
+    advertisement::{ServiceData, ShortVersion, PROTOCOL_VERSION},
+    DecodeError,
+};
+
+#[test]
+fn service_data_round_trip() {
+    let data = ServiceData::new(&SampleImage::default().app(), true, 0xa1b2_c3d4);
+    let encoded = data.encode();
+
+    assert_eq!(
+        encoded,
+        [PROTOCOL_VERSION, 0x03, 1, 2, 3, 0xd4, 0xc3, 0xb2, 0xa1]
+    );
+    assert_eq!(ServiceData::decode(&encoded), Ok(data));
+    assert_eq!(
+        data.firmware_version.map(|version| version.to_string()),
+        Some("1.2.3".to_string())
+    );
+}
+
+#[test]
+fn version_is_optional_and_saturated() {
+    let app = |version| {
+        SampleImage {
+            version,
+            ..Default::default()
+        }
+        .app()
+    };
+
+    let untagged = ServiceData::new(&app("a1b2c3d-dirty"), false, 7);
+    assert_eq!(untagged.encode()[1..5], [0x00, 0, 0, 0]);
+    assert_eq!(ServiceData::decode(&untagged.encode()), Ok(untagged));
+    assert_eq!(untagged.firmware_version, None);
+
+    assert_eq!(
+        ShortVersion::of(&app("v2.300.4-rc.1")),
+        Some(ShortVersion {
+            major: 2,
+            minor: 255,
+            patch: 4
+        })
+    );
+}
+
+#[test]
+fn service_data_of_later_versions_is_decoded() {
+    let mut encoded = ServiceData::new(&SampleImage::default().app(), false, 1)
+        .encode()
+        .to_vec();
+    encoded[0] = PROTOCOL_VERSION + 1;
+    encoded.extend_from_slice(&[0xff, 0xff]);
+
+    let data = ServiceData::decode(&encoded).unwrap();
+    assert_eq!(data.protocol_version, PROTOCOL_VERSION + 1);
+    assert!(!data.transfer_in_progress);
+
+    assert_eq!(
+        ServiceData::decode(&encoded[..8]),
+        Err(DecodeError::InvalidLength {
+            expected: 9,
+            actual: 8
+        })
+    );
+}
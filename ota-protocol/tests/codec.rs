@@ -0,0 +1,197 @@
+use std::fmt::Debug;
+
+use ota_protocol::{
+    block::{Block, BlockHeader},
+    codec::Codec,
+    command::Command,
+    hash::ImageHash,
+    link::{LinkInfo, DEFAULT_MTU, MAX_MTU},
+    size::TotalFileSize,
+    slot::{BootOutcome, SlotState, SlotStates},
+    status::{FinishedUpload, Status, StatusReport},
+    stream::StreamAck,
+    DecodeError,
+};
+
+#[test]
+fn command_round_trip() {
+    for command in [
+        Command::StartTransfer,
+        Command::ClearTransfer,
+        Command::ResetDevice,
+        Command::StartForceTransfer,
+    ] {
+        assert_eq!(Command::decode(&command.encode()), Ok(command));
+    }
+}
+
+#[test]
+fn command_rejects_unknown_opcode_and_length() {
+    assert_eq!(
+        Command::decode(&[0x00]),
+        Err(DecodeError::UnknownCommand(0))
+    );
+    assert_eq!(
+        Command::decode(&[0x01, 0x02]),
+        Err(DecodeError::InvalidLength {
+            expected: 1,
+            actual: 2
+        })
+    );
+}
+
+#[test]
+fn status_round_trip() {
+    for byte in 0..=u8::MAX {
+        if let Ok(status) = Status::try_from(byte) {
+            assert_eq!(status.encode(), [byte]);
+            assert_eq!(Status::decode(&[byte]), Ok(status));
+            assert_eq!(status.is_error(), byte >= 0x80);
+        }
+    }
+
+    assert_eq!(
+        Status::decode(&[0x7f]),
+        Err(DecodeError::UnknownStatus(0x7f))
+    );
+}
+
+#[test]
+fn finished_upload_round_trip() {
+    for finished in [FinishedUpload(false), FinishedUpload(true)] {
+        assert_eq!(FinishedUpload::decode(&finished.encode()), Ok(finished));
+    }
+}
+
+#[test]
+fn total_file_size_is_fixed_width() {
+    let size = TotalFileSize(0x001d_f000);
+
+    assert_eq!(size.encode(), [0x00, 0xf0, 0x1d, 0x00]);
+    assert_eq!(TotalFileSize::decode(&size.encode()), Ok(size));
+
+    // 8-byte `usize` encoding of older hosts is rejected
+    assert!(TotalFileSize::decode(&0x001d_f000_u64.to_le_bytes()).is_err());
+}
+
+#[test]
+fn image_hash_round_trip() {
+    let hash = ImageHash(core::array::from_fn(|i| i as u8));
+
+    assert_eq!(ImageHash::decode(&hash.encode()), Ok(hash));
+    assert!(ImageHash::decode(&[0; 16]).is_err());
+}
+
+#[test]
+fn block_round_trip() {
+    let data = [0xe9, 0x05, 0x02, 0x20];
+    let block = Block::new(0x1234, &data);
+
+    let mut buf = [0; 16];
+    let len = block.encode_into(&mut buf).unwrap();
+
+    assert_eq!(len, BlockHeader::LEN + data.len());
+    assert_eq!(&buf[..BlockHeader::LEN], &[0x34, 0x12, 0x00, 0x00]);
+    assert_eq!(Block::decode(&buf[..len]), Ok(block));
+}
+
+#[test]
+fn block_rejects_short_values() {
+    assert!(Block::decode(&[0; BlockHeader::LEN]).is_err());
+    assert!(Block::new(0, &[0; 8]).encode_into(&mut [0; 8]).is_err());
+    assert_eq!(Block::max_data_len(512), 508);
+}
+
+#[test]
+fn slot_states_round_trip() {
+    let states = SlotStates {
+        running: SlotState::PendingVerify,
+        next: SlotState::Valid,
+    };
+
+    assert_eq!(states.encode(), [0x01, 0x02]);
+    assert_eq!(SlotStates::decode(&states.encode()), Ok(states));
+    assert_eq!(
+        SlotStates::decode(&[0x05, 0x02]),
+        Err(DecodeError::UnknownSlotState(0x05))
+    );
+}
+
+#[test]
+fn boot_outcome() {
+    let outcome = |running, next| SlotStates { running, next }.outcome();
+
+    assert_eq!(
+        outcome(SlotState::PendingVerify, SlotState::Valid),
+        BootOutcome::PendingVerify
+    );
+    assert_eq!(
+        outcome(SlotState::Valid, SlotState::Valid),
+        BootOutcome::Confirmed
+    );
+    assert_eq!(
+        outcome(SlotState::Valid, SlotState::Aborted),
+        BootOutcome::RolledBack
+    );
+    assert_eq!(
+        outcome(SlotState::Undefined, SlotState::Invalid),
+        BootOutcome::RolledBack
+    );
+    assert_eq!(
+        outcome(SlotState::Undefined, SlotState::Undefined),
+        BootOutcome::Untracked
+    );
+}
+
+#[test]
+fn link_info_block_size() {
+    let link = |mtu| LinkInfo {
+        mtu,
+        max_block_size: 512,
+    };
+
+    assert_eq!(link(247).encode(), [0xf7, 0x00, 0x00, 0x02]);
+    assert_eq!(LinkInfo::decode(&link(247).encode()), Ok(link(247)));
+    assert!(LinkInfo::decode(&[0xf7, 0x00]).is_err());
+
+    // One write per ATT packet, capped by the device
+    assert_eq!(link(DEFAULT_MTU).block_size(), 20);
+    assert_eq!(link(247).block_size(), 244);
+    assert_eq!(link(MAX_MTU).block_size(), 512);
+    assert_eq!(link(0).block_size(), 0);
+}
+
+/// Round trip through the `Codec` trait, as `OtaCharacteristic<T>` does on the device
+fn codec_round_trip<T: Codec + PartialEq + Debug>(value: T) {
+    let encoded = value.encode();
+
+    assert!(encoded.as_ref().len() <= T::MAX_LEN);
+    assert_eq!(T::decode(encoded.as_ref()), Ok(value));
+}
+
+#[test]
+fn characteristic_values_implement_codec() {
+    codec_round_trip(Command::StartTransfer);
+    codec_round_trip(TotalFileSize(1234));
+    codec_round_trip(ImageHash([7; ImageHash::LEN]));
+    codec_round_trip(FinishedUpload(true));
+    codec_round_trip(StatusReport {
+        status: Status::Receiving,
+        session_id: 1,
+        offset: 4096,
+        reason: 0,
+    });
+    codec_round_trip(SlotStates {
+        running: SlotState::Valid,
+        next: SlotState::New,
+    });
+    codec_round_trip(LinkInfo {
+        mtu: 247,
+        max_block_size: 512,
+    });
+    codec_round_trip(StreamAck::new(42, 16));
+    codec_round_trip(vec![1_u8, 2, 3]);
+
+    // Inherent `decode` is used, with its length check
+    assert!(<TotalFileSize as Codec>::decode(&[0; 8]).is_err());
+}
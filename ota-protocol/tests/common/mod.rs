@@ -0,0 +1,100 @@
+//! Helpers shared by the integration tests
+
+// Not every test uses every helper
+#![allow(dead_code)]
+
+use ota_protocol::{
+    hash::ImageHash,
+    image::{AppDescriptor, ChipId, FirmwareImage, ImageHeader, IMAGE_PREFIX_LEN},
+    session::{ImageInfo, OtaSession},
+    sink::MemorySink,
+};
+
+pub const PROJECT: &str = "esp-ota-ble";
+
+/// Arbitrary image content, which doesn't repeat every 256 bytes
+pub fn image(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 13 + i / 256) as u8).collect()
+}
+
+/// Metadata of an unsigned `image`
+pub fn info(image: &[u8]) -> ImageInfo {
+    ImageInfo {
+        size: image.len() as u32,
+        hash: ImageHash::of(image),
+        signature: None,
+    }
+}
+
+/// Session without public key nor store, writing to a sink of `capacity` bytes
+pub fn session(capacity: u32) -> OtaSession<MemorySink> {
+    OtaSession::new(MemorySink::new(capacity))
+}
+
+/// App image laid out the way `esp-idf` builds it, with a payload after the app descriptor
+pub struct SampleImage {
+    pub chip_id: ChipId,
+    pub min_chip_rev: u16,
+    pub max_chip_rev: u16,
+    pub project_name: &'static str,
+    pub version: &'static str,
+    pub secure_version: u32,
+}
+
+impl Default for SampleImage {
+    fn default() -> Self {
+        Self {
+            chip_id: ChipId::ESP32_S3,
+            min_chip_rev: 0,
+            max_chip_rev: 99,
+            project_name: PROJECT,
+            version: "1.2.3",
+            secure_version: 2,
+        }
+    }
+}
+
+impl SampleImage {
+    pub fn build(&self) -> Vec<u8> {
+        let mut image = vec![0; IMAGE_PREFIX_LEN];
+
+        // esp_image_header_t
+        image[0] = ImageHeader::MAGIC;
+        image[1] = 4;
+        image[2] = 0x02;
+        image[3] = 0x2f;
+        image[4..8].copy_from_slice(&0x4037_5a88_u32.to_le_bytes());
+        image[8] = 0xee;
+        image[12..14].copy_from_slice(&self.chip_id.0.to_le_bytes());
+        image[15..17].copy_from_slice(&self.min_chip_rev.to_le_bytes());
+        image[17..19].copy_from_slice(&self.max_chip_rev.to_le_bytes());
+        image[23] = 1;
+
+        // esp_image_segment_header_t of the DROM segment
+        image[24..28].copy_from_slice(&0x3c02_0020_u32.to_le_bytes());
+        image[28..32].copy_from_slice(&0x1_0000_u32.to_le_bytes());
+
+        // esp_app_desc_t
+        let desc = &mut image[32..];
+        desc[0..4].copy_from_slice(&AppDescriptor::MAGIC.to_le_bytes());
+        desc[4..8].copy_from_slice(&self.secure_version.to_le_bytes());
+        write_str(&mut desc[16..48], self.version);
+        write_str(&mut desc[48..80], self.project_name);
+        write_str(&mut desc[80..96], "12:34:56");
+        write_str(&mut desc[96..112], "Jan  1 2024");
+        write_str(&mut desc[112..144], "v5.1.2");
+        desc[144..176].copy_from_slice(&[0xa5; 32]);
+
+        image.extend((0..1000).map(|i| (i * 7) as u8));
+        image
+    }
+
+    /// App descriptor of the image, e.g. of the running app
+    pub fn app(&self) -> AppDescriptor {
+        FirmwareImage::parse(&self.build()).unwrap().app
+    }
+}
+
+fn write_str(field: &mut [u8], value: &str) {
+    field[..value.len()].copy_from_slice(value.as_bytes());
+}
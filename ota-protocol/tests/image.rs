@@ -0,0 +1,228 @@
+mod common;
+
+use common::{info, session, SampleImage, PROJECT};
+use ota_protocol::{
+    image::{ChipId, FirmwareImage, ImageError, ImageHeader, ImageRequirements, IMAGE_PREFIX_LEN},
+    info::FirmwareInfo,
+    session::{BlockOutcome, SessionError, SessionState},
+    status::Status,
+};
+
+fn requirements() -> ImageRequirements {
+    ImageRequirements::new(ChipId::ESP32_S3, 2, PROJECT)
+}
+
+#[test]
+fn parses_sample_image() {
+    let image = FirmwareImage::parse(&SampleImage::default().build()).unwrap();
+
+    assert_eq!(
+        image.header,
+        ImageHeader {
+            segment_count: 4,
+            entry_addr: 0x4037_5a88,
+            chip_id: ChipId::ESP32_S3,
+            min_chip_rev: 0,
+            max_chip_rev: 99,
+            hash_appended: true,
+        }
+    );
+    assert_eq!(image.app.secure_version, 2);
+    assert_eq!(image.app.version(), "1.2.3");
+    assert_eq!(image.app.project_name(), PROJECT);
+    assert_eq!(image.app.time(), "12:34:56");
+    assert_eq!(image.app.date(), "Jan  1 2024");
+    assert_eq!(image.app.idf_version(), "v5.1.2");
+    assert_eq!(image.app.elf_sha256, [0xa5; 32]);
+}
+
+#[test]
+fn rejects_files_which_are_not_app_images() {
+    let mut random = SampleImage::default().build();
+    random[0] = 0x7f;
+
+    let mut bootloader = SampleImage::default().build();
+    bootloader[32..36].fill(0xff);
+
+    assert_eq!(
+        FirmwareImage::parse(&random),
+        Err(ImageError::InvalidMagic(0x7f))
+    );
+    assert_eq!(
+        FirmwareImage::parse(&bootloader),
+        Err(ImageError::InvalidAppDescMagic(0xffff_ffff))
+    );
+    assert_eq!(
+        FirmwareImage::parse(&SampleImage::default().build()[..100]),
+        Err(ImageError::Truncated { len: 100 })
+    );
+}
+
+#[test]
+fn firmware_info_describes_running_app() {
+    let info = FirmwareInfo::new(&SampleImage::default().app(), 0x1a_0000);
+    let encoded = info.encode();
+
+    assert_eq!(&encoded[..6], b"1.2.3\0");
+    assert_eq!(encoded[160..], [2, 0, 0, 0, 0x00, 0x00, 0x1a, 0x00]);
+
+    let decoded = FirmwareInfo::decode(&encoded).unwrap();
+    assert_eq!(decoded, info);
+    assert_eq!(decoded.version(), "1.2.3");
+    assert_eq!(decoded.project_name(), PROJECT);
+    assert_eq!(decoded.idf_version(), "v5.1.2");
+    assert_eq!(decoded.date(), "Jan  1 2024");
+    assert_eq!(decoded.time(), "12:34:56");
+    assert_eq!(decoded.elf_sha256, [0xa5; 32]);
+    assert_eq!(decoded.secure_version, 2);
+    assert_eq!(decoded.max_ota_size, 0x1a_0000);
+
+    assert!(FirmwareInfo::decode(&encoded[1..]).is_err());
+}
+
+#[test]
+fn checks_chip_and_project() {
+    let check =
+        |sample: SampleImage| requirements().check(&FirmwareImage::parse(&sample.build()).unwrap());
+
+    assert_eq!(check(SampleImage::default()), Ok(()));
+    assert_eq!(
+        check(SampleImage {
+            chip_id: ChipId::ESP32_C3,
+            ..Default::default()
+        }),
+        Err(ImageError::ChipMismatch {
+            expected: ChipId::ESP32_S3,
+            actual: ChipId::ESP32_C3
+        })
+    );
+    assert_eq!(
+        check(SampleImage {
+            min_chip_rev: 3,
+            ..Default::default()
+        }),
+        Err(ImageError::UnsupportedRevision {
+            revision: 2,
+            min: 3,
+            max: 99
+        })
+    );
+    assert_eq!(
+        check(SampleImage {
+            project_name: "other-project",
+            ..Default::default()
+        }),
+        Err(ImageError::ProjectMismatch)
+    );
+}
+
+#[test]
+fn max_chip_revision_is_optional() {
+    let header = |max_chip_rev| ImageHeader {
+        segment_count: 1,
+        entry_addr: 0,
+        chip_id: ChipId::ESP32_S3,
+        min_chip_rev: 1,
+        max_chip_rev,
+        hash_appended: false,
+    };
+
+    assert!(header(0).supports_revision(300));
+    assert!(header(0xffff).supports_revision(300));
+    assert!(header(199).supports_revision(199));
+    assert!(!header(199).supports_revision(200));
+    assert!(!header(0).supports_revision(0));
+}
+
+#[test]
+fn project_name_is_truncated_like_app_descriptor() {
+    let long_name = "a-project-name-longer-than-31-bytes";
+    let requirements = ImageRequirements::new(ChipId::ESP32_S3, 0, long_name);
+
+    let image = SampleImage {
+        project_name: &long_name[..31],
+        ..Default::default()
+    };
+
+    assert_eq!(requirements.project_name(), &long_name[..31]);
+    assert_eq!(
+        requirements.check(&FirmwareImage::parse(&image.build()).unwrap()),
+        Ok(())
+    );
+}
+
+#[test]
+fn matching_image_is_accepted() {
+    let image = SampleImage::default().build();
+    let mut session = session(4096).with_image_requirements(requirements());
+
+    session.start(info(&image), false).unwrap();
+    for (i, chunk) in image.chunks(64).enumerate() {
+        session.write_block(i as u32 * 64, chunk).unwrap();
+    }
+    session.finish().unwrap();
+
+    assert_eq!(session.sink().image(), image);
+    assert!(session.sink().is_finalized());
+}
+
+#[test]
+fn image_for_another_chip_is_rejected_once_headers_arrive() {
+    let image = SampleImage {
+        chip_id: ChipId::ESP32_C3,
+        ..Default::default()
+    }
+    .build();
+    let mut session = session(4096).with_image_requirements(requirements());
+
+    session.start(info(&image), false).unwrap();
+
+    let blocks: Vec<_> = image.chunks(64).collect();
+    for (i, chunk) in blocks[..IMAGE_PREFIX_LEN / 64].iter().enumerate() {
+        assert_eq!(
+            session.write_block(i as u32 * 64, chunk),
+            Ok(BlockOutcome::Accepted)
+        );
+    }
+
+    let index = IMAGE_PREFIX_LEN / 64;
+    let error = session
+        .write_block(index as u32 * 64, blocks[index])
+        .unwrap_err();
+
+    assert_eq!(
+        error,
+        SessionError::InvalidImage(ImageError::ChipMismatch {
+            expected: ChipId::ESP32_S3,
+            actual: ChipId::ESP32_C3
+        })
+    );
+    assert_eq!(error.status(), Status::InvalidImage);
+    assert_eq!(session.state(), SessionState::Failed(Status::InvalidImage));
+    assert!(session.sink().image().is_empty());
+}
+
+#[test]
+fn image_shorter_than_headers_is_rejected() {
+    let image = SampleImage::default().build()[..200].to_vec();
+    let mut session = session(4096).with_image_requirements(requirements());
+
+    session.start(info(&image), false).unwrap();
+
+    assert_eq!(
+        session.write_block(0, &image),
+        Err(SessionError::InvalidImage(ImageError::Truncated {
+            len: 200
+        }))
+    );
+}
+
+#[test]
+fn images_are_not_checked_without_requirements() {
+    let image: Vec<u8> = (0..500).map(|i| i as u8).collect();
+    let mut session = session(4096);
+
+    session.start(info(&image), false).unwrap();
+
+    assert_eq!(session.write_block(0, &image), Ok(BlockOutcome::Complete));
+}
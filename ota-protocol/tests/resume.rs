@@ -0,0 +1,318 @@
+mod common;
+
+use common::{image, info, session};
+use ota_protocol::{
+    hash::ImageHash,
+    resume::{ResumeRecord, CHECKPOINT_INTERVAL, RECORD_KEY, SESSION_ID_KEY},
+    session::{OtaSession, SessionError, SessionState},
+    sink::{MemorySink, MemorySinkError},
+    status::{Status, StatusReport},
+    store::{KeyValueStore, MemoryStore},
+};
+
+const CAPACITY: u32 = 4 * CHECKPOINT_INTERVAL;
+const BLOCK: usize = 500;
+
+/// Writes blocks of `image` from `from` until `until` bytes were sent
+fn send(session: &mut OtaSession<MemorySink, MemoryStore>, image: &[u8], from: u32, until: u32) {
+    let mut offset = from as usize;
+
+    while offset < until as usize {
+        let end = (offset + BLOCK).min(image.len());
+        session
+            .write_block(offset as u32, &image[offset..end])
+            .unwrap();
+        offset = end;
+    }
+}
+
+/// New session over what a device would find in flash and NVS after a reset
+fn reboot(session: &OtaSession<MemorySink, MemoryStore>) -> OtaSession<MemorySink, MemoryStore> {
+    OtaSession::new(MemorySink::with_image(CAPACITY, session.sink().image()))
+        .with_store(session.store().clone())
+}
+
+#[test]
+fn record_round_trip() {
+    let record = ResumeRecord {
+        session_id: 7,
+        size: 0x0001_2345,
+        committed: 0x0001_0000,
+        hash: ImageHash([0xab; ImageHash::LEN]),
+    };
+
+    assert_eq!(ResumeRecord::decode(&record.encode()), Ok(record));
+    assert!(ResumeRecord::decode(&record.encode()[1..]).is_err());
+}
+
+#[test]
+fn status_report_round_trip() {
+    let report = StatusReport {
+        status: Status::Receiving,
+        session_id: 3,
+        offset: 0x1000,
+        reason: 0,
+    };
+
+    assert_eq!(
+        report.encode(),
+        [0x01, 0x03, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00]
+    );
+    assert_eq!(StatusReport::decode(&report.encode()), Ok(report));
+    assert!(StatusReport::decode(&[0x01]).is_err());
+
+    let denied = StatusReport {
+        status: Status::UpdateDenied,
+        reason: 0x2a,
+        ..report
+    };
+
+    assert_eq!(denied.encode()[0], 0x8e);
+    assert_eq!(denied.encode()[9], 0x2a);
+    assert_eq!(StatusReport::decode(&denied.encode()), Ok(denied));
+}
+
+#[test]
+fn restarting_same_image_resumes_after_link_loss() {
+    let image = image(3000);
+    let mut session = session(CAPACITY).with_store(MemoryStore::new());
+
+    assert_eq!(session.start(info(&image), false), Ok(0));
+    send(&mut session, &image, 0, 1500);
+
+    // Client reconnects and starts the same image again
+    assert_eq!(session.start(info(&image), false), Ok(1500));
+    assert_eq!(session.report(Status::Receiving).offset, 1500);
+
+    send(&mut session, &image, 1500, 3000);
+    session.finish().unwrap();
+
+    assert_eq!(session.sink().image(), image);
+}
+
+#[test]
+fn force_restarts_same_image() {
+    let image = image(3000);
+    let mut session = session(CAPACITY).with_store(MemoryStore::new());
+
+    session.start(info(&image), false).unwrap();
+    send(&mut session, &image, 0, 1500);
+
+    assert_eq!(session.start(info(&image), true), Ok(0));
+    assert_eq!(session.session_id(), 2);
+}
+
+#[test]
+fn progress_is_checkpointed_on_sector_boundaries() {
+    let image = image(3 * CHECKPOINT_INTERVAL as usize);
+    let mut session = session(CAPACITY).with_store(MemoryStore::new());
+
+    session.start(info(&image), false).unwrap();
+
+    let record = |session: &OtaSession<MemorySink, MemoryStore>| {
+        ResumeRecord::decode(session.store().get(RECORD_KEY).unwrap()).unwrap()
+    };
+    assert_eq!(record(&session).committed, 0);
+
+    send(&mut session, &image, 0, 6000);
+    assert_eq!(record(&session).committed, CHECKPOINT_INTERVAL);
+
+    send(&mut session, &image, 6000, 9000);
+    assert_eq!(record(&session).committed, 2 * CHECKPOINT_INTERVAL);
+    assert_eq!(record(&session).session_id, session.session_id());
+}
+
+#[test]
+fn transfer_resumes_after_reset() {
+    let image = image(3 * CHECKPOINT_INTERVAL as usize);
+    let mut session = session(CAPACITY).with_store(MemoryStore::new());
+
+    session.start(info(&image), false).unwrap();
+    send(&mut session, &image, 0, 9000);
+
+    let mut session = reboot(&session);
+    let record = session.restore().unwrap().unwrap();
+
+    assert_eq!(record.committed, 2 * CHECKPOINT_INTERVAL);
+    assert_eq!(
+        session.state(),
+        SessionState::Receiving {
+            size: image.len() as u32,
+            received: 2 * CHECKPOINT_INTERVAL
+        }
+    );
+    assert_eq!(session.report(Status::Receiving).session_id, 1);
+
+    let offset = session.start(info(&image), false).unwrap();
+    assert_eq!(offset, 2 * CHECKPOINT_INTERVAL);
+
+    send(&mut session, &image, offset, image.len() as u32);
+    session.finish().unwrap();
+
+    assert_eq!(session.sink().image(), image);
+    assert!(session.sink().is_finalized());
+    assert_eq!(session.store().get(RECORD_KEY), None);
+}
+
+#[test]
+fn corrupted_flash_is_caught_after_resume() {
+    let image = image(2 * CHECKPOINT_INTERVAL as usize);
+    let mut session = session(CAPACITY).with_store(MemoryStore::new());
+
+    session.start(info(&image), false).unwrap();
+    send(&mut session, &image, 0, 5000);
+
+    let mut flash = session.sink().image().to_vec();
+    flash[100] ^= 0xff;
+
+    let mut session = OtaSession::new(MemorySink::with_image(CAPACITY, &flash))
+        .with_store(session.store().clone());
+    session.restore().unwrap();
+
+    send(
+        &mut session,
+        &image,
+        CHECKPOINT_INTERVAL,
+        image.len() as u32,
+    );
+
+    assert!(session.finish().is_err());
+    assert_eq!(session.state(), SessionState::Failed(Status::HashMismatch));
+    assert_eq!(session.store().get(RECORD_KEY), None);
+}
+
+#[test]
+fn nothing_to_restore() {
+    let mut session = session(CAPACITY).with_store(MemoryStore::new());
+
+    assert_eq!(session.restore(), Ok(None));
+    assert_eq!(session.state(), SessionState::Idle);
+}
+
+#[test]
+fn invalid_record_is_dropped() {
+    let mut store = MemoryStore::new();
+    let record = ResumeRecord {
+        session_id: 1,
+        size: CAPACITY + 1,
+        committed: 0,
+        hash: ImageHash([0; ImageHash::LEN]),
+    };
+    store.store(RECORD_KEY, &record.encode()).unwrap();
+
+    let mut session = session(CAPACITY).with_store(store);
+
+    assert_eq!(session.restore(), Ok(None));
+    assert_eq!(session.store().get(RECORD_KEY), None);
+}
+
+#[test]
+fn session_ids_survive_finished_transfers() {
+    let image = image(100);
+    let mut session = session(CAPACITY).with_store(MemoryStore::new());
+
+    session.start(info(&image), false).unwrap();
+    send(&mut session, &image, 0, 100);
+    session.finish().unwrap();
+
+    let mut session = reboot(&session);
+
+    assert_eq!(session.restore(), Ok(None));
+    assert_eq!(session.session_id(), 1);
+    assert_eq!(
+        session.store().get(SESSION_ID_KEY),
+        Some(&1_u32.to_le_bytes()[..])
+    );
+
+    session.start(info(&image), false).unwrap();
+    assert_eq!(session.session_id(), 2);
+}
+
+#[test]
+fn cleared_transfer_is_not_restored() {
+    let image = image(3000);
+    let mut session = session(CAPACITY).with_store(MemoryStore::new());
+
+    session.start(info(&image), false).unwrap();
+    send(&mut session, &image, 0, 1000);
+    session.clear().unwrap();
+
+    assert_eq!(reboot(&session).restore(), Ok(None));
+}
+
+/// `MemoryStore` whose writes fail while `failing` is set, like a full NVS partition
+#[derive(Debug, Default)]
+struct FailingStore {
+    store: MemoryStore,
+    failing: bool,
+}
+
+impl KeyValueStore for FailingStore {
+    type Error = MemorySinkError;
+
+    fn load(&mut self, key: &str, buf: &mut [u8]) -> Result<Option<usize>, Self::Error> {
+        self.store.load(key, buf).map_err(Into::into)
+    }
+
+    fn store(&mut self, key: &str, value: &[u8]) -> Result<(), Self::Error> {
+        if self.failing {
+            return Err(MemorySinkError::WriteFailed);
+        }
+
+        self.store.store(key, value).map_err(Into::into)
+    }
+
+    fn remove(&mut self, key: &str) -> Result<(), Self::Error> {
+        if self.failing {
+            return Err(MemorySinkError::WriteFailed);
+        }
+
+        self.store.remove(key).map_err(Into::into)
+    }
+}
+
+#[test]
+fn store_failure_leaves_transfer_started() {
+    let image = image(2 * CHECKPOINT_INTERVAL as usize);
+    let mut session = session(CAPACITY).with_store(FailingStore {
+        failing: true,
+        ..Default::default()
+    });
+
+    assert!(matches!(
+        session.start(info(&image), false),
+        Err(SessionError::Store(_))
+    ));
+    assert_eq!(
+        session.state(),
+        SessionState::Receiving {
+            size: image.len() as u32,
+            received: 0
+        }
+    );
+
+    // Forcing another transfer over the unforgettable one starts it as well
+    assert!(matches!(
+        session.start(info(&image), true),
+        Err(SessionError::Store(_))
+    ));
+    assert!(session.state().is_in_progress());
+
+    // Blocks crossing a checkpoint are applied all the same
+    for (i, chunk) in image.chunks(BLOCK).enumerate() {
+        match session.write_block((i * BLOCK) as u32, chunk) {
+            Ok(_) | Err(SessionError::Store(_)) => {}
+            Err(error) => panic!("Block {} failed: {:?}", i, error),
+        }
+    }
+
+    // Finalized before the record is removed
+    assert!(matches!(session.finish(), Err(SessionError::Store(_))));
+    assert_eq!(
+        session.state(),
+        SessionState::ReadyToReboot {
+            size: image.len() as u32
+        }
+    );
+    assert_eq!(session.sink().image(), image);
+}
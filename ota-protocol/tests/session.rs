@@ -0,0 +1,236 @@
+mod common;
+
+use common::{image, info, session};
+use ota_protocol::{
+    hash::ImageHash,
+    session::{BlockOutcome, SessionError, SessionState},
+    sink::MemorySinkError,
+    status::Status,
+};
+
+const CAPACITY: u32 = 64;
+
+#[test]
+fn full_transfer() {
+    let image = image(40);
+    let mut session = session(CAPACITY);
+
+    assert_eq!(session.expected_hash(), None);
+
+    session.start(info(&image), false).unwrap();
+    assert_eq!(session.expected_hash(), Some(ImageHash::of(&image)));
+
+    let mut outcomes = Vec::new();
+    for (i, chunk) in image.chunks(16).enumerate() {
+        outcomes.push(session.write_block(i as u32 * 16, chunk).unwrap());
+    }
+
+    assert_eq!(
+        outcomes,
+        [
+            BlockOutcome::Accepted,
+            BlockOutcome::Accepted,
+            BlockOutcome::Complete
+        ]
+    );
+    assert_eq!(session.state(), SessionState::Verifying { size: 40 });
+
+    session.finish().unwrap();
+
+    assert_eq!(session.state(), SessionState::ReadyToReboot { size: 40 });
+    assert_eq!(session.state().status(), Status::Finished);
+    assert_eq!(session.expected_hash(), Some(ImageHash::of(&image)));
+    assert_eq!(session.sink().image(), image);
+    assert!(session.sink().is_finalized());
+}
+
+#[test]
+fn duplicate_blocks_are_skipped() {
+    let image = image(32);
+    let mut session = session(CAPACITY);
+
+    session.start(info(&image), false).unwrap();
+    session.write_block(0, &image[..16]).unwrap();
+
+    assert_eq!(
+        session.write_block(0, &image[..16]),
+        Ok(BlockOutcome::Duplicate)
+    );
+    assert_eq!(
+        session.write_block(16, &image[16..]),
+        Ok(BlockOutcome::Complete)
+    );
+    assert_eq!(session.sink().image(), image);
+}
+
+#[test]
+fn out_of_order_blocks_are_rejected() {
+    let image = image(32);
+    let mut session = session(CAPACITY);
+
+    session.start(info(&image), false).unwrap();
+    session.write_block(0, &image[..8]).unwrap();
+
+    // Gap after the last block
+    assert_eq!(
+        session.write_block(16, &image[16..]),
+        Err(SessionError::InvalidOffset {
+            expected: 8,
+            actual: 16
+        })
+    );
+
+    // Partially overlapping block
+    assert_eq!(
+        session.write_block(4, &image[4..12]),
+        Err(SessionError::InvalidOffset {
+            expected: 8,
+            actual: 4
+        })
+    );
+
+    // Session survives, the client can continue from the right offset
+    assert_eq!(
+        session.state(),
+        SessionState::Receiving {
+            size: 32,
+            received: 8
+        }
+    );
+}
+
+#[test]
+fn premature_finish_is_rejected() {
+    let mut session = session(CAPACITY);
+
+    assert_eq!(session.finish(), Err(SessionError::NoTransfer));
+
+    session.start(info(&image(32)), false).unwrap();
+    session.write_block(0, &image(16)).unwrap();
+
+    assert_eq!(
+        session.finish(),
+        Err(SessionError::Incomplete {
+            received: 16,
+            size: 32
+        })
+    );
+    assert!(!session.sink().is_finalized());
+    assert!(session.state().is_in_progress());
+}
+
+#[test]
+fn size_overflow_is_rejected() {
+    let mut session = session(CAPACITY);
+
+    assert_eq!(
+        session.start(info(&image(CAPACITY as usize + 1)), false),
+        Err(SessionError::TooLarge {
+            size: CAPACITY + 1,
+            capacity: CAPACITY
+        })
+    );
+    assert_eq!(
+        session.start(info(&image(0)), false),
+        Err(SessionError::Empty)
+    );
+    assert_eq!(session.state(), SessionState::Idle);
+
+    session.start(info(&image(16)), false).unwrap();
+    session.write_block(0, &image(8)).unwrap();
+
+    assert_eq!(
+        session.write_block(8, &image(16)),
+        Err(SessionError::Overflow)
+    );
+    assert_eq!(
+        session.write_block(u32::MAX, &image(16)),
+        Err(SessionError::Overflow)
+    );
+}
+
+#[test]
+fn start_while_in_progress() {
+    let mut session = session(CAPACITY);
+
+    session.start(info(&image(32)), false).unwrap();
+    session.write_block(0, &image(16)).unwrap();
+
+    // Another image
+    assert_eq!(
+        session.start(info(&image(24)), false),
+        Err(SessionError::TransferInProgress)
+    );
+
+    assert_eq!(session.start(info(&image(24)), true), Ok(0));
+
+    assert_eq!(
+        session.state(),
+        SessionState::Receiving {
+            size: 24,
+            received: 0
+        }
+    );
+    assert!(session.sink().image().is_empty());
+}
+
+#[test]
+fn clear_transfer() {
+    let mut session = session(CAPACITY);
+
+    assert_eq!(session.clear(), Err(SessionError::NoTransfer));
+
+    session.start(info(&image(32)), false).unwrap();
+    session.write_block(0, &image(16)).unwrap();
+    session.clear().unwrap();
+
+    assert_eq!(session.state(), SessionState::Idle);
+    assert_eq!(
+        session.write_block(16, &image(16)),
+        Err(SessionError::NoTransfer)
+    );
+}
+
+#[test]
+fn sink_failure_fails_the_session() {
+    let mut session = session(CAPACITY);
+
+    session.start(info(&image(32)), false).unwrap();
+    session.sink_mut().fail_next_write();
+
+    assert_eq!(
+        session.write_block(0, &image(16)),
+        Err(SessionError::Sink(MemorySinkError::WriteFailed))
+    );
+    assert_eq!(session.state(), SessionState::Failed(Status::UpdateFailed));
+
+    // Failed session has to be cleared or force-restarted
+    assert_eq!(
+        session.write_block(0, &image(16)),
+        Err(SessionError::NoTransfer)
+    );
+
+    session.clear().unwrap();
+    session.start(info(&image(32)), false).unwrap();
+}
+
+#[test]
+fn hash_mismatch_aborts_the_transfer() {
+    let mut corrupted = image(32);
+    corrupted[7] ^= 0x01;
+
+    let mut session = session(CAPACITY);
+
+    session.start(info(&image(32)), false).unwrap();
+    session.write_block(0, &corrupted).unwrap();
+
+    assert_eq!(session.finish(), Err(SessionError::HashMismatch));
+    assert_eq!(session.state(), SessionState::Failed(Status::HashMismatch));
+    assert!(!session.sink().is_finalized());
+    assert!(session.sink().image().is_empty());
+}
+
+#[test]
+fn image_hash_is_sha256() {
+    assert_eq!(ImageHash::of(b"abc").0[..4], [0xba, 0x78, 0x16, 0xbf],);
+}
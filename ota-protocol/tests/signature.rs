@@ -0,0 +1,138 @@
+mod common;
+
+use common::{image, info, session};
+use ed25519_dalek::{Signer, SigningKey};
+use ota_protocol::{
+    hash::ImageHash,
+    session::{ImageInfo, OtaSession, SessionError, SessionState},
+    signature::{ImageSignature, PublicKey},
+    sink::MemorySink,
+    DecodeError,
+};
+
+/// Fixed test keys, so failures are reproducible
+const TRUSTED_SEED: [u8; 32] = [0x11; 32];
+const UNTRUSTED_SEED: [u8; 32] = [0x22; 32];
+
+const LEN: usize = 48;
+
+fn key(seed: [u8; 32]) -> SigningKey {
+    SigningKey::from_bytes(&seed)
+}
+
+fn public_key(seed: [u8; 32]) -> PublicKey {
+    PublicKey(key(seed).verifying_key().to_bytes())
+}
+
+fn sign(seed: [u8; 32], hash: &ImageHash) -> ImageSignature {
+    ImageSignature(key(seed).sign(&hash.0).to_bytes())
+}
+
+/// Metadata of `image(LEN)`, signed with `signature`
+fn signed_info(signature: ImageSignature) -> ImageInfo {
+    ImageInfo {
+        signature: Some(signature),
+        ..info(&image(LEN))
+    }
+}
+
+fn signed_session() -> OtaSession<MemorySink> {
+    OtaSession::with_public_key(MemorySink::new(64), public_key(TRUSTED_SEED))
+}
+
+#[test]
+fn verifies_signature_of_the_hash() {
+    let hash = ImageHash::of(&image(LEN));
+    let signature = sign(TRUSTED_SEED, &hash);
+
+    assert!(public_key(TRUSTED_SEED).verify(&hash, &signature));
+    assert!(!public_key(UNTRUSTED_SEED).verify(&hash, &signature));
+    assert!(!public_key(TRUSTED_SEED).verify(&ImageHash::of(&image(LEN)[1..]), &signature));
+}
+
+#[test]
+fn invalid_public_key_rejects_everything() {
+    let hash = ImageHash::of(&image(LEN));
+
+    // Not a point on the curve
+    let mut bytes = [0xff; PublicKey::LEN];
+    bytes[31] = 0x7f;
+
+    assert!(!PublicKey(bytes).verify(&hash, &sign(TRUSTED_SEED, &hash)));
+}
+
+#[test]
+fn signature_codec() {
+    let signature = sign(TRUSTED_SEED, &ImageHash::of(&image(LEN)));
+
+    assert_eq!(ImageSignature::decode(&signature.encode()), Ok(signature));
+    assert_eq!(
+        ImageSignature::decode(&[0; 32]),
+        Err(DecodeError::InvalidLength {
+            expected: ImageSignature::LEN,
+            actual: 32
+        })
+    );
+}
+
+#[test]
+fn signed_image_is_accepted() {
+    let image = image(LEN);
+    let mut session = signed_session();
+
+    let signature = sign(TRUSTED_SEED, &ImageHash::of(&image));
+    session.start(signed_info(signature), false).unwrap();
+    session.write_block(0, &image).unwrap();
+    session.finish().unwrap();
+
+    assert_eq!(session.state(), SessionState::ReadyToReboot { size: 48 });
+    assert!(session.sink().is_finalized());
+}
+
+#[test]
+fn unsigned_image_is_rejected() {
+    let mut session = signed_session();
+
+    assert_eq!(
+        session.start(info(&image(LEN)), false),
+        Err(SessionError::MissingSignature)
+    );
+    assert_eq!(session.state(), SessionState::Idle);
+}
+
+#[test]
+fn image_signed_by_other_key_is_rejected() {
+    let mut session = signed_session();
+
+    let signature = sign(UNTRUSTED_SEED, &ImageHash::of(&image(LEN)));
+
+    assert_eq!(
+        session.start(signed_info(signature), false),
+        Err(SessionError::InvalidSignature)
+    );
+    assert_eq!(session.state(), SessionState::Idle);
+}
+
+#[test]
+fn signature_of_other_image_is_rejected() {
+    let mut session = signed_session();
+
+    let signature = sign(TRUSTED_SEED, &ImageHash::of(b"other image"));
+
+    assert_eq!(
+        session.start(signed_info(signature), false),
+        Err(SessionError::InvalidSignature)
+    );
+}
+
+#[test]
+fn sessions_without_key_accept_unsigned_images() {
+    let image = image(LEN);
+    let mut session = session(64);
+
+    session.start(info(&image), false).unwrap();
+    session.write_block(0, &image).unwrap();
+    session.finish().unwrap();
+
+    assert!(session.sink().is_finalized());
+}
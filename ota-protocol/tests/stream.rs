@@ -0,0 +1,280 @@
+use std::collections::VecDeque;
+
+mod common;
+
+use common::image;
+use ota_protocol::{
+    stream::{MissingRange, StreamAck, StreamBlock, StreamReceiver, StreamSender},
+    DecodeError,
+};
+
+/// Link dropping writes and notifications at random, reproducibly
+struct LossyLink {
+    state: u64,
+    loss_percent: u64,
+}
+
+impl LossyLink {
+    fn new(seed: u64, loss_percent: u64) -> Self {
+        Self {
+            state: seed,
+            loss_percent,
+        }
+    }
+
+    fn delivers(&mut self) -> bool {
+        // Linear congruential generator of Knuth's MMIX
+        self.state = self
+            .state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+
+        (self.state >> 33) % 100 >= self.loss_percent
+    }
+}
+
+/// Outcome of `stream`
+struct Transfer {
+    received: Vec<u8>,
+    writes: usize,
+    acks: usize,
+}
+
+/// Streams `image` over `link` until the sender saw every block acknowledged
+fn stream(image: &[u8], data_len: usize, window: u16, link: &mut LossyLink) -> Transfer {
+    let blocks = image.chunks(data_len).collect::<Vec<_>>();
+    let mut receiver = StreamReceiver::new(window, window / 2);
+    let mut sender = StreamSender::new(blocks.len() as u32, window);
+    let mut transfer = Transfer {
+        received: Vec::new(),
+        writes: 0,
+        acks: 0,
+    };
+
+    let mut notified = VecDeque::new();
+    for _ in 0..100_000 {
+        if sender.is_done() {
+            return transfer;
+        }
+
+        // Client fills the window, then waits for acks
+        while let Some(seq) = sender.next_block() {
+            assert!(seq < sender.acked() + window as u32);
+            transfer.writes += 1;
+
+            if !link.delivers() {
+                continue;
+            }
+
+            let ack = receiver
+                .receive(seq, blocks[seq as usize], |data| {
+                    transfer.received.extend_from_slice(data);
+                    Ok::<_, ()>(())
+                })
+                .unwrap();
+
+            // As the device, which acks the block completing the image right away
+            let complete = transfer.received.len() == image.len();
+            let ack = ack.or_else(|| complete.then(|| receiver.ack()));
+
+            if let Some(ack) = ack {
+                transfer.acks += 1;
+
+                if link.delivers() {
+                    notified.push_back(ack);
+                }
+            }
+        }
+
+        if notified.is_empty() {
+            sender.on_timeout();
+        }
+        for ack in notified.drain(..) {
+            sender.on_ack(&ack);
+        }
+    }
+
+    panic!("Stream didn't complete");
+}
+
+#[test]
+fn stream_block_round_trip() {
+    let block = StreamBlock::new(0x0102_0304, &[0xaa, 0xbb]);
+    let mut buf = [0; 8];
+
+    let len = block.encode_into(&mut buf).unwrap();
+
+    assert_eq!(&buf[..len], [0x04, 0x03, 0x02, 0x01, 0xaa, 0xbb]);
+    assert_eq!(StreamBlock::decode(&buf[..len]), Ok(block));
+    assert!(StreamBlock::decode(&buf[..4]).is_err());
+    assert!(block.encode_into(&mut buf[..5]).is_err());
+    assert_eq!(StreamBlock::max_data_len(244), 240);
+}
+
+#[test]
+fn stream_ack_round_trip() {
+    let mut ack = StreamAck::new(10, 16);
+    assert!(!ack.is_nak());
+    assert_eq!(StreamAck::decode(&ack.encode()), Ok(ack));
+
+    ack.missing[0] = Some(MissingRange { start: 12, end: 15 });
+    let encoded = ack.encode();
+
+    assert_eq!(encoded[..12], [10, 0, 0, 0, 16, 0, 12, 0, 0, 0, 3, 0]);
+    assert_eq!(encoded[12..], [0; 6]);
+    assert_eq!(StreamAck::decode(&encoded), Ok(ack));
+    assert!(ack.is_nak());
+    assert_eq!(
+        StreamAck::decode(&encoded[..17]),
+        Err(DecodeError::InvalidLength {
+            expected: 18,
+            actual: 17
+        })
+    );
+}
+
+#[test]
+fn acks_every_interval() {
+    let mut receiver = StreamReceiver::new(8, 4);
+    let mut acks = Vec::new();
+
+    for seq in 0..8 {
+        acks.push(receiver.receive(seq, &[0], |_| Ok::<_, ()>(())).unwrap());
+    }
+
+    assert_eq!(acks.iter().flatten().count(), 2);
+    assert_eq!(acks[3], Some(StreamAck::new(4, 8)));
+    assert_eq!(acks[7], Some(StreamAck::new(8, 8)));
+}
+
+#[test]
+fn gaps_are_nacked_and_reordered() {
+    let mut receiver = StreamReceiver::new(8, 3);
+    let mut delivered = Vec::new();
+    let mut receive = |receiver: &mut StreamReceiver, seq: u32| {
+        receiver
+            .receive(seq, &[seq as u8], |data| {
+                delivered.push(data[0]);
+                Ok::<_, ()>(())
+            })
+            .unwrap()
+    };
+
+    assert_eq!(receive(&mut receiver, 0), None);
+
+    // 1 and 2 are lost, 3 opens the gap
+    let nak = receive(&mut receiver, 3).unwrap();
+    assert_eq!(nak.next_seq, 1);
+    assert_eq!(
+        nak.missing().collect::<Vec<_>>(),
+        [MissingRange { start: 1, end: 3 }]
+    );
+
+    // 4 is lost as well, reported once the interval is reached
+    assert_eq!(receive(&mut receiver, 5), None);
+    assert_eq!(receive(&mut receiver, 6), None);
+    let nak = receive(&mut receiver, 7).unwrap();
+    assert_eq!(
+        nak.missing().collect::<Vec<_>>(),
+        [
+            MissingRange { start: 1, end: 3 },
+            MissingRange { start: 4, end: 5 }
+        ]
+    );
+
+    // Retransmissions fill the gaps
+    let ack = receive(&mut receiver, 1).unwrap();
+    assert_eq!(ack.next_seq, 2);
+    assert!(ack.is_nak());
+    assert_eq!(receive(&mut receiver, 2).unwrap().next_seq, 4);
+    assert_eq!(receive(&mut receiver, 4).unwrap(), StreamAck::new(8, 8));
+
+    assert_eq!(delivered, [0, 1, 2, 3, 4, 5, 6, 7]);
+}
+
+#[test]
+fn unexpected_blocks_are_acked_immediately() {
+    let mut receiver = StreamReceiver::new(4, 4);
+
+    for seq in 0..2 {
+        receiver.receive(seq, &[0], |_| Ok::<_, ()>(())).unwrap();
+    }
+
+    // Client missed an ack and retransmitted
+    let duplicate = receiver.receive(0, &[0], |_| Ok::<_, ()>(())).unwrap();
+    assert_eq!(duplicate, Some(StreamAck::new(2, 4)));
+
+    // Client went past the window
+    let ahead = receiver.receive(6, &[0], |_| Ok::<_, ()>(())).unwrap();
+    assert_eq!(ahead, Some(StreamAck::new(2, 4)));
+}
+
+#[test]
+fn failed_delivery_keeps_the_stream_position() {
+    let mut receiver = StreamReceiver::new(4, 4);
+
+    receiver.receive(1, &[1], |_| Ok::<_, ()>(())).unwrap();
+    assert_eq!(receiver.receive(0, &[0], |_| Err("sink")), Err("sink"));
+    assert_eq!(receiver.next_seq(), 0);
+
+    let mut delivered = Vec::new();
+    receiver
+        .receive(0, &[0], |data| {
+            delivered.push(data[0]);
+            Ok::<_, ()>(())
+        })
+        .unwrap();
+
+    assert_eq!(delivered, [0, 1]);
+    assert_eq!(receiver.next_seq(), 2);
+}
+
+#[test]
+fn sender_respects_window_and_retransmits() {
+    let mut sender = StreamSender::new(10, 4);
+
+    let sent = std::iter::from_fn(|| sender.next_block()).collect::<Vec<_>>();
+    assert_eq!(sent, [0, 1, 2, 3]);
+
+    let mut nak = StreamAck::new(1, 4);
+    nak.missing[0] = Some(MissingRange { start: 2, end: 3 });
+    sender.on_ack(&nak);
+
+    let sent = std::iter::from_fn(|| sender.next_block()).collect::<Vec<_>>();
+    assert_eq!(sent, [2, 4]);
+
+    // Acks are lost, the first unacknowledged block is probed
+    sender.on_timeout();
+    assert_eq!(sender.next_block(), Some(1));
+    assert_eq!(sender.next_block(), None);
+
+    sender.on_ack(&StreamAck::new(10, 4));
+    assert!(sender.is_done());
+}
+
+#[test]
+fn lossless_stream() {
+    let image = image(5000);
+    let mut link = LossyLink::new(1, 0);
+
+    let transfer = stream(&image, 64, 16, &mut link);
+
+    assert_eq!(transfer.received, image);
+    assert_eq!(transfer.writes, 5000_usize.div_ceil(64));
+    // One ack every 8 blocks, and one for the last partial interval
+    assert_eq!(transfer.acks, transfer.writes.div_ceil(8));
+}
+
+#[test]
+fn lossy_stream_is_retransmitted() {
+    let image = image(20_000);
+
+    for (seed, loss_percent) in [(1, 5), (2, 20), (3, 50)] {
+        let mut link = LossyLink::new(seed, loss_percent);
+
+        let transfer = stream(&image, 100, 16, &mut link);
+
+        assert_eq!(transfer.received, image, "{}% loss", loss_percent);
+        assert!(transfer.writes > 200, "{}% loss", loss_percent);
+    }
+}
@@ -0,0 +1,226 @@
+mod common;
+
+use common::{info, session, SampleImage};
+use ed25519_dalek::{Signer, SigningKey};
+use ota_protocol::{
+    session::{ImageInfo, OtaSession, SessionError, SessionState},
+    signature::{ImageSignature, PublicKey},
+    sink::{MemorySink, MemorySinkError},
+    status::Status,
+    store::{KeyValueStore, MemoryStore},
+    version::{DowngradeError, DowngradePolicy, Version, SECURE_VERSION_KEY},
+};
+
+const SEED: [u8; 32] = [0x11; 32];
+
+fn version(version: &str) -> Version<'_> {
+    Version::parse(version).unwrap()
+}
+
+fn sample(version: &'static str, secure_version: u32) -> SampleImage {
+    SampleImage {
+        version,
+        secure_version,
+        ..Default::default()
+    }
+}
+
+/// Metadata of `image` signed with the key of `SEED`
+fn signed_info(image: &[u8]) -> ImageInfo {
+    let info = info(image);
+
+    ImageInfo {
+        signature: Some(ImageSignature(
+            SigningKey::from_bytes(&SEED).sign(&info.hash.0).to_bytes(),
+        )),
+        ..info
+    }
+}
+
+/// Sends the whole image, stopping at the first error
+fn transfer<K>(session: &mut OtaSession<MemorySink, K>, image: &[u8]) -> Result<(), Status>
+where
+    K: KeyValueStore,
+    K::Error: Into<MemorySinkError>,
+{
+    for (i, chunk) in image.chunks(64).enumerate() {
+        session
+            .write_block(i as u32 * 64, chunk)
+            .map_err(|error| error.status())?;
+    }
+
+    session.finish().map_err(|error| error.status())
+}
+
+#[test]
+fn parses_semantic_versions() {
+    assert_eq!(
+        Version::parse("v1.2.3-rc.1+build.5").map(|v| (v.major, v.minor, v.patch, v.pre)),
+        Some((1, 2, 3, "rc.1"))
+    );
+    assert_eq!(version("10.0.7").pre, "");
+
+    for invalid in ["", "1.2", "1.2.3.4", "1.x.3", "1.2.3-", "a1b2c3d", "1..3"] {
+        assert_eq!(Version::parse(invalid), None, "{:?}", invalid);
+    }
+}
+
+#[test]
+fn orders_by_semver_precedence() {
+    let ordered = [
+        "1.0.0-alpha",
+        "1.0.0-alpha.1",
+        "1.0.0-alpha.beta",
+        "1.0.0-beta.2",
+        "1.0.0-beta.11",
+        "1.0.0-rc.1",
+        "1.0.0",
+        "1.0.1",
+        "1.2.0",
+        "1.10.0",
+        "2.0.0",
+    ];
+
+    for pair in ordered.windows(2) {
+        assert!(version(pair[0]) < version(pair[1]), "{:?}", pair);
+    }
+
+    assert_eq!(version("1.2.3+build.1"), version("v1.2.3"));
+    assert_eq!(version("1.2.3-rc.01"), version("1.2.3-rc.1"));
+}
+
+#[test]
+fn reject_older_version_policy() {
+    let policy = DowngradePolicy::RejectOlderVersion;
+    let running = sample("1.2.3", 0).app();
+    let check = |version| policy.check(&running, &sample(version, 0).app(), 0);
+
+    assert_eq!(check("1.2.3"), Ok(()));
+    assert_eq!(check("1.3.0-rc.1"), Ok(()));
+    assert_eq!(check("1.2.2"), Err(DowngradeError::OlderVersion));
+    assert_eq!(check("1.2.3-rc.1"), Err(DowngradeError::OlderVersion));
+    assert_eq!(check("a1b2c3d"), Err(DowngradeError::InvalidVersion));
+
+    // Nothing to compare with
+    let unversioned = sample("a1b2c3d-dirty", 0).app();
+    assert_eq!(
+        policy.check(&unversioned, &sample("0.1.0", 0).app(), 0),
+        Ok(())
+    );
+}
+
+#[test]
+fn unversioned_app_accepts_unversioned_image() {
+    let policy = DowngradePolicy::RejectOlderVersion;
+    let running = sample("a1b2c3d-dirty", 0).app();
+
+    assert_eq!(
+        policy.check(&running, &sample("e4f5a6b", 0).app(), 0),
+        Ok(())
+    );
+}
+
+#[test]
+fn secure_version_policy() {
+    let policy = DowngradePolicy::SecureVersion;
+    let running = sample("1.0.0", 2).app();
+    let check =
+        |secure_version, min| policy.check(&running, &sample("0.1.0", secure_version).app(), min);
+
+    assert_eq!(check(2, 0), Ok(()));
+    assert_eq!(check(5, 3), Ok(()));
+    assert_eq!(
+        check(1, 0),
+        Err(DowngradeError::SecureVersionTooLow { version: 1, min: 2 })
+    );
+    // Counter committed by a newer app before a rollback to the running one
+    assert_eq!(
+        check(2, 3),
+        Err(DowngradeError::SecureVersionTooLow { version: 2, min: 3 })
+    );
+}
+
+#[test]
+fn allow_any_policy() {
+    let running = sample("2.0.0", 5).app();
+
+    assert_eq!(
+        DowngradePolicy::AllowAny.check(&running, &sample("1.0.0", 0).app(), 9),
+        Ok(())
+    );
+}
+
+#[test]
+fn downgrade_is_refused_once_headers_arrive() {
+    let mut session = session(4096).with_downgrade_policy(
+        DowngradePolicy::RejectOlderVersion,
+        sample("1.2.3", 0).app(),
+    );
+    let image = sample("1.2.0", 0).build();
+
+    session.start(info(&image), false).unwrap();
+
+    assert_eq!(transfer(&mut session, &image), Err(Status::Downgrade));
+    assert_eq!(session.state(), SessionState::Failed(Status::Downgrade));
+    assert!(session.sink().image().is_empty());
+
+    // Newer image goes through
+    let image = sample("1.3.0", 0).build();
+    session.start(info(&image), false).unwrap();
+
+    assert_eq!(transfer(&mut session, &image), Ok(()));
+}
+
+#[test]
+fn committed_secure_version_survives_rollback() {
+    let store = {
+        let mut newer = session(4096)
+            .with_downgrade_policy(DowngradePolicy::SecureVersion, sample("2.0.0", 3).app())
+            .with_store(MemoryStore::new());
+        newer.commit_secure_version().unwrap();
+        newer.store().clone()
+    };
+
+    assert_eq!(
+        store.get(SECURE_VERSION_KEY),
+        Some(&3_u32.to_le_bytes()[..])
+    );
+
+    // Rolled back to an app with secure version 2
+    let mut session = session(4096)
+        .with_downgrade_policy(DowngradePolicy::SecureVersion, sample("1.0.0", 2).app())
+        .with_store(store);
+    session.commit_secure_version().unwrap();
+    assert_eq!(session.secure_version(), Ok(3));
+
+    let image = sample("1.0.1", 2).build();
+    session.start(info(&image), false).unwrap();
+
+    assert_eq!(transfer(&mut session, &image), Err(Status::Downgrade));
+}
+
+#[test]
+fn forced_downgrade_requires_authenticated_client() {
+    let running = sample("1.2.3", 0);
+    let image = sample("1.0.0", 0).build();
+
+    // Unsigned sessions can't authenticate the client, force only drops the ongoing transfer
+    let mut unsigned =
+        session(4096).with_downgrade_policy(DowngradePolicy::RejectOlderVersion, running.app());
+    unsigned.start(info(&image), true).unwrap();
+    assert_eq!(transfer(&mut unsigned, &image), Err(Status::Downgrade));
+
+    let public_key = PublicKey(SigningKey::from_bytes(&SEED).verifying_key().to_bytes());
+    let mut signed = OtaSession::with_public_key(MemorySink::new(4096), public_key)
+        .with_downgrade_policy(DowngradePolicy::RejectOlderVersion, running.app());
+
+    signed.start(signed_info(&image), false).unwrap();
+    assert_eq!(
+        signed.write_block(0, &image[..512]),
+        Err(SessionError::Downgrade(DowngradeError::OlderVersion))
+    );
+
+    signed.start(signed_info(&image), true).unwrap();
+    assert_eq!(transfer(&mut signed, &image), Ok(()));
+    assert_eq!(signed.sink().image(), image);
+}